@@ -0,0 +1,38 @@
+# Kitty Plus shell integration for bash
+#
+# Marks prompts, command lines and command output with OSC 133 and reports
+# the working directory with OSC 7. Source it from ~/.bashrc:
+#
+#   [ "$TERM_PROGRAM" = "kitty-plus" ] && source /path/to/kitty-plus.bash
+
+[[ $- == *i* ]] || return 0
+[[ -n ${__kitty_plus_integration:-} ]] && return 0
+__kitty_plus_integration=1
+
+__kitty_plus_executing=""
+__kitty_plus_ready=""
+
+__kitty_plus_precmd() {
+    local ret=$?
+    __kitty_plus_ready=""
+    if [[ -n $__kitty_plus_executing ]]; then
+        printf '\e]133;D;%s\a' "$ret"
+        __kitty_plus_executing=""
+    fi
+    printf '\e]7;file://%s%s\a' "$HOSTNAME" "$PWD"
+}
+
+# Runs from the DEBUG trap before every simple command; only the first one
+# after the prompt starts a command line. An empty command line goes
+# straight back to PROMPT_COMMAND.
+__kitty_plus_preexec() {
+    [[ -z $__kitty_plus_ready || -n ${COMP_LINE:-} ]] && return
+    [[ $BASH_COMMAND == __kitty_plus_precmd* ]] && return
+    __kitty_plus_ready=""
+    __kitty_plus_executing=1
+    printf '\e]133;C\a'
+}
+
+PS1="\[\e]133;A\a\]${PS1}\[\e]133;B\a\]"
+PROMPT_COMMAND="__kitty_plus_precmd${PROMPT_COMMAND:+; $PROMPT_COMMAND}; __kitty_plus_ready=1"
+trap '__kitty_plus_preexec' DEBUG
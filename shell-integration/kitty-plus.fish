@@ -0,0 +1,30 @@
+# Kitty Plus shell integration for fish
+#
+# Marks prompts, command lines and command output with OSC 133 and reports
+# the working directory with OSC 7. Source it from ~/.config/fish/config.fish:
+#
+#   test "$TERM_PROGRAM" = "kitty-plus"; and source /path/to/kitty-plus.fish
+
+status is-interactive; or exit 0
+set -q __kitty_plus_integration; and exit 0
+set -g __kitty_plus_integration 1
+
+function __kitty_plus_prompt_start --on-event fish_prompt
+    printf '\e]7;file://%s%s\a' (prompt_hostname) "$PWD"
+    printf '\e]133;A\a'
+end
+
+function __kitty_plus_preexec --on-event fish_preexec
+    printf '\e]133;C\a'
+end
+
+function __kitty_plus_postexec --on-event fish_postexec
+    printf '\e]133;D;%s\a' $status
+end
+
+# Mark where the command line starts, after whatever the prompt prints
+functions -c fish_prompt __kitty_plus_original_prompt
+function fish_prompt
+    __kitty_plus_original_prompt
+    printf '\e]133;B\a'
+end
@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+
+/// Which system selection an operation targets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Selection {
+    Clipboard,
+    /// The X11/Wayland primary selection, set by selecting text.
+    Primary,
+}
+
+/// A clipboard operation requested by a program through OSC 52. Requests
+/// are queued by the terminal state and carried out according to the
+/// configured policy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    Store(Selection, String),
+    Load(Selection),
+}
+
+pub trait Clipboard: Send {
+    fn load(&mut self, selection: Selection) -> Result<String>;
+    fn store(&mut self, selection: Selection, text: String) -> Result<()>;
+}
+
+impl Selection {
+    /// Parses the `Pc` parameter of OSC 52. `p` is the primary selection and
+    /// everything else, including the empty default, maps to the clipboard.
+    pub fn from_osc52(targets: &[u8]) -> Self {
+        if targets.contains(&b'p') && !targets.contains(&b'c') {
+            Selection::Primary
+        } else {
+            Selection::Clipboard
+        }
+    }
+    
+    pub fn osc52_code(self) -> char {
+        match self {
+            Selection::Clipboard => 'c',
+            Selection::Primary => 'p',
+        }
+    }
+}
+
+/// A clipboard that lives only in this process, used for tests and when no
+/// display server is available.
+#[derive(Debug, Default)]
+pub struct MemoryClipboard {
+    clipboard: String,
+    primary: String,
+}
+
+impl Clipboard for MemoryClipboard {
+    fn load(&mut self, selection: Selection) -> Result<String> {
+        Ok(match selection {
+            Selection::Clipboard => self.clipboard.clone(),
+            Selection::Primary => self.primary.clone(),
+        })
+    }
+    
+    fn store(&mut self, selection: Selection, text: String) -> Result<()> {
+        match selection {
+            Selection::Clipboard => self.clipboard = text,
+            Selection::Primary => self.primary = text,
+        }
+        Ok(())
+    }
+}
+
+/// The desktop clipboard: X11 or Wayland on Linux, the native one elsewhere.
+pub struct SystemClipboard {
+    inner: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self> {
+        let inner = arboard::Clipboard::new().context("Failed to connect to the system clipboard")?;
+        Ok(Self { inner })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Clipboard for SystemClipboard {
+    fn load(&mut self, selection: Selection) -> Result<String> {
+        use arboard::{GetExtLinux, LinuxClipboardKind};
+        
+        let kind = match selection {
+            Selection::Clipboard => LinuxClipboardKind::Clipboard,
+            Selection::Primary => LinuxClipboardKind::Primary,
+        };
+        match self.inner.get().clipboard(kind).text() {
+            Ok(text) => Ok(text),
+            Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    
+    fn store(&mut self, selection: Selection, text: String) -> Result<()> {
+        use arboard::{LinuxClipboardKind, SetExtLinux};
+        
+        let kind = match selection {
+            Selection::Clipboard => LinuxClipboardKind::Clipboard,
+            Selection::Primary => LinuxClipboardKind::Primary,
+        };
+        self.inner.set().clipboard(kind).text(text)?;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Clipboard for SystemClipboard {
+    fn load(&mut self, _selection: Selection) -> Result<String> {
+        match self.inner.get_text() {
+            Ok(text) => Ok(text),
+            Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    
+    fn store(&mut self, _selection: Selection, text: String) -> Result<()> {
+        self.inner.set_text(text)?;
+        Ok(())
+    }
+}
+
+/// The system clipboard when one is reachable, otherwise an in-memory one
+/// so copy and paste still work within the terminal.
+pub fn system_or_memory() -> Box<dyn Clipboard> {
+    match SystemClipboard::new() {
+        Ok(clipboard) => Box::new(clipboard),
+        Err(e) => {
+            log::warn!("{:#}; using an in-memory clipboard", e);
+            Box::<MemoryClipboard>::default()
+        }
+    }
+}
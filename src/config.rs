@@ -1,8 +1,12 @@
 use anyhow::{Result, Context};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use directories::ProjectDirs;
 
+use crate::keyboard::{Key, KeyEvent, NamedKey};
+use crate::layout::{LayoutKind, MainPaneOptions};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub font: FontConfig,
@@ -10,6 +14,16 @@ pub struct Config {
     pub keybindings: Vec<KeyBinding>,
     pub features: Features,
     pub performance: Performance,
+    #[serde(default)]
+    pub scrollback: ScrollbackConfig,
+    #[serde(default)]
+    pub shell: ShellConfig,
+    #[serde(default)]
+    pub layouts: LayoutConfig,
+    #[serde(default)]
+    pub hyperlinks: HyperlinkConfig,
+    #[serde(default)]
+    pub clipboard: ClipboardConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +57,47 @@ pub struct KeyBinding {
     pub action: String,
 }
 
+impl KeyBinding {
+    /// Whether a key event is this binding's key with exactly its modifiers.
+    /// Keys are a character or a name such as `left` or `f5`, and case
+    /// doesn't matter in either.
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let has = |name: &str| self.modifiers.iter().any(|modifier| modifier.eq_ignore_ascii_case(name));
+        let modifiers = &event.modifiers;
+        if (has("ctrl"), has("shift"), has("alt"), has("super"))
+            != (modifiers.ctrl, modifiers.shift, modifiers.alt, modifiers.super_key)
+        {
+            return false;
+        }
+        
+        let key = self.key.to_lowercase();
+        match event.key {
+            Key::Char(c) => key.chars().eq(c.to_lowercase()),
+            Key::Named(named) => {
+                let name = match named {
+                    NamedKey::Escape => "escape",
+                    NamedKey::Enter => "enter",
+                    NamedKey::Tab => "tab",
+                    NamedKey::Backspace => "backspace",
+                    NamedKey::Insert => "insert",
+                    NamedKey::Delete => "delete",
+                    NamedKey::Left => "left",
+                    NamedKey::Right => "right",
+                    NamedKey::Up => "up",
+                    NamedKey::Down => "down",
+                    NamedKey::PageUp => "pageup",
+                    NamedKey::PageDown => "pagedown",
+                    NamedKey::Home => "home",
+                    NamedKey::End => "end",
+                    NamedKey::F(n) => return key.strip_prefix('f').and_then(|n| n.parse().ok()) == Some(n),
+                    _ => return false,
+                };
+                key == name
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Features {
     pub command_palette: bool,
@@ -57,9 +112,165 @@ pub struct Features {
 pub struct Performance {
     pub gpu_acceleration: bool,
     pub render_fps: u32,
+    /// Memory budget in megabytes shared by all panes. Each pane's share is
+    /// split evenly between its scrollback and its images.
     pub cache_size_mb: u32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrollbackConfig {
+    /// Maximum number of lines kept per terminal.
+    pub lines: usize,
+    /// Optional memory budget for the scrollback in megabytes. It is capped
+    /// by the scrollback's half of the pane's share of
+    /// `performance.cache_size_mb` and whichever limit is hit first wins.
+    pub max_memory_mb: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShellConfig {
+    /// Program to run in new terminals; falls back to `$SHELL`, then `/bin/sh`.
+    pub program: Option<String>,
+    pub args: Vec<String>,
+    /// Extra environment variables for the shell.
+    pub env: HashMap<String, String>,
+    pub working_directory: Option<PathBuf>,
+    /// Value of `TERM` advertised to programs.
+    pub term: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    /// Layouts cycled through by `next_layout`. New tabs start with the first.
+    pub enabled: Vec<LayoutKind>,
+    pub tall: MainPaneOptions,
+    pub fat: MainPaneOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HyperlinkConfig {
+    /// Command that opens a URI, which is passed as its last argument.
+    pub opener: String,
+    pub opener_args: Vec<String>,
+    /// URI schemes that may be opened; anything else is refused.
+    pub allowed_schemes: Vec<String>,
+}
+
+/// What programs may do with the clipboard through OSC 52.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClipboardPolicy {
+    Allow,
+    Deny,
+    /// Hold each request until the user allows or denies it.
+    Ask,
+    /// Programs may copy, but not read what is on the clipboard.
+    WriteOnly,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    pub osc52: ClipboardPolicy,
+    /// Largest text, in bytes, that OSC 52 may copy or read back.
+    pub osc52_max_bytes: usize,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            program: None,
+            args: Vec::new(),
+            env: HashMap::new(),
+            working_directory: None,
+            term: "xterm-256color".to_string(),
+        }
+    }
+}
+
+impl ShellConfig {
+    pub fn program(&self) -> String {
+        self.program
+            .clone()
+            .or_else(|| std::env::var("SHELL").ok())
+            .unwrap_or_else(|| "/bin/sh".to_string())
+    }
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            enabled: LayoutKind::ALL.to_vec(),
+            tall: MainPaneOptions::default(),
+            fat: MainPaneOptions::default(),
+        }
+    }
+}
+
+impl LayoutConfig {
+    pub fn initial(&self) -> LayoutKind {
+        self.enabled.first().copied().unwrap_or(LayoutKind::Splits)
+    }
+    
+    /// The enabled layout after `current`, wrapping around.
+    pub fn next(&self, current: LayoutKind) -> LayoutKind {
+        match self.enabled.iter().position(|&kind| kind == current) {
+            Some(index) => self.enabled[(index + 1) % self.enabled.len()],
+            None => self.initial(),
+        }
+    }
+    
+    pub fn options(&self, kind: LayoutKind) -> MainPaneOptions {
+        match kind {
+            LayoutKind::Fat => self.fat,
+            _ => self.tall,
+        }
+    }
+}
+
+impl Default for HyperlinkConfig {
+    fn default() -> Self {
+        let opener = if cfg!(target_os = "macos") {
+            "open"
+        } else if cfg!(windows) {
+            "explorer"
+        } else {
+            "xdg-open"
+        };
+        
+        Self {
+            opener: opener.to_string(),
+            opener_args: Vec::new(),
+            allowed_schemes: ["http", "https", "file", "mailto", "ftp"]
+                .iter()
+                .map(|scheme| scheme.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            osc52: ClipboardPolicy::WriteOnly,
+            osc52_max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        Self {
+            lines: 10_000,
+            max_memory_mb: None,
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -94,6 +305,91 @@ impl Default for Config {
                     modifiers: vec!["ctrl".to_string(), "shift".to_string()],
                     action: "new_tab".to_string(),
                 },
+                KeyBinding {
+                    key: "d".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "split_right".to_string(),
+                },
+                KeyBinding {
+                    key: "e".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "split_down".to_string(),
+                },
+                KeyBinding {
+                    key: "x".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "close_pane".to_string(),
+                },
+                KeyBinding {
+                    key: "left".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "focus_pane_left".to_string(),
+                },
+                KeyBinding {
+                    key: "right".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "focus_pane_right".to_string(),
+                },
+                KeyBinding {
+                    key: "up".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "focus_pane_up".to_string(),
+                },
+                KeyBinding {
+                    key: "down".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "focus_pane_down".to_string(),
+                },
+                KeyBinding {
+                    key: "right".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "alt".to_string()],
+                    action: "pane_wider".to_string(),
+                },
+                KeyBinding {
+                    key: "left".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "alt".to_string()],
+                    action: "pane_narrower".to_string(),
+                },
+                KeyBinding {
+                    key: "down".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "alt".to_string()],
+                    action: "pane_taller".to_string(),
+                },
+                KeyBinding {
+                    key: "up".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "alt".to_string()],
+                    action: "pane_shorter".to_string(),
+                },
+                KeyBinding {
+                    key: "z".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "toggle_pane_zoom".to_string(),
+                },
+                KeyBinding {
+                    key: "l".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "next_layout".to_string(),
+                },
+                KeyBinding {
+                    key: "k".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "previous_prompt".to_string(),
+                },
+                KeyBinding {
+                    key: "j".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "next_prompt".to_string(),
+                },
+                KeyBinding {
+                    key: "g".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "copy_last_command_output".to_string(),
+                },
+                KeyBinding {
+                    key: "v".to_string(),
+                    modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                    action: "paste".to_string(),
+                },
             ],
             features: Features {
                 command_palette: true,
@@ -108,11 +404,40 @@ impl Default for Config {
                 render_fps: 120,
                 cache_size_mb: 256,
             },
+            scrollback: ScrollbackConfig::default(),
+            shell: ShellConfig::default(),
+            layouts: LayoutConfig::default(),
+            hyperlinks: HyperlinkConfig::default(),
+            clipboard: ClipboardConfig::default(),
         }
     }
 }
 
 impl Config {
+    /// Number of scrollback lines to keep for a terminal `cols` wide that is
+    /// one of `panes`, given that each line costs `line_bytes_per_col * cols`
+    /// bytes.
+    pub fn scrollback_capacity(&self, cols: usize, line_bytes_per_col: usize, panes: usize) -> usize {
+        let share = self.pane_cache_bytes(panes) - self.image_cache_bytes(panes);
+        let budget = self
+            .scrollback
+            .max_memory_mb
+            .map_or(share, |mb| share.min(mb as usize * 1024 * 1024));
+        let line_bytes = (cols * line_bytes_per_col).max(1);
+        
+        self.scrollback.lines.min(budget / line_bytes)
+    }
+    
+    /// Bytes of image data one of `panes` may keep: half of its share of the
+    /// cache budget.
+    pub fn image_cache_bytes(&self, panes: usize) -> usize {
+        self.pane_cache_bytes(panes) / 2
+    }
+    
+    fn pane_cache_bytes(&self, panes: usize) -> usize {
+        self.performance.cache_size_mb as usize * 1024 * 1024 / panes.max(1)
+    }
+    
     pub fn load() -> Result<Self> {
         if let Some(proj_dirs) = ProjectDirs::from("com", "kitty-plus", "kitty-plus") {
             let config_path = proj_dirs.config_dir().join("config.toml");
@@ -142,4 +467,81 @@ impl Config {
         }
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::{KeyEventKind, Modifiers};
+    
+    #[test]
+    fn key_bindings_match_key_and_exact_modifiers() {
+        let config = Config::default();
+        let binding = |action: &str| config.keybindings.iter().find(|binding| binding.action == action).unwrap();
+        let key = |key: Key, modifiers: Modifiers| KeyEvent {
+            key,
+            kind: KeyEventKind::Press,
+            modifiers,
+            text: None,
+            shifted_key: None,
+            base_layout_key: None,
+        };
+        let ctrl_shift = Modifiers { shift: true, ..Modifiers::CTRL };
+        
+        assert!(binding("command_palette").matches(&key(Key::Char('p'), ctrl_shift)));
+        assert!(!binding("command_palette").matches(&key(Key::Char('p'), Modifiers::CTRL)));
+        assert!(!binding("command_palette").matches(&key(Key::Char('p'), Modifiers { alt: true, ..ctrl_shift })));
+        assert!(binding("focus_pane_left").matches(&key(Key::Named(NamedKey::Left), ctrl_shift)));
+        assert!(!binding("focus_pane_left").matches(&key(Key::Named(NamedKey::Right), ctrl_shift)));
+        
+        let f5 = KeyBinding {
+            key: "F5".to_string(),
+            modifiers: Vec::new(),
+            action: "reload".to_string(),
+        };
+        assert!(f5.matches(&key(Key::Named(NamedKey::F(5)), Modifiers { caps_lock: true, ..Modifiers::NONE })));
+        assert!(!f5.matches(&key(Key::Named(NamedKey::F(15)), Modifiers::NONE)));
+    }
+    
+    #[test]
+    fn config_round_trips_and_fills_in_missing_fields() {
+        let config = Config::default();
+        let text = toml::to_string_pretty(&config).unwrap();
+        let parsed: Config = toml::from_str(&text).unwrap();
+        assert_eq!(toml::to_string_pretty(&parsed).unwrap(), text);
+        
+        // A section that sets only some of its fields keeps the defaults for
+        // the rest
+        let mut value = toml::Value::try_from(&config).unwrap();
+        let scrollback = value.get_mut("scrollback").and_then(toml::Value::as_table_mut).unwrap();
+        scrollback.clear();
+        scrollback.insert("max_memory_mb".to_string(), toml::Value::Integer(64));
+        let parsed: Config = value.try_into().unwrap();
+        assert_eq!(parsed.scrollback.lines, ScrollbackConfig::default().lines);
+        assert_eq!(parsed.scrollback.max_memory_mb, Some(64));
+    }
+    
+    #[test]
+    fn scrollback_and_images_share_the_pane_budget() {
+        let mut config = Config::default();
+        config.performance.cache_size_mb = 2;
+        config.scrollback.lines = usize::MAX;
+        assert_eq!(config.image_cache_bytes(1), 1024 * 1024);
+        assert_eq!(config.scrollback_capacity(1024, 1, 1), 1024);
+        
+        config.scrollback.max_memory_mb = Some(8);
+        assert_eq!(config.scrollback_capacity(1024, 1, 1), 1024);
+        config.performance.cache_size_mb = 64;
+        assert_eq!(config.scrollback_capacity(1024, 1, 1), 8 * 1024);
+    }
+    
+    #[test]
+    fn panes_split_the_cache_budget() {
+        let mut config = Config::default();
+        config.performance.cache_size_mb = 4;
+        config.scrollback.lines = usize::MAX;
+        assert_eq!(config.image_cache_bytes(4), 512 * 1024);
+        assert_eq!(config.scrollback_capacity(1024, 1, 4), 512);
+        assert_eq!(config.image_cache_bytes(0), config.image_cache_bytes(1));
+    }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::io::Write;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionRecorder {
@@ -23,7 +23,18 @@ pub enum EventType {
     Input,
     Output,
     Resize { width: u32, height: u32 },
-    Command(String),
+    /// A command line reported by shell integration, recorded when it ends.
+    Command {
+        command: String,
+        exit_code: Option<i32>,
+        duration_ms: u64,
+    },
+}
+
+impl Default for SessionRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SessionRecorder {
@@ -112,7 +123,7 @@ impl SessionRecorder {
         Ok(())
     }
     
-    pub fn record_command(&mut self, command: String) -> Result<()> {
+    pub fn record_command(&mut self, command: String, exit_code: Option<i32>, duration: Duration) -> Result<()> {
         if !self.recording {
             return Ok(());
         }
@@ -123,7 +134,11 @@ impl SessionRecorder {
         
         self.events.push(SessionEvent {
             timestamp,
-            event_type: EventType::Command(command),
+            event_type: EventType::Command {
+                command,
+                exit_code,
+                duration_ms: duration.as_millis() as u64,
+            },
             data: vec![],
         });
         
@@ -175,8 +190,13 @@ impl SessionRecorder {
                     let entry = serde_json::json!([relative_time, "r", format!("{}x{}", width, height)]);
                     writeln!(file, "{}", entry)?;
                 }
-                EventType::Command(cmd) => {
-                    let entry = serde_json::json!([relative_time, "c", cmd]);
+                EventType::Command { command, exit_code, duration_ms } => {
+                    let details = serde_json::json!({
+                        "command": command,
+                        "exit_code": exit_code,
+                        "duration_ms": duration_ms,
+                    });
+                    let entry = serde_json::json!([relative_time, "c", details]);
                     writeln!(file, "{}", entry)?;
                 }
             }
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+/// What a glyph is rasterized from. Wide glyphs take a whole slot, others
+/// its left half.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub wide: bool,
+}
+
+struct Entry {
+    slot: u32,
+    last_used: u64,
+}
+
+/// A texture of rasterized glyphs, divided into equal slots two cells wide.
+/// When every slot is taken, the glyph drawn longest ago makes room; glyphs
+/// drawn in the current frame are never evicted, since the frame's
+/// instances already point at them.
+pub struct GlyphAtlas {
+    size: u32,
+    slot_size: (u32, u32),
+    columns: u32,
+    entries: HashMap<GlyphKey, Entry>,
+    free: Vec<u32>,
+    /// Counts lookups, to order glyphs by when they were last drawn.
+    clock: u64,
+    /// Value of the clock when the current frame began.
+    frame_start: u64,
+    /// Slots rasterized since the last upload, with their coverage bytes.
+    pending: Vec<(u32, Vec<u8>)>,
+}
+
+impl GlyphAtlas {
+    /// Lays out a square texture `size` pixels wide for glyphs of the given
+    /// cell size.
+    pub fn new(size: u32, cell_size: (u32, u32)) -> Self {
+        let slot_size = (cell_size.0.max(1) * 2, cell_size.1.max(1));
+        let columns = (size / slot_size.0).max(1);
+        let slots = columns * (size / slot_size.1).max(1);
+        Self {
+            size,
+            slot_size,
+            columns,
+            entries: HashMap::new(),
+            // Handed out from the end, so slot 0 goes first
+            free: (0..slots).rev().collect(),
+            clock: 0,
+            frame_start: 0,
+            pending: Vec::new(),
+        }
+    }
+    
+    pub fn slot_size(&self) -> (u32, u32) {
+        self.slot_size
+    }
+    
+    pub fn begin_frame(&mut self) {
+        self.frame_start = self.clock + 1;
+    }
+    
+    /// Finds the slot holding a glyph, rasterizing it into the atlas on a
+    /// miss. `rasterize` fills a slot-sized coverage bitmap. Returns `None`
+    /// if every slot holds a glyph of the current frame.
+    pub fn get_or_insert(&mut self, key: &GlyphKey, rasterize: impl FnOnce() -> Vec<u8>) -> Option<u32> {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_used = self.clock;
+            return Some(entry.slot);
+        }
+        
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => self.evict()?,
+        };
+        self.entries.insert(key.clone(), Entry {
+            slot,
+            last_used: self.clock,
+        });
+        self.pending.push((slot, rasterize()));
+        Some(slot)
+    }
+    
+    fn evict(&mut self) -> Option<u32> {
+        let key = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.last_used < self.frame_start)
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())?;
+        self.entries.remove(&key).map(|entry| entry.slot)
+    }
+    
+    /// Pixel position of a slot's top left corner.
+    pub fn origin(&self, slot: u32) -> (u32, u32) {
+        (slot % self.columns * self.slot_size.0, slot / self.columns * self.slot_size.1)
+    }
+    
+    /// Texture coordinates of a slot as `[x, y, width, height]`, covering
+    /// `width` pixels of it.
+    pub fn uv(&self, slot: u32, width: u32) -> [f32; 4] {
+        let (x, y) = self.origin(slot);
+        let size = self.size as f32;
+        [
+            x as f32 / size,
+            y as f32 / size,
+            width.min(self.slot_size.0) as f32 / size,
+            self.slot_size.1 as f32 / size,
+        ]
+    }
+    
+    /// Rasterized slots waiting to be written to the texture.
+    pub fn take_uploads(&mut self) -> Vec<(u32, Vec<u8>)> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn key(text: &str) -> GlyphKey {
+        GlyphKey {
+            text: text.to_string(),
+            bold: false,
+            italic: false,
+            wide: false,
+        }
+    }
+    
+    #[test]
+    fn evicts_least_recently_drawn_glyph() {
+        // Room for two slots of 8x16
+        let mut atlas = GlyphAtlas::new(16, (4, 16));
+        atlas.begin_frame();
+        let a = atlas.get_or_insert(&key("a"), || vec![1; 128]).unwrap();
+        let b = atlas.get_or_insert(&key("b"), || vec![2; 128]).unwrap();
+        assert_eq!((atlas.origin(a), atlas.origin(b)), ((0, 0), (8, 0)));
+        assert_eq!(atlas.take_uploads().len(), 2);
+        
+        // Everything is in use by this frame
+        assert_eq!(atlas.get_or_insert(&key("c"), Vec::new), None);
+        
+        atlas.begin_frame();
+        assert_eq!(atlas.get_or_insert(&key("a"), || unreachable!()), Some(a));
+        assert_eq!(atlas.get_or_insert(&key("c"), || vec![3; 128]), Some(b));
+        assert_eq!(atlas.take_uploads(), vec![(b, vec![3; 128])]);
+        
+        atlas.begin_frame();
+        assert_eq!(atlas.get_or_insert(&key("b"), || vec![2; 128]), Some(a));
+    }
+}
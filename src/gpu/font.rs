@@ -0,0 +1,183 @@
+use anyhow::{bail, Context, Result};
+use fontdue::{Font, FontSettings};
+use std::path::{Path, PathBuf};
+
+use crate::config::FontConfig;
+
+/// Monospace families tried when the configured one isn't installed.
+const FALLBACK_FAMILIES: [&str; 5] = ["DejaVu Sans Mono", "Liberation Mono", "Menlo", "Consolas", "Noto Sans Mono"];
+
+/// The configured font in its four styles, at one pixel size. Missing
+/// styles fall back to the regular face.
+pub struct FontSet {
+    regular: Font,
+    bold: Option<Font>,
+    italic: Option<Font>,
+    bold_italic: Option<Font>,
+    px: f32,
+    /// Cell size in pixels.
+    pub cell_width: u32,
+    pub cell_height: u32,
+    /// Distance from the top of a cell to the baseline.
+    pub baseline: u32,
+    /// Thickness of underlines and strikethroughs.
+    pub line_thickness: u32,
+}
+
+impl FontSet {
+    /// Finds the configured family among the installed fonts and sizes it
+    /// for a display with the given scale factor.
+    pub fn load(config: &FontConfig, scale_factor: f32) -> Result<Self> {
+        let files = font_files();
+        let family = std::iter::once(config.family.as_str())
+            .chain(FALLBACK_FAMILIES)
+            .find(|family| find_face(&files, family, "regular").is_some())
+            .with_context(|| format!("No font found for {:?} or the fallback families", config.family))?;
+        if family != config.family {
+            log::warn!("Font {:?} isn't installed; using {:?}", config.family, family);
+        }
+        
+        let load = |style: &str| -> Result<Option<Font>> {
+            let Some(path) = find_face(&files, family, style) else {
+                return Ok(None);
+            };
+            let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            match Font::from_bytes(data, FontSettings::default()) {
+                Ok(font) => Ok(Some(font)),
+                Err(e) => bail!("Failed to load {}: {}", path.display(), e),
+            }
+        };
+        let regular = load("regular")?.with_context(|| format!("No regular face of {:?}", family))?;
+        
+        let px = config.size * scale_factor;
+        let advance = regular.metrics('M', px).advance_width;
+        let Some(line) = regular.horizontal_line_metrics(px) else {
+            bail!("Font {:?} has no horizontal metrics", family);
+        };
+        Ok(Self {
+            bold: load("bold")?,
+            italic: load("italic")?,
+            bold_italic: load("bolditalic")?,
+            px,
+            cell_width: advance.ceil().max(1.0) as u32,
+            cell_height: line.new_line_size.ceil().max(1.0) as u32,
+            baseline: line.ascent.round().max(0.0) as u32,
+            line_thickness: (px / 14.0).round().max(1.0) as u32,
+            regular,
+        })
+    }
+    
+    fn face(&self, bold: bool, italic: bool) -> &Font {
+        let styled = match (bold, italic) {
+            (true, true) => self.bold_italic.as_ref(),
+            (true, false) => self.bold.as_ref(),
+            (false, true) => self.italic.as_ref(),
+            (false, false) => None,
+        };
+        styled.unwrap_or(&self.regular)
+    }
+    
+    /// Rasterizes a grapheme cluster into a coverage bitmap of `width` by
+    /// `height` pixels, with the baseline where cells have it. Characters
+    /// the styled face lacks come from the regular face.
+    pub fn rasterize(&self, text: &str, bold: bool, italic: bool, (width, height): (u32, u32)) -> Vec<u8> {
+        let mut bitmap = vec![0u8; (width * height) as usize];
+        let mut pen = 0.0;
+        for c in text.chars() {
+            let styled = self.face(bold, italic);
+            let font = if styled.lookup_glyph_index(c) != 0 { styled } else { &self.regular };
+            let (metrics, coverage) = font.rasterize(c, self.px);
+            
+            let left = (pen + metrics.xmin as f32).round() as i32;
+            let top = self.baseline as i32 - metrics.ymin - metrics.height as i32;
+            for (row, line) in coverage.chunks_exact(metrics.width.max(1)).enumerate() {
+                let y = top + row as i32;
+                if !(0..height as i32).contains(&y) {
+                    continue;
+                }
+                for (col, &value) in line.iter().enumerate() {
+                    let x = left + col as i32;
+                    if (0..width as i32).contains(&x) {
+                        let pixel = &mut bitmap[(y as u32 * width + x as u32) as usize];
+                        *pixel = (*pixel).max(value);
+                    }
+                }
+            }
+            pen += metrics.advance_width;
+        }
+        bitmap
+    }
+}
+
+/// Font files in the usual system and user font directories.
+fn font_files() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = [
+        "/usr/share/fonts",
+        "/usr/local/share/fonts",
+        "/Library/Fonts",
+        "/System/Library/Fonts",
+        "C:\\Windows\\Fonts",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    if let Some(home) = directories::BaseDirs::new().map(|dirs| dirs.home_dir().to_path_buf()) {
+        dirs.extend([".local/share/fonts", ".fonts", "Library/Fonts"].map(|dir| home.join(dir)));
+    }
+    
+    let mut files = Vec::new();
+    for dir in dirs {
+        collect_fonts(&dir, 4, &mut files);
+    }
+    files
+}
+
+fn collect_fonts(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            if depth > 0 {
+                collect_fonts(&path, depth - 1, files);
+            }
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ttf") || ext.eq_ignore_ascii_case("otf"))
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// Picks the file of a family's style by name, as in `JetBrainsMono-Bold.ttf`.
+/// The regular face may also go without a suffix, and italics may be
+/// called oblique.
+fn find_face<'a>(files: &'a [PathBuf], family: &str, style: &str) -> Option<&'a Path> {
+    let normalize = |name: &str| -> String {
+        name.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    };
+    let family = normalize(family);
+    let styles: &[&str] = match style {
+        "regular" => &["regular", "book", ""],
+        "italic" => &["italic", "oblique"],
+        "bolditalic" => &["bolditalic", "boldoblique"],
+        _ => &["bold"],
+    };
+    
+    files
+        .iter()
+        .find(|path| {
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                return false;
+            };
+            normalize(stem)
+                .strip_prefix(&family)
+                .is_some_and(|rest| styles.contains(&rest))
+        })
+        .map(PathBuf::as_path)
+}
@@ -1,16 +1,69 @@
+mod atlas;
+mod font;
+
 use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
 use wgpu::{Device, Queue, Surface, SurfaceConfiguration, TextureFormat};
 use winit::window::Window;
 
+use crate::config::FontConfig;
+use crate::layout::Rect;
+use crate::terminal::{CellWidth, Rgb, Terminal, TerminalState};
+use atlas::{GlyphAtlas, GlyphKey};
+use font::FontSet;
+
+/// Side of the glyph atlas texture, unless the device allows less.
+const ATLAS_SIZE: u32 = 2048;
+
+/// One quad: a solid rectangle, or a glyph from the atlas.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct Instance {
+    position: [f32; 2],
+    size: [f32; 2],
+    uv: [f32; 4],
+    color: [f32; 4],
+    textured: u32,
+}
+
+impl Instance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] =
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x4, 3 => Float32x4, 4 => Uint32];
+    
+    fn rect(position: [f32; 2], size: [f32; 2], color: [f32; 4]) -> Self {
+        Self {
+            position,
+            size,
+            uv: [0.0; 4],
+            color,
+            textured: 0,
+        }
+    }
+}
+
 pub struct GpuRenderer {
     device: Device,
     queue: Queue,
     surface: Surface,
     config: SurfaceConfiguration,
+    pipeline: wgpu::RenderPipeline,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    atlas_texture: wgpu::Texture,
+    atlas: GlyphAtlas,
+    fonts: FontSet,
+    instances: wgpu::Buffer,
+    /// Instances the buffer has room for.
+    instance_capacity: usize,
+    /// Whether the missing image support has been logged already.
+    warned_about_images: bool,
 }
 
 impl GpuRenderer {
-    pub async fn new(window: &Window) -> Result<Self> {
+    pub async fn new(window: &Window, font: &FontConfig) -> Result<Self> {
+        let fonts = FontSet::load(font, window.scale_factor() as f32)?;
+        
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             dx12_shader_compiler: Default::default(),
@@ -61,21 +114,203 @@ impl GpuRenderer {
         
         surface.configure(&device, &config);
         
+        let atlas_size = ATLAS_SIZE.min(device.limits().max_texture_dimension_2d);
+        let atlas = GlyphAtlas::new(atlas_size, (fonts.cell_width, fonts.cell_height));
+        let atlas_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: wgpu::Extent3d {
+                width: atlas_size,
+                height: atlas_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let atlas_view = atlas_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Glyphs are drawn at their rasterized size, so pixels map one to one
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Glyph Sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let uniforms = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Uniforms"),
+            contents: bytemuck::cast_slice(&[config.width as f32, config.height as f32, 0.0, 0.0]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cell Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &Instance::ATTRIBUTES,
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        
+        let instance_capacity = 4096;
+        let instances = Self::create_instance_buffer(&device, instance_capacity);
+        
         Ok(Self {
             device,
             queue,
             surface,
             config,
+            pipeline,
+            uniforms,
+            bind_group,
+            atlas_texture,
+            atlas,
+            fonts,
+            instances,
+            instance_capacity,
+            warned_about_images: false,
+        })
+    }
+    
+    fn create_instance_buffer(device: &Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instances"),
+            size: (capacity * std::mem::size_of::<Instance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         })
     }
     
+    /// Size of a cell in pixels, as `(width, height)`.
+    pub fn cell_size(&self) -> (u32, u32) {
+        (self.fonts.cell_width, self.fonts.cell_height)
+    }
+    
     pub fn resize(&mut self, width: u32, height: u32) {
-        self.config.width = width;
-        self.config.height = height;
+        self.config.width = width.max(1);
+        self.config.height = height.max(1);
         self.surface.configure(&self.device, &self.config);
+        self.queue.write_buffer(
+            &self.uniforms,
+            0,
+            bytemuck::cast_slice(&[self.config.width as f32, self.config.height as f32, 0.0, 0.0]),
+        );
     }
     
-    pub fn render(&mut self) -> Result<()> {
+    /// Draws the visible panes of the active tab: cell backgrounds and the
+    /// cursor in one draw call, then glyphs and decorations over them in a
+    /// second.
+    ///
+    /// Images from the kitty graphics protocol and sixel are stored and
+    /// answered for, but not drawn yet; their cells render as plain text.
+    pub fn render(&mut self, terminal: &Terminal) -> Result<()> {
+        self.atlas.begin_frame();
+        let clear = terminal.active_state().palette.background;
+        let focused = terminal.focused_pane_id();
+        let (mut backgrounds, mut foregrounds) = (Vec::new(), Vec::new());
+        for (pane_id, rect) in terminal.pane_layout() {
+            if let Some(state) = terminal.pane_state(pane_id) {
+                if !self.warned_about_images && !state.visible_placements().is_empty() {
+                    log::warn!("Images are not rendered yet; showing only the text around them");
+                    self.warned_about_images = true;
+                }
+                self.build_pane(state, rect, pane_id == focused, clear, &mut backgrounds, &mut foregrounds);
+            }
+        }
+        self.upload_glyphs();
+        
+        let split = backgrounds.len() as u32;
+        let mut instances = backgrounds;
+        instances.append(&mut foregrounds);
+        if instances.len() > self.instance_capacity {
+            self.instance_capacity = instances.len().next_power_of_two();
+            self.instances = Self::create_instance_buffer(&self.device, self.instance_capacity);
+        }
+        self.queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&instances));
+        
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         
@@ -84,23 +319,31 @@ impl GpuRenderer {
         });
         
         {
-            let _render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            let [r, g, b, a] = self.color(clear);
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.1,
-                            a: 1.0,
+                            r: r as f64,
+                            g: g as f64,
+                            b: b as f64,
+                            a: a as f64,
                         }),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
             });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.instances.slice(..));
+            render_pass.draw(0..4, 0..split);
+            render_pass.draw(0..4, split..instances.len() as u32);
         }
         
         self.queue.submit(std::iter::once(encoder.finish()));
@@ -108,4 +351,138 @@ impl GpuRenderer {
         
         Ok(())
     }
-}
\ No newline at end of file
+    
+    /// Adds the instances of a pane, whose top left cell is at `rect`.
+    fn build_pane(
+        &mut self,
+        state: &TerminalState,
+        rect: Rect,
+        focused: bool,
+        clear: Rgb,
+        backgrounds: &mut Vec<Instance>,
+        foregrounds: &mut Vec<Instance>,
+    ) {
+        let (cell_width, cell_height) = (self.fonts.cell_width as f32, self.fonts.cell_height as f32);
+        let (baseline, thickness) = (self.fonts.baseline as f32, self.fonts.line_thickness as f32);
+        let palette = &state.palette;
+        let origin = [rect.x as f32 * cell_width, rect.y as f32 * cell_height];
+        if palette.background != clear {
+            let size = [rect.cols as f32 * cell_width, rect.rows as f32 * cell_height];
+            backgrounds.push(Instance::rect(origin, size, self.color(palette.background)));
+        }
+        
+        // Only the focused pane shows its cursor, and only at the bottom
+        // unless the application hid it
+        let cursor = (focused && state.display_offset == 0 && state.modes.cursor_visible)
+            .then(|| (state.cursor_y, state.cursor_x.min(rect.cols.saturating_sub(1))));
+        let slot_size = self.atlas.slot_size();
+        
+        for (y, (line, row)) in state.visible_rows().into_iter().enumerate().take(rect.rows) {
+            let selected = line.is_some_and(|line| state.selection.as_ref().is_some_and(|lines| lines.contains(&line)));
+            for (x, cell) in row.cells.iter().enumerate().take(rect.cols) {
+                if cell.width == CellWidth::Spacer {
+                    continue;
+                }
+                let cells = if cell.width == CellWidth::Wide { 2 } else { 1 };
+                let position = [origin[0] + x as f32 * cell_width, origin[1] + y as f32 * cell_height];
+                let width = cells as f32 * cell_width;
+                
+                let mut fg = palette.resolve(cell.fg, palette.foreground);
+                let mut bg = palette.resolve(cell.bg, palette.background);
+                if cell.attrs.reverse != selected {
+                    std::mem::swap(&mut fg, &mut bg);
+                }
+                // A block cursor shows the text in the cell's background. It
+                // covers a wide character even when it sits on its spacer.
+                if cursor.is_some_and(|(cursor_y, cursor_x)| cursor_y == y && (x..x + cells).contains(&cursor_x)) {
+                    (fg, bg) = (bg, palette.cursor);
+                }
+                if cell.attrs.dim {
+                    fg = blend(fg, bg);
+                }
+                if bg != palette.background {
+                    backgrounds.push(Instance::rect(position, [width, cell_height], self.color(bg)));
+                }
+                
+                let color = self.color(fg);
+                if !cell.attrs.hidden && !matches!(cell.c, ' ' | '\0') {
+                    let key = GlyphKey {
+                        text: cell.grapheme(),
+                        bold: cell.attrs.bold,
+                        italic: cell.attrs.italic,
+                        wide: cells == 2,
+                    };
+                    let fonts = &self.fonts;
+                    match self.atlas.get_or_insert(&key, || fonts.rasterize(&key.text, key.bold, key.italic, slot_size)) {
+                        Some(slot) => foregrounds.push(Instance {
+                            position,
+                            size: [width, slot_size.1 as f32],
+                            uv: self.atlas.uv(slot, width as u32),
+                            color,
+                            textured: 1,
+                        }),
+                        None => log::debug!("Glyph atlas is full; skipping {:?}", key.text),
+                    }
+                }
+                
+                if cell.attrs.underline || state.is_hovered(cell) {
+                    let top = (baseline + thickness).min(cell_height - thickness);
+                    foregrounds.push(Instance::rect([position[0], position[1] + top], [width, thickness], color));
+                }
+                if cell.attrs.strikethrough {
+                    let top = (baseline * 2.0 / 3.0).round();
+                    foregrounds.push(Instance::rect([position[0], position[1] + top], [width, thickness], color));
+                }
+            }
+        }
+    }
+    
+    /// Writes glyphs rasterized this frame into the atlas texture.
+    fn upload_glyphs(&mut self) {
+        let (width, height) = self.atlas.slot_size();
+        for (slot, coverage) in self.atlas.take_uploads() {
+            let (x, y) = self.atlas.origin(slot);
+            self.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.atlas_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &coverage,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+    
+    /// Converts a color for the surface, which blends in linear space when
+    /// its format is sRGB.
+    fn color(&self, color: Rgb) -> [f32; 4] {
+        let channel = |value: u8| {
+            let value = value as f32 / 255.0;
+            if !self.config.format.is_srgb() {
+                value
+            } else if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        [channel(color.r), channel(color.g), channel(color.b), 1.0]
+    }
+}
+
+/// Halfway between two colors, for dim text.
+fn blend(fg: Rgb, bg: Rgb) -> Rgb {
+    let mix = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+    Rgb::new(mix(fg.r, bg.r), mix(fg.g, bg.g), mix(fg.b, bg.b))
+}
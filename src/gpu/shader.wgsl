@@ -0,0 +1,55 @@
+// Draws every instance as a quad: a solid rectangle, or a glyph whose
+// coverage comes from the atlas.
+
+struct Uniforms {
+    screen_size: vec2<f32>,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+@group(0) @binding(1) var atlas: texture_2d<f32>;
+@group(0) @binding(2) var atlas_sampler: sampler;
+
+struct Instance {
+    // Top left corner and size, in pixels
+    @location(0) position: vec2<f32>,
+    @location(1) size: vec2<f32>,
+    // Atlas rectangle as x, y, width, height
+    @location(2) uv: vec4<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) textured: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) textured: u32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, instance: Instance) -> VertexOutput {
+    // Triangle strip corners: top left, top right, bottom left, bottom right
+    let corner = vec2<f32>(f32(vertex & 1u), f32(vertex >> 1u));
+    let pixel = instance.position + corner * instance.size;
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(
+        pixel.x / uniforms.screen_size.x * 2.0 - 1.0,
+        1.0 - pixel.y / uniforms.screen_size.y * 2.0,
+        0.0,
+        1.0,
+    );
+    out.uv = instance.uv.xy + corner * instance.uv.zw;
+    out.color = instance.color;
+    out.textured = instance.textured;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Sampled unconditionally, as sampling must happen in uniform control flow
+    let coverage = textureSample(atlas, atlas_sampler, in.uv).r;
+    let alpha = select(in.color.a, in.color.a * coverage, in.textured != 0u);
+    return vec4<f32>(in.color.rgb, alpha);
+}
@@ -0,0 +1,765 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+/// Largest width or height accepted for an image, as in kitty.
+pub const MAX_IMAGE_DIMENSION: u32 = 10_000;
+
+/// Cell size in pixels used to size placements until the renderer reports
+/// the real one.
+pub const DEFAULT_CELL_SIZE: (u32, u32) = (10, 20);
+
+/// Programs may leave the padding off the last chunk.
+const PAYLOAD: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Action {
+    /// `a=t`
+    #[default]
+    Transmit,
+    /// `a=T`
+    TransmitAndPut,
+    /// `a=q`: checks that an image could be loaded, without keeping it.
+    Query,
+    /// `a=p`
+    Put,
+    /// `a=d`
+    Delete,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// `f=24`
+    Rgb,
+    /// `f=32`
+    #[default]
+    Rgba,
+    /// `f=100`
+    Png,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Medium {
+    /// `t=d`: the payload is the image data.
+    #[default]
+    Direct,
+    /// `t=f`: the payload is the path of a file to read.
+    File,
+    /// `t=t`: like a file, but deleted once read.
+    TempFile,
+    /// `t=s`: the payload is the name of a POSIX shared memory object,
+    /// unlinked once read.
+    SharedMemory,
+}
+
+/// A kitty graphics protocol command, `ESC _ G control-data ; payload ESC \`.
+/// Fields keep the protocol's defaults when their key is missing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphicsCommand {
+    pub action: Action,
+    pub format: Format,
+    pub medium: Medium,
+    /// `o=z`: the data is zlib compressed.
+    pub compressed: bool,
+    /// `s` and `v`: size of raw RGB(A) data in pixels.
+    pub data_width: u32,
+    pub data_height: u32,
+    /// `S` and `O`: how much to read from a file or shared memory, and where.
+    pub data_size: usize,
+    pub data_offset: usize,
+    /// `i`, `I` and `p`.
+    pub image_id: u32,
+    pub image_number: u32,
+    pub placement_id: u32,
+    /// `m=1`: more chunks of the payload follow.
+    pub more: bool,
+    /// `q`: 1 suppresses OK responses, 2 also suppresses errors.
+    pub quiet: u8,
+    /// `x`, `y`, `w` and `h`: the part of the image to show, in pixels. For
+    /// deletions, `x` and `y` are a cell or a range of ids instead.
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    /// `X` and `Y`: offset of the image within its first cell, in pixels.
+    pub offset_x: u32,
+    pub offset_y: u32,
+    /// `c` and `r`: cells to fill; zero means the image's own size.
+    pub cols: u32,
+    pub rows: u32,
+    /// `z`
+    pub z_index: i32,
+    /// `C=1`: leave the cursor where it is after placing the image.
+    pub keep_cursor: bool,
+    /// `d`: what to delete, lowercase to keep the image data.
+    pub delete: u8,
+    /// Base64 payload, decoded once the last chunk has arrived.
+    pub payload: Vec<u8>,
+}
+
+impl Default for GraphicsCommand {
+    fn default() -> Self {
+        Self {
+            action: Action::default(),
+            format: Format::default(),
+            medium: Medium::default(),
+            compressed: false,
+            data_width: 0,
+            data_height: 0,
+            data_size: 0,
+            data_offset: 0,
+            image_id: 0,
+            image_number: 0,
+            placement_id: 0,
+            more: false,
+            quiet: 0,
+            x: 0,
+            y: 0,
+            w: 0,
+            h: 0,
+            offset_x: 0,
+            offset_y: 0,
+            cols: 0,
+            rows: 0,
+            z_index: 0,
+            keep_cursor: false,
+            delete: b'a',
+            payload: Vec::new(),
+        }
+    }
+}
+
+impl GraphicsCommand {
+    /// Parses the body of an APC G sequence, without the leading `G`.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (control, payload) = match data.iter().position(|&b| b == b';') {
+            Some(split) => (&data[..split], &data[split + 1..]),
+            None => (data, &[][..]),
+        };
+        let mut command = Self {
+            payload: payload.to_vec(),
+            ..Self::default()
+        };
+        
+        for pair in control.split(|&b| b == b',').filter(|pair| !pair.is_empty()) {
+            let (&key, value) = match pair {
+                [key, b'=', value @ ..] if !value.is_empty() => (key, value),
+                _ => bail!("EINVAL:Malformed control data {:?}", String::from_utf8_lossy(pair)),
+            };
+            let number = || -> Result<u32> {
+                std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| anyhow!("EINVAL:Invalid value for key {}", key as char))
+            };
+            match key {
+                b'a' => {
+                    command.action = match value {
+                        b"t" => Action::Transmit,
+                        b"T" => Action::TransmitAndPut,
+                        b"q" => Action::Query,
+                        b"p" => Action::Put,
+                        b"d" => Action::Delete,
+                        _ => bail!("EINVAL:Unsupported action {}", String::from_utf8_lossy(value)),
+                    }
+                }
+                b'f' => {
+                    command.format = match number()? {
+                        24 => Format::Rgb,
+                        32 => Format::Rgba,
+                        100 => Format::Png,
+                        format => bail!("EINVAL:Unknown format {}", format),
+                    }
+                }
+                b't' => {
+                    command.medium = match value {
+                        b"d" => Medium::Direct,
+                        b"f" => Medium::File,
+                        b"t" => Medium::TempFile,
+                        b"s" => Medium::SharedMemory,
+                        _ => bail!("EINVAL:Unknown transmission medium {}", String::from_utf8_lossy(value)),
+                    }
+                }
+                b'o' => command.compressed = value == b"z",
+                b's' => command.data_width = number()?,
+                b'v' => command.data_height = number()?,
+                b'S' => command.data_size = number()? as usize,
+                b'O' => command.data_offset = number()? as usize,
+                b'i' => command.image_id = number()?,
+                b'I' => command.image_number = number()?,
+                b'p' => command.placement_id = number()?,
+                b'm' => command.more = value == b"1",
+                b'q' => command.quiet = number()?.min(2) as u8,
+                b'x' => command.x = number()?,
+                b'y' => command.y = number()?,
+                b'w' => command.w = number()?,
+                b'h' => command.h = number()?,
+                b'X' => command.offset_x = number()?,
+                b'Y' => command.offset_y = number()?,
+                b'c' => command.cols = number()?,
+                b'r' => command.rows = number()?,
+                b'C' => command.keep_cursor = value == b"1",
+                b'd' => command.delete = value[0],
+                b'z' => {
+                    command.z_index = std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| anyhow!("EINVAL:Invalid z-index"))?
+                }
+                // Animation and Unicode placeholder keys aren't supported
+                _ => log::trace!("Ignoring graphics key {}", key as char),
+            }
+        }
+        Ok(command)
+    }
+    
+    /// Formats a response to this command, as sent back to the program.
+    pub fn response(&self, message: &str) -> String {
+        let mut keys = Vec::new();
+        if self.image_id != 0 {
+            keys.push(format!("i={}", self.image_id));
+        }
+        if self.image_number != 0 {
+            keys.push(format!("I={}", self.image_number));
+        }
+        if self.placement_id != 0 {
+            keys.push(format!("p={}", self.placement_id));
+        }
+        format!("\x1b_G{};{}\x1b\\", keys.join(","), message)
+    }
+}
+
+/// An image held in memory as RGBA pixels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub id: u32,
+    pub number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    /// When the image was last transmitted or placed, for eviction.
+    last_used: u64,
+}
+
+/// Where an image is shown. Placements are anchored to absolute lines, so
+/// they scroll with the text around them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub image_id: u32,
+    /// Zero for placements the program didn't name.
+    pub id: u32,
+    pub line: usize,
+    pub col: usize,
+    pub rows: usize,
+    pub cols: usize,
+    pub z_index: i32,
+    /// Part of the image shown, in pixels: x, y, width and height.
+    pub source: (u32, u32, u32, u32),
+    /// Offset within the first cell, in pixels.
+    pub offset: (u32, u32),
+    /// Placed on the alternate screen, which has its own images.
+    pub alt_screen: bool,
+}
+
+impl Placement {
+    pub fn lines(&self) -> Range<usize> {
+        self.line..self.line + self.rows
+    }
+    
+    pub fn intersects(&self, line: usize, col: usize) -> bool {
+        self.lines().contains(&line) && (self.col..self.col + self.cols).contains(&col)
+    }
+}
+
+/// Images and their placements for one terminal. The pixel data is kept
+/// within a memory budget by evicting the least recently used images.
+#[derive(Debug, Default)]
+pub struct ImageStore {
+    images: HashMap<u32, Image>,
+    pub placements: Vec<Placement>,
+    /// A chunked upload in progress: its first command with the payload
+    /// received so far.
+    upload: Option<GraphicsCommand>,
+    limit: usize,
+    used: usize,
+    next_id: u32,
+    clock: u64,
+}
+
+impl ImageStore {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            next_id: u32::MAX,
+            ..Self::default()
+        }
+    }
+    
+    /// Changes the byte limit, evicting images that no longer fit.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.evict(0);
+    }
+    
+    /// Bytes of pixel data held.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+    
+    pub fn get(&self, id: u32) -> Option<&Image> {
+        self.images.get(&id)
+    }
+    
+    /// The most recent image transmitted with number `number`.
+    pub fn find_number(&self, number: u32) -> Option<u32> {
+        self.images
+            .values()
+            .filter(|image| image.number == number)
+            .max_by_key(|image| image.last_used)
+            .map(|image| image.id)
+    }
+    
+    /// Collects the chunks of an upload. Returns `None` while more chunks
+    /// are expected, and otherwise the whole command along with whether it
+    /// arrived intact; other commands pass straight through.
+    pub fn receive(&mut self, command: GraphicsCommand) -> Option<(GraphicsCommand, Result<()>)> {
+        let Some(upload) = &mut self.upload else {
+            let chunked = command.more && matches!(command.action, Action::Transmit | Action::TransmitAndPut | Action::Query);
+            if chunked {
+                self.upload = Some(command);
+                return None;
+            }
+            return Some((command, Ok(())));
+        };
+        
+        upload.payload.extend_from_slice(&command.payload);
+        // Base64 takes four bytes for every three
+        if upload.payload.len() / 4 * 3 > self.limit {
+            let mut upload = self.upload.take()?;
+            upload.payload.clear();
+            let error = anyhow!("EFBIG:Upload of image {} exceeds the image cache size", upload.image_id);
+            return Some((upload, Err(error)));
+        }
+        if command.more {
+            return None;
+        }
+        self.upload.take().map(|upload| (upload, Ok(())))
+    }
+    
+    /// Budget for pixel data, in bytes.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+    
+    /// Stores an image under `id`, replacing any image with that id, or
+    /// under a new id when `id` is zero. Returns the id used.
+    pub fn insert(&mut self, id: u32, number: u32, width: u32, height: u32, pixels: Vec<u8>) -> Result<u32> {
+        if pixels.len() > self.limit {
+            bail!("EFBIG:Image of {}x{} pixels exceeds the image cache size", width, height);
+        }
+        
+        let id = if id == 0 { self.allocate_id() } else { id };
+        self.remove(id);
+        self.evict(pixels.len());
+        self.clock += 1;
+        self.used += pixels.len();
+        self.images.insert(id, Image {
+            id,
+            number,
+            width,
+            height,
+            pixels,
+            last_used: self.clock,
+        });
+        Ok(id)
+    }
+    
+    /// Ids chosen by the terminal count down from the top of the range,
+    /// away from the small ids programs pick.
+    fn allocate_id(&mut self) -> u32 {
+        while self.images.contains_key(&self.next_id) || self.next_id == 0 {
+            self.next_id = self.next_id.wrapping_sub(1);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_sub(1);
+        id
+    }
+    
+    fn remove(&mut self, id: u32) {
+        if let Some(image) = self.images.remove(&id) {
+            self.used -= image.pixels.len();
+        }
+    }
+    
+    /// Makes room for `incoming` bytes, dropping images nothing shows before
+    /// the ones still on screen, oldest first.
+    fn evict(&mut self, incoming: usize) {
+        while self.used + incoming > self.limit {
+            let placed = |id: u32| self.placements.iter().any(|placement| placement.image_id == id);
+            let Some(victim) = self
+                .images
+                .values()
+                .min_by_key(|image| (placed(image.id), image.last_used))
+                .map(|image| image.id)
+            else {
+                break;
+            };
+            log::debug!("Evicting image {} from the image cache", victim);
+            self.remove(victim);
+            self.placements.retain(|placement| placement.image_id != victim);
+        }
+    }
+    
+    /// Adds a placement, replacing the one with the same image and
+    /// placement id.
+    pub fn place(&mut self, placement: Placement) {
+        self.clock += 1;
+        if let Some(image) = self.images.get_mut(&placement.image_id) {
+            image.last_used = self.clock;
+        }
+        if placement.id != 0 {
+            self.placements
+                .retain(|old| (old.image_id, old.id) != (placement.image_id, placement.id));
+        }
+        self.placements.push(placement);
+    }
+    
+    /// Removes the placements `matches` selects. With `free_images`, images
+    /// left without placements are dropped too, as uppercase delete
+    /// commands ask.
+    pub fn delete(&mut self, mut matches: impl FnMut(&Placement) -> bool, free_images: bool) {
+        let mut affected = Vec::new();
+        self.placements.retain(|placement| {
+            let delete = matches(placement);
+            if delete {
+                affected.push(placement.image_id);
+            }
+            !delete
+        });
+        
+        if free_images {
+            for id in affected {
+                if !self.placements.iter().any(|placement| placement.image_id == id) {
+                    self.remove(id);
+                }
+            }
+        }
+    }
+    
+    /// Drops an image and all of its placements.
+    pub fn delete_image(&mut self, id: u32) {
+        self.placements.retain(|placement| placement.image_id != id);
+        self.remove(id);
+    }
+    
+    /// Image ids in `range`, for range deletions.
+    pub fn ids_in(&self, range: Range<u32>) -> Vec<u32> {
+        self.images.keys().copied().filter(|id| range.contains(id)).collect()
+    }
+    
+    /// Forgets placements that scrolled out of the scrollback entirely.
+    pub fn prune(&mut self, first_line: usize) {
+        self.placements.retain(|placement| placement.lines().end > first_line);
+    }
+    
+    /// Placements on `lines` of a screen, bottom-most z-index first.
+    pub fn visible(&self, lines: Range<usize>, alt_screen: bool) -> Vec<&Placement> {
+        let mut visible: Vec<&Placement> = self
+            .placements
+            .iter()
+            .filter(|placement| placement.alt_screen == alt_screen)
+            .filter(|placement| placement.line < lines.end && placement.lines().end > lines.start)
+            .collect();
+        visible.sort_by_key(|placement| placement.z_index);
+        visible
+    }
+}
+
+/// Reads and decodes the image a transmit or query command carries,
+/// returning its size and RGBA pixels.
+pub fn load(command: &GraphicsCommand, limit: usize) -> Result<(u32, u32, Vec<u8>)> {
+    let payload = PAYLOAD
+        .decode(&command.payload)
+        .context("EINVAL:Payload is not valid base64")?;
+    if command.compressed {
+        bail!("EINVAL:Compressed image data is not supported");
+    }
+    
+    let data = match command.medium {
+        Medium::Direct => payload,
+        Medium::File | Medium::TempFile => {
+            let path = String::from_utf8(payload).context("EINVAL:File name is not UTF-8")?;
+            let path = Path::new(&path);
+            if command.medium == Medium::TempFile && !is_temp_file(path) {
+                bail!("EPERM:{} is not a graphics temporary file", path.display());
+            }
+            let data = read_file(path, command.data_offset, command.data_size, limit);
+            if command.medium == Medium::TempFile {
+                let _ = std::fs::remove_file(path);
+            }
+            data?
+        }
+        Medium::SharedMemory => {
+            let name = String::from_utf8(payload).context("EINVAL:Shared memory name is not UTF-8")?;
+            read_shared_memory(&name, command.data_offset, command.data_size, limit)?
+        }
+    };
+    decode(command, data)
+}
+
+/// Temporary files must live in a temporary directory and carry the
+/// protocol's marker in their name, so a program can't have the terminal
+/// delete arbitrary files.
+fn is_temp_file(path: &Path) -> bool {
+    let in_temp_dir = path.parent().is_some_and(|dir| {
+        dir == std::env::temp_dir() || dir == Path::new("/tmp") || dir == Path::new("/dev/shm")
+    });
+    let marked = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().contains("tty-graphics-protocol"));
+    in_temp_dir && marked
+}
+
+fn read_file(path: &Path, offset: usize, size: usize, limit: usize) -> Result<Vec<u8>> {
+    if path.starts_with("/proc") || path.starts_with("/sys") {
+        bail!("EPERM:Refusing to read {}", path.display());
+    }
+    // Opening a FIFO or a terminal device could block the emulator for
+    // good, so only regular files get opened, without blocking in case the
+    // path was swapped in between
+    let metadata = std::fs::metadata(path).with_context(|| format!("ENOENT:Failed to read {}", path.display()))?;
+    if !metadata.is_file() {
+        bail!("EPERM:{} is not a regular file", path.display());
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NONBLOCK);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("EBADF:Failed to open {}", path.display()))?;
+    if !file.metadata()?.is_file() {
+        bail!("EPERM:{} is not a regular file", path.display());
+    }
+    
+    file.seek(SeekFrom::Start(offset as u64))?;
+    let size = if size == 0 { limit } else { size.min(limit) };
+    let mut data = Vec::new();
+    file.take(size as u64 + 1).read_to_end(&mut data)?;
+    if data.len() > limit {
+        bail!("EFBIG:{} exceeds the image cache size", path.display());
+    }
+    Ok(data)
+}
+
+#[cfg(unix)]
+fn read_shared_memory(name: &str, offset: usize, size: usize, limit: usize) -> Result<Vec<u8>> {
+    use std::ffi::CString;
+    
+    let c_name = CString::new(name).context("EINVAL:Shared memory name contains NUL")?;
+    // SAFETY: the descriptor is checked before use and closed below; the
+    // mapping covers exactly the object's size and is unmapped after copying
+    unsafe {
+        let fd = libc::shm_open(c_name.as_ptr(), libc::O_RDONLY, 0);
+        if fd < 0 {
+            let error = std::io::Error::last_os_error();
+            bail!("EBADF:Failed to open shared memory {}: {}", name, error);
+        }
+        libc::shm_unlink(c_name.as_ptr());
+        
+        let mut stat: libc::stat = std::mem::zeroed();
+        let result = if libc::fstat(fd, &mut stat) != 0 {
+            Err(anyhow!("EBADF:Failed to stat shared memory {}", name))
+        } else {
+            let total = stat.st_size as usize;
+            let end = if size == 0 { total } else { offset.saturating_add(size).min(total) };
+            if offset >= end {
+                Err(anyhow!("EINVAL:Offset {} is past the end of shared memory {}", offset, name))
+            } else if end - offset > limit {
+                Err(anyhow!("EFBIG:Shared memory {} exceeds the image cache size", name))
+            } else {
+                let map = libc::mmap(std::ptr::null_mut(), total, libc::PROT_READ, libc::MAP_SHARED, fd, 0);
+                if map == libc::MAP_FAILED {
+                    Err(anyhow!("EBADF:Failed to map shared memory {}", name))
+                } else {
+                    let data = std::slice::from_raw_parts(map as *const u8, total)[offset..end].to_vec();
+                    libc::munmap(map, total);
+                    Ok(data)
+                }
+            }
+        };
+        libc::close(fd);
+        result
+    }
+}
+
+#[cfg(not(unix))]
+fn read_shared_memory(_name: &str, _offset: usize, _size: usize, _limit: usize) -> Result<Vec<u8>> {
+    bail!("EINVAL:Shared memory transmission is not supported on this platform")
+}
+
+fn decode(command: &GraphicsCommand, data: Vec<u8>) -> Result<(u32, u32, Vec<u8>)> {
+    let (width, height) = (command.data_width, command.data_height);
+    let (width, height, pixels) = match command.format {
+        Format::Png => decode_png(&data)?,
+        Format::Rgb | Format::Rgba => {
+            let channels = if command.format == Format::Rgb { 3 } else { 4 };
+            let expected = width as usize * height as usize * channels;
+            if width == 0 || height == 0 {
+                bail!("EINVAL:Raw image data needs its width and height");
+            }
+            if data.len() != expected {
+                bail!("ENODATA:Expected {} bytes of image data, got {}", expected, data.len());
+            }
+            let pixels = if channels == 3 { rgb_to_rgba(&data) } else { data };
+            (width, height, pixels)
+        }
+    };
+    
+    if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        bail!("EINVAL:Image of {}x{} pixels is too large", width, height);
+    }
+    Ok((width, height, pixels))
+}
+
+fn rgb_to_rgba(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xff])
+        .collect()
+}
+
+fn decode_png(data: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().context("EBADPNG:Failed to read PNG header")?;
+    let (width, height) = reader.info().size();
+    if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        bail!("EINVAL:Image of {}x{} pixels is too large", width, height);
+    }
+    
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer).context("EBADPNG:Failed to decode PNG")?;
+    buffer.truncate(frame.buffer_size());
+    let pixels = match frame.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => rgb_to_rgba(&buffer),
+        png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 0xff]).collect(),
+        png::ColorType::Indexed => bail!("EBADPNG:Indexed PNG was not expanded"),
+    };
+    Ok((frame.width, frame.height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn parses_control_data() {
+        let command = GraphicsCommand::parse(b"a=T,f=24,s=2,v=1,i=7,p=3,z=-2,C=1,q=1,m=1;AAAA").unwrap();
+        assert_eq!(command.action, Action::TransmitAndPut);
+        assert_eq!(command.format, Format::Rgb);
+        assert_eq!((command.data_width, command.data_height), (2, 1));
+        assert_eq!((command.image_id, command.placement_id), (7, 3));
+        assert_eq!(command.z_index, -2);
+        assert!(command.keep_cursor && command.more);
+        assert_eq!(command.quiet, 1);
+        assert_eq!(command.payload, b"AAAA");
+        
+        assert_eq!(GraphicsCommand::parse(b"a=d").unwrap().delete, b'a');
+        assert!(GraphicsCommand::parse(b"f=7").is_err());
+        assert!(GraphicsCommand::parse(b"i").is_err());
+        assert_eq!(command.response("OK"), "\x1b_Gi=7,p=3;OK\x1b\\");
+    }
+    
+    #[test]
+    fn loads_raw_pixels_from_payload_and_temp_file() {
+        let rgb = GraphicsCommand {
+            format: Format::Rgb,
+            data_width: 1,
+            data_height: 2,
+            payload: PAYLOAD.encode([1, 2, 3, 4, 5, 6]).into_bytes(),
+            ..GraphicsCommand::default()
+        };
+        assert_eq!(load(&rgb, 1024).unwrap(), (1, 2, vec![1, 2, 3, 255, 4, 5, 6, 255]));
+        
+        let short = GraphicsCommand {
+            data_width: 2,
+            ..rgb.clone()
+        };
+        assert!(format!("{:#}", load(&short, 1024).unwrap_err()).starts_with("ENODATA:"));
+        
+        let path = std::env::temp_dir().join(format!("tty-graphics-protocol-{}", std::process::id()));
+        std::fs::write(&path, [0, 9, 8, 7, 6, 5, 4]).unwrap();
+        let file = GraphicsCommand {
+            medium: Medium::TempFile,
+            data_offset: 1,
+            data_size: 6,
+            payload: PAYLOAD.encode(path.to_str().unwrap()).into_bytes(),
+            ..rgb.clone()
+        };
+        assert_eq!(load(&file, 1024).unwrap().2, [9, 8, 7, 255, 6, 5, 4, 255]);
+        assert!(!path.exists());
+        
+        let outside = GraphicsCommand {
+            payload: PAYLOAD.encode("/etc/hostname").into_bytes(),
+            ..file
+        };
+        assert!(format!("{:#}", load(&outside, 1024).unwrap_err()).starts_with("EPERM:"));
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn refuses_to_open_fifos() {
+        let path = std::env::temp_dir().join(format!("kitty-plus-fifo-{}", std::process::id()));
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        // SAFETY: the path is a valid NUL-terminated string
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        
+        let command = GraphicsCommand {
+            medium: Medium::File,
+            payload: PAYLOAD.encode(path.to_str().unwrap()).into_bytes(),
+            ..GraphicsCommand::default()
+        };
+        let error = format!("{:#}", load(&command, 1024).unwrap_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.starts_with("EPERM:"), "{}", error);
+    }
+    
+    #[test]
+    fn store_stays_within_its_budget() {
+        let mut store = ImageStore::new(100);
+        let placed = store.insert(1, 0, 5, 1, vec![0; 40]).unwrap();
+        store.place(Placement {
+            image_id: placed,
+            id: 0,
+            line: 0,
+            col: 0,
+            rows: 1,
+            cols: 1,
+            z_index: 0,
+            source: (0, 0, 5, 1),
+            offset: (0, 0),
+            alt_screen: false,
+        });
+        store.insert(2, 0, 5, 1, vec![0; 40]).unwrap();
+        
+        // The unplaced image goes first, even though it is newer
+        store.insert(3, 0, 5, 1, vec![0; 40]).unwrap();
+        assert!(store.get(1).is_some() && store.get(2).is_none() && store.get(3).is_some());
+        assert_eq!(store.used(), 80);
+        
+        assert!(store.insert(4, 0, 20, 20, vec![0; 1600]).is_err());
+        let automatic = store.insert(0, 9, 1, 1, vec![0; 4]).unwrap();
+        assert_eq!(automatic, u32::MAX);
+        assert_eq!(store.find_number(9), Some(automatic));
+    }
+}
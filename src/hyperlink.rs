@@ -0,0 +1,143 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::process::Command;
+
+use crate::config::HyperlinkConfig;
+
+/// Compact reference from a cell to an entry in a `HyperlinkTable`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LinkId(NonZeroU32);
+
+/// A link opened with OSC 8.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hyperlink {
+    /// The `id=` parameter, which joins separately printed runs into one link.
+    pub id: Option<String>,
+    pub uri: String,
+}
+
+/// The links referenced by the cells of one terminal.
+#[derive(Debug)]
+pub struct HyperlinkTable {
+    links: HashMap<LinkId, Hyperlink>,
+    /// Links that carry an explicit id, so reopening one reuses its entry.
+    by_id: HashMap<(String, String), LinkId>,
+    next: NonZeroU32,
+}
+
+impl Default for HyperlinkTable {
+    fn default() -> Self {
+        Self {
+            links: HashMap::new(),
+            by_id: HashMap::new(),
+            next: NonZeroU32::MIN,
+        }
+    }
+}
+
+impl HyperlinkTable {
+    /// Registers a link and returns the reference to store in cells. Links
+    /// without an id always get a new entry, so two of them are never
+    /// highlighted together even if they point to the same URI.
+    pub fn insert(&mut self, id: Option<String>, uri: String) -> LinkId {
+        if let Some(id) = &id {
+            if let Some(&link) = self.by_id.get(&(id.clone(), uri.clone())) {
+                return link;
+            }
+        }
+        
+        let link = LinkId(self.next);
+        self.next = self.next.checked_add(1).unwrap_or(NonZeroU32::MIN);
+        if let Some(id) = &id {
+            self.by_id.insert((id.clone(), uri.clone()), link);
+        }
+        self.links.insert(link, Hyperlink { id, uri });
+        link
+    }
+    
+    pub fn get(&self, link: LinkId) -> Option<&Hyperlink> {
+        self.links.get(&link)
+    }
+    
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+    
+    /// Drops every link for which `keep` returns false.
+    pub fn retain(&mut self, mut keep: impl FnMut(LinkId) -> bool) {
+        self.links.retain(|&link, _| keep(link));
+        let links = &self.links;
+        self.by_id.retain(|_, link| links.contains_key(link));
+    }
+}
+
+/// Lowercased scheme of `uri`, if it has a syntactically valid one.
+fn scheme(uri: &str) -> Option<String> {
+    let (scheme, _) = uri.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then(|| scheme.to_ascii_lowercase())
+}
+
+pub fn is_allowed(config: &HyperlinkConfig, uri: &str) -> bool {
+    scheme(uri).is_some_and(|scheme| {
+        config
+            .allowed_schemes
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&scheme))
+    })
+}
+
+/// Hands `uri` to the configured opener command, refusing schemes that are
+/// not on the allowlist. The opener runs in the background.
+pub fn open(config: &HyperlinkConfig, uri: &str) -> Result<()> {
+    if !is_allowed(config, uri) {
+        bail!("Refusing to open {}: scheme not allowed", uri);
+    }
+    
+    let mut child = Command::new(&config.opener)
+        .args(&config.opener_args)
+        .arg(uri)
+        .spawn()
+        .with_context(|| format!("Failed to run {}", config.opener))?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn links_with_an_id_share_an_entry() {
+        let mut table = HyperlinkTable::default();
+        let a = table.insert(Some("1".to_string()), "https://a".to_string());
+        let b = table.insert(Some("1".to_string()), "https://a".to_string());
+        let c = table.insert(None, "https://a".to_string());
+        let d = table.insert(None, "https://a".to_string());
+        
+        assert_eq!(a, b);
+        assert_ne!(c, d);
+        assert_eq!(table.len(), 3);
+        
+        table.retain(|link| link == c);
+        assert_eq!(table.get(c).map(|link| link.uri.as_str()), Some("https://a"));
+        assert_ne!(table.insert(Some("1".to_string()), "https://a".to_string()), a);
+    }
+    
+    #[test]
+    fn only_allowed_schemes_open() {
+        let config = HyperlinkConfig::default();
+        assert!(is_allowed(&config, "https://example.com"));
+        assert!(is_allowed(&config, "FILE:///etc/hosts"));
+        assert!(!is_allowed(&config, "javascript:alert(1)"));
+        assert!(!is_allowed(&config, "/no/scheme"));
+        assert!(open(&config, "ssh://host").is_err());
+    }
+}
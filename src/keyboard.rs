@@ -0,0 +1,643 @@
+/// Progressive enhancement flags of the kitty keyboard protocol, which
+/// programs set with `CSI > flags u` and related sequences.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyboardFlags(u8);
+
+impl KeyboardFlags {
+    pub const DISAMBIGUATE: Self = Self(0b1);
+    pub const REPORT_EVENT_TYPES: Self = Self(0b10);
+    pub const REPORT_ALTERNATE_KEYS: Self = Self(0b100);
+    pub const REPORT_ALL_KEYS: Self = Self(0b1000);
+    pub const REPORT_TEXT: Self = Self(0b10000);
+    
+    /// Keeps only the bits this terminal knows about.
+    pub fn from_bits_truncate(bits: u16) -> Self {
+        Self(bits as u8 & 0b11111)
+    }
+    
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+    
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+    
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+    pub super_key: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Self = Self {
+        shift: false,
+        alt: false,
+        ctrl: false,
+        super_key: false,
+        caps_lock: false,
+        num_lock: false,
+    };
+    pub const SHIFT: Self = Self { shift: true, ..Self::NONE };
+    pub const ALT: Self = Self { alt: true, ..Self::NONE };
+    pub const CTRL: Self = Self { ctrl: true, ..Self::NONE };
+    
+    /// Modifiers that change what a key means rather than which character
+    /// it types.
+    fn has_command(self) -> bool {
+        self.alt || self.ctrl || self.super_key
+    }
+    
+    /// The modifier parameter of CSI sequences: one plus the modifier bits.
+    fn parameter(self, with_locks: bool) -> u32 {
+        let bits = [
+            (self.shift, 1),
+            (self.alt, 2),
+            (self.ctrl, 4),
+            (self.super_key, 8),
+            (self.caps_lock && with_locks, 64),
+            (self.num_lock && with_locks, 128),
+        ];
+        1 + bits.iter().filter(|(set, _)| *set).map(|(_, bit)| bit).sum::<u32>()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyEventKind {
+    #[default]
+    Press,
+    Repeat,
+    Release,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NamedKey {
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Insert,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    CapsLock,
+    ScrollLock,
+    NumLock,
+    PrintScreen,
+    Pause,
+    Menu,
+    /// F1 to F35.
+    F(u8),
+    LeftShift,
+    LeftControl,
+    LeftAlt,
+    LeftSuper,
+    RightShift,
+    RightControl,
+    RightAlt,
+    RightSuper,
+}
+
+impl NamedKey {
+    /// Keys that are only reported with `REPORT_ALL_KEYS`.
+    fn is_modifier_or_lock(self) -> bool {
+        use NamedKey::*;
+        
+        matches!(
+            self,
+            CapsLock
+                | ScrollLock
+                | NumLock
+                | LeftShift
+                | LeftControl
+                | LeftAlt
+                | LeftSuper
+                | RightShift
+                | RightControl
+                | RightAlt
+                | RightSuper
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A key that types a character, identified by that character without
+    /// shift applied.
+    Char(char),
+    Named(NamedKey),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub kind: KeyEventKind,
+    pub modifiers: Modifiers,
+    /// Text the key types with the current modifiers and layout. Never
+    /// contains control characters.
+    pub text: Option<String>,
+    /// The character typed with shift held.
+    pub shifted_key: Option<char>,
+    /// The key in the same position on a US layout.
+    pub base_layout_key: Option<char>,
+}
+
+/// How a named key is numbered in CSI sequences.
+enum Functional {
+    /// `CSI number ~`
+    Tilde(u32),
+    /// `CSI 1 letter`, with the 1 left out when nothing follows it.
+    Letter(char),
+    /// `CSI number u`
+    Code(u32),
+}
+
+fn functional(key: NamedKey) -> Functional {
+    use Functional::*;
+    
+    match key {
+        NamedKey::Escape => Code(27),
+        NamedKey::Enter => Code(13),
+        NamedKey::Tab => Code(9),
+        NamedKey::Backspace => Code(127),
+        NamedKey::Insert => Tilde(2),
+        NamedKey::Delete => Tilde(3),
+        NamedKey::Left => Letter('D'),
+        NamedKey::Right => Letter('C'),
+        NamedKey::Up => Letter('A'),
+        NamedKey::Down => Letter('B'),
+        NamedKey::PageUp => Tilde(5),
+        NamedKey::PageDown => Tilde(6),
+        NamedKey::Home => Letter('H'),
+        NamedKey::End => Letter('F'),
+        NamedKey::CapsLock => Code(57358),
+        NamedKey::ScrollLock => Code(57359),
+        NamedKey::NumLock => Code(57360),
+        NamedKey::PrintScreen => Code(57361),
+        NamedKey::Pause => Code(57362),
+        NamedKey::Menu => Code(57363),
+        NamedKey::F(1) => Letter('P'),
+        NamedKey::F(2) => Letter('Q'),
+        // CSI R would be mistaken for a cursor position report
+        NamedKey::F(3) => Tilde(13),
+        NamedKey::F(4) => Letter('S'),
+        NamedKey::F(n @ 5..=12) => Tilde([15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5]),
+        NamedKey::F(n) => Code(57376 + n.clamp(13, 35) as u32 - 13),
+        NamedKey::LeftShift => Code(57441),
+        NamedKey::LeftControl => Code(57442),
+        NamedKey::LeftAlt => Code(57443),
+        NamedKey::LeftSuper => Code(57444),
+        NamedKey::RightShift => Code(57447),
+        NamedKey::RightControl => Code(57448),
+        NamedKey::RightAlt => Code(57449),
+        NamedKey::RightSuper => Code(57450),
+    }
+}
+
+/// Encodes a key event for the program: the kitty keyboard protocol when
+/// the program enabled any of its flags, xterm's legacy encoding otherwise.
+/// `application_cursor` is DECCKM, which switches unmodified cursor keys
+/// to SS3 sequences.
+pub fn encode(event: &KeyEvent, flags: KeyboardFlags, application_cursor: bool) -> Vec<u8> {
+    if flags.is_empty() {
+        encode_legacy(event, application_cursor)
+    } else {
+        encode_kitty(event, flags, application_cursor)
+    }
+}
+
+fn encode_legacy(event: &KeyEvent, application_cursor: bool) -> Vec<u8> {
+    if event.kind == KeyEventKind::Release {
+        return Vec::new();
+    }
+    
+    let modifiers = event.modifiers;
+    let mut bytes = Vec::new();
+    if modifiers.alt {
+        bytes.push(0x1b);
+    }
+    
+    let named = match event.key {
+        Key::Char(c) => {
+            match (modifiers.ctrl, control_byte(event.shifted_key.filter(|_| modifiers.shift).unwrap_or(c))) {
+                (true, Some(byte)) => bytes.push(byte),
+                _ => match &event.text {
+                    Some(text) => bytes.extend_from_slice(text.as_bytes()),
+                    None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                },
+            }
+            return bytes;
+        }
+        Key::Named(named) => named,
+    };
+    
+    match named {
+        NamedKey::Enter => bytes.push(b'\r'),
+        NamedKey::Tab if modifiers.shift => bytes.extend_from_slice(b"\x1b[Z"),
+        NamedKey::Tab => bytes.push(b'\t'),
+        NamedKey::Backspace => bytes.push(if modifiers.ctrl { 0x08 } else { 0x7f }),
+        NamedKey::Escape => bytes.push(0x1b),
+        _ => {
+            let parameter = modifiers.parameter(false);
+            bytes.clear();
+            let sequence = match (functional(named), parameter) {
+                (Functional::Letter(letter), 1) => {
+                    let ss3 = matches!(named, NamedKey::F(_)) || application_cursor;
+                    format!("{}{}", if ss3 { "\x1bO" } else { "\x1b[" }, letter)
+                }
+                (Functional::Letter(letter), _) => format!("\x1b[1;{}{}", parameter, letter),
+                // xterm's F3 is SS3 R; only the kitty protocol needs to avoid it
+                (Functional::Tilde(13), 1) => "\x1bOR".to_string(),
+                (Functional::Tilde(13), _) => format!("\x1b[1;{}R", parameter),
+                (Functional::Tilde(number), 1) => format!("\x1b[{}~", number),
+                (Functional::Tilde(number), _) => format!("\x1b[{};{}~", number, parameter),
+                // Keys xterm has no encoding for
+                (Functional::Code(_), _) => String::new(),
+            };
+            bytes.extend_from_slice(sequence.as_bytes());
+        }
+    }
+    bytes
+}
+
+/// The byte xterm sends for ctrl and a character, if there is one.
+fn control_byte(c: char) -> Option<u8> {
+    Some(match c {
+        'a'..='z' => c as u8 - b'a' + 1,
+        'A'..='Z' => c as u8 - b'A' + 1,
+        ' ' | '@' | '2' => 0,
+        '[' | '3' => 0x1b,
+        '\\' | '4' => 0x1c,
+        ']' | '5' => 0x1d,
+        '^' | '6' => 0x1e,
+        '_' | '/' | '-' | '7' => 0x1f,
+        '?' | '8' => 0x7f,
+        _ => return None,
+    })
+}
+
+fn encode_kitty(event: &KeyEvent, flags: KeyboardFlags, application_cursor: bool) -> Vec<u8> {
+    let report_all = flags.contains(KeyboardFlags::REPORT_ALL_KEYS);
+    let report_types = flags.contains(KeyboardFlags::REPORT_EVENT_TYPES);
+    let (kind, modifiers) = (event.kind, event.modifiers);
+    if kind == KeyEventKind::Release && !report_types {
+        return Vec::new();
+    }
+    
+    if !report_all {
+        match event.key {
+            Key::Named(named) if named.is_modifier_or_lock() => return Vec::new(),
+            // Enter, Tab and Backspace keep their legacy bytes so the shell
+            // stays usable if a program exits without restoring the flags
+            Key::Named(NamedKey::Enter | NamedKey::Tab | NamedKey::Backspace)
+                if !modifiers.has_command() && !modifiers.shift =>
+            {
+                return match kind {
+                    KeyEventKind::Release => Vec::new(),
+                    _ => encode_legacy(event, application_cursor),
+                };
+            }
+            Key::Named(named)
+                if named != NamedKey::Escape
+                    && modifiers.parameter(false) == 1
+                    && kind == KeyEventKind::Press =>
+            {
+                return encode_legacy(event, application_cursor);
+            }
+            Key::Char(_) if !modifiers.has_command() && kind != KeyEventKind::Release => {
+                if let Some(text) = &event.text {
+                    return text.as_bytes().to_vec();
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    let (number, terminator) = match event.key {
+        Key::Char(c) => (c as u32, 'u'),
+        Key::Named(named) => match functional(named) {
+            Functional::Tilde(number) => (number, '~'),
+            Functional::Letter(letter) => (1, letter),
+            Functional::Code(number) => (number, 'u'),
+        },
+    };
+    
+    let mut key = number.to_string();
+    if flags.contains(KeyboardFlags::REPORT_ALTERNATE_KEYS) && matches!(event.key, Key::Char(_)) {
+        let shifted = event
+            .shifted_key
+            .filter(|&shifted| modifiers.shift && shifted as u32 != number);
+        let base = event.base_layout_key.filter(|&base| base as u32 != number);
+        if shifted.is_some() || base.is_some() {
+            key += &format!(":{}", shifted.map(|c| (c as u32).to_string()).unwrap_or_default());
+        }
+        if let Some(base) = base {
+            key += &format!(":{}", base as u32);
+        }
+    }
+    
+    let parameter = modifiers.parameter(report_all);
+    let event_type = match kind {
+        KeyEventKind::Press => None,
+        _ if !report_types => None,
+        KeyEventKind::Repeat => Some(2),
+        KeyEventKind::Release => Some(3),
+    };
+    let text = event
+        .text
+        .as_deref()
+        .filter(|_| report_all && flags.contains(KeyboardFlags::REPORT_TEXT) && kind != KeyEventKind::Release)
+        .map(|text| text.chars().map(|c| (c as u32).to_string()).collect::<Vec<_>>().join(":"));
+    
+    let mut fields = Vec::new();
+    if parameter != 1 || event_type.is_some() || text.is_some() {
+        let mut field = parameter.to_string();
+        if let Some(event_type) = event_type {
+            field += &format!(":{}", event_type);
+        }
+        fields.push(field);
+    }
+    fields.extend(text);
+    
+    if fields.is_empty() && terminator != 'u' && terminator != '~' {
+        return format!("\x1b[{}", terminator).into_bytes();
+    }
+    let mut sequence = format!("\x1b[{}", key);
+    for field in fields {
+        sequence += ";";
+        sequence += &field;
+    }
+    sequence.push(terminator);
+    sequence.into_bytes()
+}
+
+/// Translates a winit key event. Returns `None` for keys the terminal
+/// doesn't encode, such as media keys.
+pub fn from_winit(event: &winit::event::KeyEvent, modifiers: winit::keyboard::ModifiersState) -> Option<KeyEvent> {
+    use winit::event::ElementState;
+    use winit::keyboard::{Key as WinitKey, KeyLocation, NamedKey as Winit, PhysicalKey};
+    use winit::platform::modifier_supplement::KeyEventExtModifierSupplement;
+    
+    let right = event.location == KeyLocation::Right;
+    let key = match event.key_without_modifiers() {
+        WinitKey::Character(text) => Key::Char(text.chars().next()?.to_lowercase().next()?),
+        WinitKey::Named(named) => match named {
+            Winit::Space => Key::Char(' '),
+            Winit::Escape => Key::Named(NamedKey::Escape),
+            Winit::Enter => Key::Named(NamedKey::Enter),
+            Winit::Tab => Key::Named(NamedKey::Tab),
+            Winit::Backspace => Key::Named(NamedKey::Backspace),
+            Winit::Insert => Key::Named(NamedKey::Insert),
+            Winit::Delete => Key::Named(NamedKey::Delete),
+            Winit::ArrowLeft => Key::Named(NamedKey::Left),
+            Winit::ArrowRight => Key::Named(NamedKey::Right),
+            Winit::ArrowUp => Key::Named(NamedKey::Up),
+            Winit::ArrowDown => Key::Named(NamedKey::Down),
+            Winit::PageUp => Key::Named(NamedKey::PageUp),
+            Winit::PageDown => Key::Named(NamedKey::PageDown),
+            Winit::Home => Key::Named(NamedKey::Home),
+            Winit::End => Key::Named(NamedKey::End),
+            Winit::CapsLock => Key::Named(NamedKey::CapsLock),
+            Winit::ScrollLock => Key::Named(NamedKey::ScrollLock),
+            Winit::NumLock => Key::Named(NamedKey::NumLock),
+            Winit::PrintScreen => Key::Named(NamedKey::PrintScreen),
+            Winit::Pause => Key::Named(NamedKey::Pause),
+            Winit::ContextMenu => Key::Named(NamedKey::Menu),
+            Winit::Shift if right => Key::Named(NamedKey::RightShift),
+            Winit::Shift => Key::Named(NamedKey::LeftShift),
+            Winit::Control if right => Key::Named(NamedKey::RightControl),
+            Winit::Control => Key::Named(NamedKey::LeftControl),
+            Winit::Alt if right => Key::Named(NamedKey::RightAlt),
+            Winit::AltGraph => Key::Named(NamedKey::RightAlt),
+            Winit::Alt => Key::Named(NamedKey::LeftAlt),
+            Winit::Super | Winit::Meta if right => Key::Named(NamedKey::RightSuper),
+            Winit::Super | Winit::Meta => Key::Named(NamedKey::LeftSuper),
+            Winit::F1 => Key::Named(NamedKey::F(1)),
+            Winit::F2 => Key::Named(NamedKey::F(2)),
+            Winit::F3 => Key::Named(NamedKey::F(3)),
+            Winit::F4 => Key::Named(NamedKey::F(4)),
+            Winit::F5 => Key::Named(NamedKey::F(5)),
+            Winit::F6 => Key::Named(NamedKey::F(6)),
+            Winit::F7 => Key::Named(NamedKey::F(7)),
+            Winit::F8 => Key::Named(NamedKey::F(8)),
+            Winit::F9 => Key::Named(NamedKey::F(9)),
+            Winit::F10 => Key::Named(NamedKey::F(10)),
+            Winit::F11 => Key::Named(NamedKey::F(11)),
+            Winit::F12 => Key::Named(NamedKey::F(12)),
+            Winit::F13 => Key::Named(NamedKey::F(13)),
+            Winit::F14 => Key::Named(NamedKey::F(14)),
+            Winit::F15 => Key::Named(NamedKey::F(15)),
+            Winit::F16 => Key::Named(NamedKey::F(16)),
+            Winit::F17 => Key::Named(NamedKey::F(17)),
+            Winit::F18 => Key::Named(NamedKey::F(18)),
+            Winit::F19 => Key::Named(NamedKey::F(19)),
+            Winit::F20 => Key::Named(NamedKey::F(20)),
+            Winit::F21 => Key::Named(NamedKey::F(21)),
+            Winit::F22 => Key::Named(NamedKey::F(22)),
+            Winit::F23 => Key::Named(NamedKey::F(23)),
+            Winit::F24 => Key::Named(NamedKey::F(24)),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    
+    let shifted_key = match &event.logical_key {
+        WinitKey::Character(text) if modifiers.shift_key() => text.chars().next(),
+        _ => None,
+    };
+    let base_layout_key = match event.physical_key {
+        PhysicalKey::Code(code) => us_layout_key(code),
+        PhysicalKey::Unidentified(_) => None,
+    };
+    let kind = match (event.state, event.repeat) {
+        (ElementState::Released, _) => KeyEventKind::Release,
+        (ElementState::Pressed, true) => KeyEventKind::Repeat,
+        (ElementState::Pressed, false) => KeyEventKind::Press,
+    };
+    
+    Some(KeyEvent {
+        key,
+        kind,
+        modifiers: modifiers_from_winit(modifiers),
+        text: event
+            .text
+            .as_ref()
+            .map(|text| text.to_string())
+            .filter(|text| !text.chars().any(char::is_control)),
+        shifted_key,
+        base_layout_key,
+    })
+}
+
+pub fn modifiers_from_winit(modifiers: winit::keyboard::ModifiersState) -> Modifiers {
+    Modifiers {
+        shift: modifiers.shift_key(),
+        alt: modifiers.alt_key(),
+        ctrl: modifiers.control_key(),
+        super_key: modifiers.super_key(),
+        ..Modifiers::NONE
+    }
+}
+
+/// The unshifted character of a physical key on a US layout.
+fn us_layout_key(code: winit::keyboard::KeyCode) -> Option<char> {
+    use winit::keyboard::KeyCode::*;
+    
+    let letters = [
+        KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM, KeyN, KeyO, KeyP, KeyQ,
+        KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ,
+    ];
+    if let Some(index) = letters.iter().position(|&letter| letter == code) {
+        return Some((b'a' + index as u8) as char);
+    }
+    let digits = [Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9];
+    if let Some(index) = digits.iter().position(|&digit| digit == code) {
+        return Some((b'0' + index as u8) as char);
+    }
+    Some(match code {
+        Backquote => '`',
+        Minus => '-',
+        Equal => '=',
+        BracketLeft => '[',
+        BracketRight => ']',
+        Backslash => '\\',
+        Semicolon => ';',
+        Quote => '\'',
+        Comma => ',',
+        Period => '.',
+        Slash => '/',
+        Space => ' ',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn key(key: Key, modifiers: Modifiers) -> KeyEvent {
+        let text = match key {
+            Key::Char(c) if modifiers.shift => Some(c.to_ascii_uppercase().to_string()),
+            Key::Char(c) => Some(c.to_string()),
+            Key::Named(_) => None,
+        };
+        KeyEvent {
+            key,
+            kind: KeyEventKind::Press,
+            modifiers,
+            text,
+            shifted_key: None,
+            base_layout_key: None,
+        }
+    }
+    
+    fn legacy(event: &KeyEvent) -> String {
+        String::from_utf8(encode(event, KeyboardFlags::default(), false)).unwrap()
+    }
+    
+    fn kitty(event: &KeyEvent, flags: u16) -> String {
+        String::from_utf8(encode(event, KeyboardFlags::from_bits_truncate(flags), false)).unwrap()
+    }
+    
+    #[test]
+    fn legacy_encoding_matches_xterm() {
+        let ctrl_alt = Modifiers { ctrl: true, alt: true, ..Modifiers::NONE };
+        let ctrl_shift = Modifiers { ctrl: true, shift: true, ..Modifiers::NONE };
+        
+        assert_eq!(legacy(&key(Key::Char('a'), Modifiers::NONE)), "a");
+        assert_eq!(legacy(&key(Key::Char('a'), Modifiers::SHIFT)), "A");
+        assert_eq!(legacy(&key(Key::Char('a'), Modifiers::CTRL)), "\x01");
+        assert_eq!(legacy(&key(Key::Char('a'), Modifiers::ALT)), "\x1ba");
+        assert_eq!(legacy(&key(Key::Char('a'), ctrl_alt)), "\x1b\x01");
+        assert_eq!(legacy(&key(Key::Char(' '), Modifiers::CTRL)), "\0");
+        assert_eq!(legacy(&key(Key::Char('['), Modifiers::CTRL)), "\x1b");
+        
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Enter), Modifiers::NONE)), "\r");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Tab), Modifiers::SHIFT)), "\x1b[Z");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Backspace), Modifiers::NONE)), "\x7f");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Backspace), Modifiers::ALT)), "\x1b\x7f");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Up), Modifiers::NONE)), "\x1b[A");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Up), ctrl_shift)), "\x1b[1;6A");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Left), Modifiers::ALT)), "\x1b[1;3D");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::Delete), Modifiers::CTRL)), "\x1b[3;5~");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::F(1)), Modifiers::NONE)), "\x1bOP");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::F(3)), Modifiers::SHIFT)), "\x1b[1;2R");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::F(5)), Modifiers::NONE)), "\x1b[15~");
+        assert_eq!(legacy(&key(Key::Named(NamedKey::LeftShift), Modifiers::SHIFT)), "");
+        
+        let up = key(Key::Named(NamedKey::Up), Modifiers::NONE);
+        assert_eq!(encode(&up, KeyboardFlags::default(), true), b"\x1bOA");
+        let release = KeyEvent { kind: KeyEventKind::Release, ..key(Key::Char('a'), Modifiers::NONE) };
+        assert_eq!(legacy(&release), "");
+    }
+    
+    #[test]
+    fn disambiguate_reports_ambiguous_keys_as_csi_u() {
+        assert_eq!(kitty(&key(Key::Char('a'), Modifiers::NONE), 1), "a");
+        assert_eq!(kitty(&key(Key::Char('a'), Modifiers::SHIFT), 1), "A");
+        assert_eq!(kitty(&key(Key::Char('a'), Modifiers::CTRL), 1), "\x1b[97;5u");
+        assert_eq!(kitty(&key(Key::Char('a'), Modifiers::ALT), 1), "\x1b[97;3u");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::Escape), Modifiers::NONE), 1), "\x1b[27u");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::Enter), Modifiers::NONE), 1), "\r");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::Enter), Modifiers::CTRL), 1), "\x1b[13;5u");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::Up), Modifiers::NONE), 1), "\x1b[A");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::Up), Modifiers::CTRL), 1), "\x1b[1;5A");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::F(3)), Modifiers::CTRL), 1), "\x1b[13;5~");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::F(13)), Modifiers::CTRL), 1), "\x1b[57376;5u");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::LeftShift), Modifiers::SHIFT), 1), "");
+    }
+    
+    #[test]
+    fn event_types_alternate_keys_and_text() {
+        let release = KeyEvent { kind: KeyEventKind::Release, ..key(Key::Char('a'), Modifiers::NONE) };
+        let repeat = KeyEvent { kind: KeyEventKind::Repeat, ..key(Key::Named(NamedKey::Up), Modifiers::NONE) };
+        assert_eq!(kitty(&release, 1), "");
+        assert_eq!(kitty(&release, 0b11), "\x1b[97;1:3u");
+        assert_eq!(kitty(&repeat, 0b11), "\x1b[1;1:2A");
+        
+        let shifted = KeyEvent {
+            shifted_key: Some('A'),
+            base_layout_key: Some('q'),
+            ..key(Key::Char('a'), Modifiers { shift: true, ctrl: true, ..Modifiers::NONE })
+        };
+        assert_eq!(kitty(&shifted, 0b101), "\x1b[97:65:113;6u");
+        
+        let unshifted = KeyEvent { base_layout_key: Some('a'), ..key(Key::Char('a'), Modifiers::CTRL) };
+        assert_eq!(kitty(&unshifted, 0b101), "\x1b[97;5u");
+        
+        assert_eq!(kitty(&key(Key::Char('a'), Modifiers::NONE), 0b1000), "\x1b[97u");
+        assert_eq!(kitty(&key(Key::Char('a'), Modifiers::SHIFT), 0b11000), "\x1b[97;2;65u");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::Enter), Modifiers::NONE), 0b1000), "\x1b[13u");
+        assert_eq!(kitty(&key(Key::Named(NamedKey::LeftShift), Modifiers::SHIFT), 0b1000), "\x1b[57441;2u");
+        
+        let caps = KeyEvent {
+            text: Some("A".to_string()),
+            ..key(Key::Char('a'), Modifiers { caps_lock: true, ..Modifiers::NONE })
+        };
+        assert_eq!(kitty(&caps, 1), "A");
+        assert_eq!(kitty(&caps, 0b1000), "\x1b[97;65u");
+    }
+}
@@ -0,0 +1,703 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifies a pane across all tabs of a window.
+pub type PaneId = usize;
+
+/// A rectangle of terminal cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub cols: usize,
+    pub rows: usize,
+}
+
+/// How a split arranges its two halves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitDirection {
+    /// Side by side, with a vertical divider between them.
+    Horizontal,
+    /// Stacked on top of each other.
+    Vertical,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Algorithms for arranging all panes of a tab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutKind {
+    /// Panes placed by the user's own splits.
+    Splits,
+    /// Main panes in full-height columns, the others stacked beside them.
+    Tall,
+    /// Main panes in full-width rows, the others side by side below them.
+    Fat,
+    /// Panes in rows and columns, as close to a square grid as possible.
+    Grid,
+    /// Only the focused pane, using the whole tab.
+    Stack,
+    /// All panes side by side.
+    Horizontal,
+}
+
+/// Options for the layouts that give main panes a larger share of the tab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MainPaneOptions {
+    /// Percentage of the tab given to the main panes, from 10 to 90.
+    pub bias: u8,
+    /// How many of the first panes are main panes.
+    pub full_size: usize,
+    /// Put the main panes on the right (tall) or at the bottom (fat).
+    pub mirrored: bool,
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf(PaneId),
+    Split {
+        direction: SplitDirection,
+        /// Share of the space given to `first`, between 0 and 1.
+        ratio: f32,
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+/// The binary tree of splits that arranges the panes of one tab.
+#[derive(Debug)]
+pub struct PaneTree {
+    root: Node,
+    focused: PaneId,
+    zoomed: bool,
+    kind: LayoutKind,
+    options: MainPaneOptions,
+}
+
+const MIN_RATIO: f32 = 0.1;
+const MAX_RATIO: f32 = 0.9;
+const MIN_BIAS: u8 = 10;
+const MAX_BIAS: u8 = 90;
+
+impl LayoutKind {
+    pub const ALL: [LayoutKind; 6] = [
+        LayoutKind::Splits,
+        LayoutKind::Tall,
+        LayoutKind::Fat,
+        LayoutKind::Grid,
+        LayoutKind::Stack,
+        LayoutKind::Horizontal,
+    ];
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            LayoutKind::Splits => "Splits",
+            LayoutKind::Tall => "Tall",
+            LayoutKind::Fat => "Fat",
+            LayoutKind::Grid => "Grid",
+            LayoutKind::Stack => "Stack",
+            LayoutKind::Horizontal => "Horizontal",
+        }
+    }
+}
+
+impl Default for MainPaneOptions {
+    fn default() -> Self {
+        Self {
+            bias: 50,
+            full_size: 1,
+            mirrored: false,
+        }
+    }
+}
+
+impl SplitDirection {
+    fn perpendicular(self) -> Self {
+        match self {
+            SplitDirection::Horizontal => SplitDirection::Vertical,
+            SplitDirection::Vertical => SplitDirection::Horizontal,
+        }
+    }
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, cols: usize, rows: usize) -> Self {
+        Self { x, y, cols, rows }
+    }
+    
+    /// Splits the rectangle in two, giving `ratio` of it to the first half.
+    /// Both halves keep at least one cell when there is room for it.
+    pub fn split(&self, direction: SplitDirection, ratio: f32) -> (Rect, Rect) {
+        let total = match direction {
+            SplitDirection::Horizontal => self.cols,
+            SplitDirection::Vertical => self.rows,
+        };
+        let first = if total < 2 {
+            total
+        } else {
+            ((total as f32 * ratio).round() as usize).clamp(1, total - 1)
+        };
+        
+        match direction {
+            SplitDirection::Horizontal => (
+                Rect::new(self.x, self.y, first, self.rows),
+                Rect::new(self.x + first, self.y, self.cols - first, self.rows),
+            ),
+            SplitDirection::Vertical => (
+                Rect::new(self.x, self.y, self.cols, first),
+                Rect::new(self.x, self.y + first, self.cols, self.rows - first),
+            ),
+        }
+    }
+    
+    /// Divides the rectangle into `count` equal parts, handing any leftover
+    /// cells to the first ones. Parts past the rectangle's size are empty,
+    /// which `PaneTree::split` refuses to create.
+    pub fn divide(&self, direction: SplitDirection, count: usize) -> Vec<Rect> {
+        let total = match direction {
+            SplitDirection::Horizontal => self.cols,
+            SplitDirection::Vertical => self.rows,
+        };
+        let count = count.max(1);
+        let mut offset = 0;
+        
+        (0..count)
+            .map(|i| {
+                let size = total / count + usize::from(i < total % count);
+                let rect = match direction {
+                    SplitDirection::Horizontal => Rect::new(self.x + offset, self.y, size, self.rows),
+                    SplitDirection::Vertical => Rect::new(self.x, self.y + offset, self.cols, size),
+                };
+                offset += size;
+                rect
+            })
+            .collect()
+    }
+}
+
+impl Node {
+    fn first_leaf(&self) -> PaneId {
+        match self {
+            Node::Leaf(id) => *id,
+            Node::Split { first, .. } => first.first_leaf(),
+        }
+    }
+    
+    fn contains(&self, pane: PaneId) -> bool {
+        match self {
+            Node::Leaf(id) => *id == pane,
+            Node::Split { first, second, .. } => first.contains(pane) || second.contains(pane),
+        }
+    }
+    
+    fn collect(&self, panes: &mut Vec<PaneId>) {
+        match self {
+            Node::Leaf(id) => panes.push(*id),
+            Node::Split { first, second, .. } => {
+                first.collect(panes);
+                second.collect(panes);
+            }
+        }
+    }
+    
+    fn layout(&self, area: Rect, out: &mut Vec<(PaneId, Rect)>) {
+        match self {
+            Node::Leaf(id) => out.push((*id, area)),
+            Node::Split { direction, ratio, first, second } => {
+                let (a, b) = area.split(*direction, *ratio);
+                first.layout(a, out);
+                second.layout(b, out);
+            }
+        }
+    }
+    
+    /// Replaces the leaf `target` with a split holding it and `new_pane`.
+    fn split(&mut self, target: PaneId, direction: SplitDirection, new_pane: PaneId) -> bool {
+        match self {
+            Node::Leaf(id) if *id == target => {
+                *self = Node::Split {
+                    direction,
+                    ratio: 0.5,
+                    first: Box::new(Node::Leaf(target)),
+                    second: Box::new(Node::Leaf(new_pane)),
+                };
+                true
+            }
+            Node::Leaf(_) => false,
+            Node::Split { first, second, .. } => {
+                first.split(target, direction, new_pane) || second.split(target, direction, new_pane)
+            }
+        }
+    }
+    
+    /// Removes the leaf `target`, letting its sibling take the parent's
+    /// place. Returns the pane that should take focus.
+    fn remove(&mut self, target: PaneId) -> Option<PaneId> {
+        let Node::Split { first, second, .. } = self else {
+            return None;
+        };
+        
+        let survivor = if matches!(**first, Node::Leaf(id) if id == target) {
+            std::mem::replace(&mut **second, Node::Leaf(target))
+        } else if matches!(**second, Node::Leaf(id) if id == target) {
+            std::mem::replace(&mut **first, Node::Leaf(target))
+        } else {
+            return first.remove(target).or_else(|| second.remove(target));
+        };
+        
+        let focus = survivor.first_leaf();
+        *self = survivor;
+        Some(focus)
+    }
+    
+    /// Moves the divider of the innermost `direction` split around `target`
+    /// so that `target` grows by `delta` (or shrinks, when negative).
+    fn resize(&mut self, target: PaneId, direction: SplitDirection, delta: f32) -> bool {
+        let Node::Split { direction: own, ratio, first, second } = self else {
+            return false;
+        };
+        
+        let in_first = first.contains(target);
+        let child = if in_first { first } else { second };
+        if !child.contains(target) {
+            return false;
+        }
+        if child.resize(target, direction, delta) {
+            return true;
+        }
+        if *own != direction {
+            return false;
+        }
+        
+        let delta = if in_first { delta } else { -delta };
+        *ratio = (*ratio + delta).clamp(MIN_RATIO, MAX_RATIO);
+        true
+    }
+}
+
+impl PaneTree {
+    pub fn new(pane: PaneId) -> Self {
+        Self {
+            root: Node::Leaf(pane),
+            focused: pane,
+            zoomed: false,
+            kind: LayoutKind::Splits,
+            options: MainPaneOptions::default(),
+        }
+    }
+    
+    pub fn kind(&self) -> LayoutKind {
+        self.kind
+    }
+    
+    /// Switches to another layout algorithm. The split tree is kept, so going
+    /// back to `Splits` restores the user's arrangement.
+    pub fn set_kind(&mut self, kind: LayoutKind, options: MainPaneOptions) {
+        self.kind = kind;
+        self.options = options;
+        self.zoomed = false;
+    }
+    
+    pub fn focused(&self) -> PaneId {
+        self.focused
+    }
+    
+    pub fn is_zoomed(&self) -> bool {
+        self.zoomed
+    }
+    
+    /// All panes, in reading order of the tree.
+    pub fn panes(&self) -> Vec<PaneId> {
+        let mut panes = Vec::new();
+        self.root.collect(&mut panes);
+        panes
+    }
+    
+    pub fn contains(&self, pane: PaneId) -> bool {
+        self.root.contains(pane)
+    }
+    
+    /// Splits the focused pane, placing `new_pane` after it, and focuses the
+    /// new pane. Returns `false` without splitting when laying out `area`
+    /// would leave some pane without a single cell.
+    pub fn split(&mut self, direction: SplitDirection, new_pane: PaneId, area: Rect) -> bool {
+        if !self.root.split(self.focused, direction, new_pane) {
+            return false;
+        }
+        if self.arrange(area).iter().any(|(_, rect)| rect.cols == 0 || rect.rows == 0) {
+            self.root.remove(new_pane);
+            return false;
+        }
+        self.focused = new_pane;
+        self.zoomed = false;
+        true
+    }
+    
+    /// Removes a pane from the tree. Returns `false` when it is the last
+    /// pane, which can't be removed.
+    pub fn close(&mut self, pane: PaneId) -> bool {
+        match self.root.remove(pane) {
+            Some(next) => {
+                if self.focused == pane {
+                    self.focused = next;
+                    self.zoomed = false;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    
+    pub fn focus(&mut self, pane: PaneId) {
+        if self.contains(pane) {
+            self.focused = pane;
+        }
+    }
+    
+    /// Focuses the closest pane in `direction` from the focused one within
+    /// `area`, preferring the one that overlaps it the most.
+    pub fn focus_direction(&mut self, direction: Direction, area: Rect) -> bool {
+        if self.kind == LayoutKind::Stack {
+            return self.focus_adjacent(matches!(direction, Direction::Right | Direction::Down));
+        }
+        
+        let layout = self.arrange(area);
+        let Some(&(_, current)) = layout.iter().find(|(id, _)| *id == self.focused) else {
+            return false;
+        };
+        
+        let overlap = |a0: usize, a1: usize, b0: usize, b1: usize| a1.min(b1).saturating_sub(a0.max(b0));
+        let best = layout
+            .iter()
+            .filter(|(id, _)| *id != self.focused)
+            .filter_map(|(id, rect)| {
+                let (distance, shared) = match direction {
+                    Direction::Left if rect.x + rect.cols <= current.x => (
+                        current.x - (rect.x + rect.cols),
+                        overlap(rect.y, rect.y + rect.rows, current.y, current.y + current.rows),
+                    ),
+                    Direction::Right if rect.x >= current.x + current.cols => (
+                        rect.x - (current.x + current.cols),
+                        overlap(rect.y, rect.y + rect.rows, current.y, current.y + current.rows),
+                    ),
+                    Direction::Up if rect.y + rect.rows <= current.y => (
+                        current.y - (rect.y + rect.rows),
+                        overlap(rect.x, rect.x + rect.cols, current.x, current.x + current.cols),
+                    ),
+                    Direction::Down if rect.y >= current.y + current.rows => (
+                        rect.y - (current.y + current.rows),
+                        overlap(rect.x, rect.x + rect.cols, current.x, current.x + current.cols),
+                    ),
+                    _ => return None,
+                };
+                (shared > 0).then_some((distance, std::cmp::Reverse(shared), *id))
+            })
+            .min();
+        
+        match best {
+            Some((_, _, id)) => {
+                self.focused = id;
+                self.zoomed = false;
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// The stack layout has no geometry to move through, so focus steps
+    /// through the panes in order instead.
+    fn focus_adjacent(&mut self, forward: bool) -> bool {
+        let panes = self.panes();
+        let Some(index) = panes.iter().position(|&id| id == self.focused) else {
+            return false;
+        };
+        let next = if forward {
+            panes.get(index + 1)
+        } else {
+            index.checked_sub(1).and_then(|i| panes.get(i))
+        };
+        
+        match next {
+            Some(&id) => {
+                self.focused = id;
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Grows (or, with a negative `delta`, shrinks) the focused pane along
+    /// `direction`. With splits this moves the divider of its innermost
+    /// matching split; in tall and fat layouts it changes the main bias.
+    pub fn resize(&mut self, direction: SplitDirection, delta: f32) -> bool {
+        let axis = match self.kind {
+            LayoutKind::Splits => return self.root.resize(self.focused, direction, delta),
+            LayoutKind::Tall => SplitDirection::Horizontal,
+            LayoutKind::Fat => SplitDirection::Vertical,
+            _ => return false,
+        };
+        if direction != axis {
+            return false;
+        }
+        
+        let index = self.panes().iter().position(|&id| id == self.focused).unwrap_or(0);
+        let is_main = index < self.options.full_size.max(1);
+        let delta = if is_main { delta } else { -delta };
+        let bias = (self.options.bias as f32 + delta * 100.0).round();
+        let bias = bias.clamp(MIN_BIAS as f32, MAX_BIAS as f32) as u8;
+        let changed = bias != self.options.bias;
+        self.options.bias = bias;
+        changed
+    }
+    
+    pub fn toggle_zoom(&mut self) {
+        self.zoomed = !self.zoomed && matches!(self.root, Node::Split { .. });
+    }
+    
+    /// Cell rectangles of the visible panes. A zoomed pane gets all of
+    /// `area` and the others are hidden.
+    pub fn layout(&self, area: Rect) -> Vec<(PaneId, Rect)> {
+        if self.zoomed {
+            return vec![(self.focused, area)];
+        }
+        self.arrange(area)
+    }
+    
+    fn arrange(&self, area: Rect) -> Vec<(PaneId, Rect)> {
+        let panes = self.panes();
+        match self.kind {
+            LayoutKind::Splits => {
+                let mut layout = Vec::new();
+                self.root.layout(area, &mut layout);
+                layout
+            }
+            LayoutKind::Tall => main_pane_layout(&panes, area, SplitDirection::Horizontal, self.options),
+            LayoutKind::Fat => main_pane_layout(&panes, area, SplitDirection::Vertical, self.options),
+            LayoutKind::Grid => grid_layout(&panes, area),
+            LayoutKind::Stack => vec![(self.focused, area)],
+            LayoutKind::Horizontal => {
+                let rects = area.divide(SplitDirection::Horizontal, panes.len());
+                panes.into_iter().zip(rects).collect()
+            }
+        }
+    }
+}
+
+/// Places the main panes along `axis` in `options.bias` of the area and
+/// divides the rest of it among the other panes the other way.
+fn main_pane_layout(
+    panes: &[PaneId],
+    area: Rect,
+    axis: SplitDirection,
+    options: MainPaneOptions,
+) -> Vec<(PaneId, Rect)> {
+    let main = options.full_size.clamp(1, panes.len());
+    if main == panes.len() {
+        return panes.iter().copied().zip(area.divide(axis, main)).collect();
+    }
+    
+    let bias = options.bias.clamp(MIN_BIAS, MAX_BIAS) as f32 / 100.0;
+    let (main_area, rest_area) = if options.mirrored {
+        let (rest, main) = area.split(axis, 1.0 - bias);
+        (main, rest)
+    } else {
+        area.split(axis, bias)
+    };
+    
+    let rects = main_area
+        .divide(axis, main)
+        .into_iter()
+        .chain(rest_area.divide(axis.perpendicular(), panes.len() - main));
+    panes.iter().copied().zip(rects).collect()
+}
+
+/// Fills rows of up to ceil(sqrt(n)) panes; a short last row stretches its
+/// panes to the full width.
+fn grid_layout(panes: &[PaneId], area: Rect) -> Vec<(PaneId, Rect)> {
+    let columns = (panes.len() as f64).sqrt().ceil() as usize;
+    let rows = panes.len().div_ceil(columns);
+    
+    let rects = area
+        .divide(SplitDirection::Vertical, rows)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(row, rect)| rect.divide(SplitDirection::Horizontal, columns.min(panes.len() - row * columns)));
+    panes.iter().copied().zip(rects).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const AREA: Rect = Rect { x: 0, y: 0, cols: 80, rows: 24 };
+    
+    #[test]
+    fn split_divides_area() {
+        let mut tree = PaneTree::new(1);
+        tree.split(SplitDirection::Horizontal, 2, AREA);
+        tree.split(SplitDirection::Vertical, 3, AREA);
+        
+        assert_eq!(tree.focused(), 3);
+        assert_eq!(
+            tree.layout(AREA),
+            vec![
+                (1, Rect::new(0, 0, 40, 24)),
+                (2, Rect::new(40, 0, 40, 12)),
+                (3, Rect::new(40, 12, 40, 12)),
+            ]
+        );
+    }
+    
+    #[test]
+    fn close_promotes_sibling() {
+        let mut tree = PaneTree::new(1);
+        tree.split(SplitDirection::Horizontal, 2, AREA);
+        tree.split(SplitDirection::Vertical, 3, AREA);
+        
+        assert!(tree.close(2));
+        assert_eq!(tree.focused(), 3);
+        assert_eq!(tree.layout(AREA), vec![(1, Rect::new(0, 0, 40, 24)), (3, Rect::new(40, 0, 40, 24))]);
+        
+        assert!(tree.close(3));
+        assert_eq!(tree.focused(), 1);
+        assert!(!tree.close(1));
+    }
+    
+    #[test]
+    fn focus_moves_to_nearest_neighbor() {
+        let mut tree = PaneTree::new(1);
+        tree.split(SplitDirection::Horizontal, 2, AREA);
+        tree.split(SplitDirection::Vertical, 3, AREA);
+        
+        assert!(tree.focus_direction(Direction::Left, AREA));
+        assert_eq!(tree.focused(), 1);
+        assert!(!tree.focus_direction(Direction::Left, AREA));
+        
+        assert!(tree.focus_direction(Direction::Right, AREA));
+        assert_eq!(tree.focused(), 2);
+        assert!(tree.focus_direction(Direction::Down, AREA));
+        assert_eq!(tree.focused(), 3);
+    }
+    
+    #[test]
+    fn resize_moves_matching_divider() {
+        let mut tree = PaneTree::new(1);
+        tree.split(SplitDirection::Horizontal, 2, AREA);
+        tree.split(SplitDirection::Vertical, 3, AREA);
+        
+        assert!(tree.resize(SplitDirection::Horizontal, 0.25));
+        assert_eq!(tree.layout(AREA)[0].1, Rect::new(0, 0, 20, 24));
+        
+        assert!(tree.resize(SplitDirection::Horizontal, 1.0));
+        assert_eq!(tree.layout(AREA)[0].1.cols, 8);
+    }
+    
+    #[test]
+    fn zoom_shows_only_focused_pane() {
+        let mut tree = PaneTree::new(1);
+        tree.toggle_zoom();
+        assert!(!tree.is_zoomed());
+        
+        tree.split(SplitDirection::Horizontal, 2, AREA);
+        tree.toggle_zoom();
+        assert_eq!(tree.layout(AREA), vec![(2, AREA)]);
+        
+        tree.toggle_zoom();
+        assert_eq!(tree.layout(AREA).len(), 2);
+    }
+    
+    #[test]
+    fn split_is_refused_once_a_pane_would_have_no_cells() {
+        let narrow = Rect::new(0, 0, 3, 24);
+        let mut tree = PaneTree::new(1);
+        assert!(tree.split(SplitDirection::Horizontal, 2, narrow));
+        assert!(!tree.split(SplitDirection::Horizontal, 3, narrow));
+        assert_eq!(tree.panes(), vec![1, 2]);
+        assert_eq!(tree.focused(), 2);
+        assert_eq!(tree.layout(narrow), vec![(1, Rect::new(0, 0, 2, 24)), (2, Rect::new(2, 0, 1, 24))]);
+        
+        // Layouts that divide the whole area run out of room the same way
+        tree.set_kind(LayoutKind::Horizontal, MainPaneOptions::default());
+        assert!(tree.split(SplitDirection::Vertical, 3, narrow));
+        assert!(!tree.split(SplitDirection::Vertical, 4, narrow));
+        assert!(tree.layout(narrow).iter().all(|(_, rect)| rect.cols == 1));
+    }
+    
+    fn tree_of(count: usize) -> PaneTree {
+        let mut tree = PaneTree::new(0);
+        for pane in 1..count {
+            tree.split(SplitDirection::Horizontal, pane, AREA);
+        }
+        tree
+    }
+    
+    #[test]
+    fn tall_layout_stacks_other_panes_beside_main() {
+        let mut tree = tree_of(3);
+        tree.set_kind(LayoutKind::Tall, MainPaneOptions { bias: 60, ..Default::default() });
+        assert_eq!(
+            tree.layout(AREA),
+            vec![
+                (0, Rect::new(0, 0, 48, 24)),
+                (1, Rect::new(48, 0, 32, 12)),
+                (2, Rect::new(48, 12, 32, 12)),
+            ]
+        );
+        
+        tree.focus(0);
+        assert!(tree.resize(SplitDirection::Horizontal, 0.1));
+        assert_eq!(tree.layout(AREA)[0].1.cols, 56);
+        assert!(!tree.resize(SplitDirection::Vertical, 0.1));
+        
+        tree.set_kind(LayoutKind::Fat, MainPaneOptions { mirrored: true, ..Default::default() });
+        assert_eq!(
+            tree.layout(AREA),
+            vec![
+                (0, Rect::new(0, 12, 80, 12)),
+                (1, Rect::new(0, 0, 40, 12)),
+                (2, Rect::new(40, 0, 40, 12)),
+            ]
+        );
+    }
+    
+    #[test]
+    fn grid_layout_stretches_short_last_row() {
+        let mut tree = tree_of(5);
+        tree.set_kind(LayoutKind::Grid, MainPaneOptions::default());
+        assert_eq!(
+            tree.layout(AREA),
+            vec![
+                (0, Rect::new(0, 0, 27, 12)),
+                (1, Rect::new(27, 0, 27, 12)),
+                (2, Rect::new(54, 0, 26, 12)),
+                (3, Rect::new(0, 12, 40, 12)),
+                (4, Rect::new(40, 12, 40, 12)),
+            ]
+        );
+        
+        tree.set_kind(LayoutKind::Horizontal, MainPaneOptions::default());
+        assert!(tree.layout(AREA).iter().all(|(_, rect)| rect.rows == 24 && rect.cols == 16));
+    }
+    
+    #[test]
+    fn stack_layout_shows_focused_and_cycles_focus() {
+        let mut tree = tree_of(3);
+        tree.set_kind(LayoutKind::Stack, MainPaneOptions::default());
+        assert_eq!(tree.layout(AREA), vec![(2, AREA)]);
+        
+        assert!(tree.focus_direction(Direction::Left, AREA));
+        assert_eq!(tree.layout(AREA), vec![(1, AREA)]);
+        assert!(tree.focus_direction(Direction::Down, AREA));
+        assert!(!tree.focus_direction(Direction::Right, AREA));
+        
+        tree.set_kind(LayoutKind::Splits, MainPaneOptions::default());
+        assert_eq!(tree.layout(AREA).len(), 3);
+    }
+}
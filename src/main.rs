@@ -5,7 +5,17 @@ use tokio::sync::Mutex;
 mod core;
 mod gpu;
 mod ui;
+mod clipboard;
 mod config;
+mod graphics;
+mod hyperlink;
+mod keyboard;
+mod layout;
+mod mouse;
+mod notifications;
+mod palette;
+mod pty;
+mod sixel;
 mod terminal;
 
 use crate::config::Config;
@@ -18,6 +28,8 @@ async fn main() -> Result<()> {
     
     let config = Config::load()?;
     let terminal = Arc::new(Mutex::new(Terminal::new(config.clone())?));
+    let pane_id = terminal.lock().await.focused_pane_id();
+    Terminal::spawn_shell(terminal.clone(), pane_id).await?;
     
     let app = App::new(terminal, config).await?;
     app.run().await?;
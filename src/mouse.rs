@@ -0,0 +1,193 @@
+use crate::keyboard::Modifiers;
+
+/// Which mouse events a program asked for with DECSET 9, 1000, 1002 and
+/// 1003.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseTracking {
+    #[default]
+    Off,
+    /// 9: presses only, without modifiers.
+    X10,
+    /// 1000: presses and releases.
+    Normal,
+    /// 1002: also motion while a button is held.
+    ButtonEvent,
+    /// 1003: also motion without a button.
+    AnyEvent,
+}
+
+/// How mouse reports are written, set with DECSET 1006 and 1016.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseEncoding {
+    /// `CSI M` followed by three bytes, limited to 223 columns and rows.
+    #[default]
+    Default,
+    /// 1006: `CSI < button ; col ; row M`, or `m` for releases.
+    Sgr,
+    /// 1016: like SGR, with the position in pixels.
+    SgrPixels,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+}
+
+impl MouseButton {
+    fn code(self) -> u32 {
+        match self {
+            MouseButton::Left => 0,
+            MouseButton::Middle => 1,
+            MouseButton::Right => 2,
+            MouseButton::WheelUp => 64,
+            MouseButton::WheelDown => 65,
+            MouseButton::WheelLeft => 66,
+            MouseButton::WheelRight => 67,
+        }
+    }
+    
+    pub fn is_wheel(self) -> bool {
+        self.code() >= 64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    /// Pointer motion, with the button held down if there is one.
+    Motion(Option<MouseButton>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    pub modifiers: Modifiers,
+    /// Cell under the pointer, counted from zero.
+    pub col: usize,
+    pub row: usize,
+    /// Pointer position in pixels from the top left of the screen.
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Encodes a mouse event as a report, or returns `None` when the tracking
+/// mode doesn't report it or the default encoding can't represent it.
+pub fn encode(event: &MouseEvent, tracking: MouseTracking, encoding: MouseEncoding) -> Option<Vec<u8>> {
+    let (mut code, release) = match (event.kind, tracking) {
+        (_, MouseTracking::Off) => return None,
+        (MouseEventKind::Press(button), _) => (button.code(), false),
+        (MouseEventKind::Release(_), MouseTracking::X10) => return None,
+        (MouseEventKind::Release(button), _) if button.is_wheel() => return None,
+        (MouseEventKind::Release(button), _) => (button.code(), true),
+        (MouseEventKind::Motion(Some(button)), MouseTracking::ButtonEvent | MouseTracking::AnyEvent) => {
+            (button.code() + 32, false)
+        }
+        (MouseEventKind::Motion(None), MouseTracking::AnyEvent) => (3 + 32, false),
+        (MouseEventKind::Motion(_), _) => return None,
+    };
+    
+    if tracking != MouseTracking::X10 {
+        let modifiers = event.modifiers;
+        code += [(modifiers.shift, 4), (modifiers.alt, 8), (modifiers.ctrl, 16)]
+            .iter()
+            .filter(|(held, _)| *held)
+            .map(|(_, bit)| bit)
+            .sum::<u32>();
+    }
+    
+    let terminator = if release { 'm' } else { 'M' };
+    match encoding {
+        MouseEncoding::Default => {
+            // Releases don't say which button was released
+            let code = if release { code & !0b11 | 3 } else { code };
+            let bytes = [code as usize + 32, event.col + 33, event.row + 33];
+            if bytes.iter().any(|&byte| byte > 255) {
+                return None;
+            }
+            let mut report = b"\x1b[M".to_vec();
+            report.extend(bytes.iter().map(|&byte| byte as u8));
+            Some(report)
+        }
+        MouseEncoding::Sgr => {
+            let (col, row) = (event.col + 1, event.row + 1);
+            Some(format!("\x1b[<{};{};{}{}", code, col, row, terminator).into_bytes())
+        }
+        MouseEncoding::SgrPixels => {
+            let (x, y) = (event.x.max(0.0) as usize + 1, event.y.max(0.0) as usize + 1);
+            Some(format!("\x1b[<{};{};{}{}", code, x, y, terminator).into_bytes())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn event(kind: MouseEventKind, modifiers: Modifiers) -> MouseEvent {
+        MouseEvent {
+            kind,
+            modifiers,
+            col: 4,
+            row: 2,
+            x: 36.5,
+            y: 40.0,
+        }
+    }
+    
+    fn report(event: &MouseEvent, tracking: MouseTracking, encoding: MouseEncoding) -> Option<String> {
+        encode(event, tracking, encoding).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+    
+    #[test]
+    fn tracking_modes_choose_reported_events() {
+        use MouseEventKind::*;
+        
+        let press = event(Press(MouseButton::Left), Modifiers::NONE);
+        let release = event(Release(MouseButton::Left), Modifiers::NONE);
+        let drag = event(Motion(Some(MouseButton::Left)), Modifiers::NONE);
+        let hover = event(Motion(None), Modifiers::NONE);
+        let sgr = |event: &MouseEvent, tracking| report(event, tracking, MouseEncoding::Sgr);
+        
+        assert_eq!(sgr(&press, MouseTracking::Off), None);
+        assert_eq!(sgr(&press, MouseTracking::X10).as_deref(), Some("\x1b[<0;5;3M"));
+        assert_eq!(sgr(&release, MouseTracking::X10), None);
+        assert_eq!(sgr(&release, MouseTracking::Normal).as_deref(), Some("\x1b[<0;5;3m"));
+        assert_eq!(sgr(&drag, MouseTracking::Normal), None);
+        assert_eq!(sgr(&drag, MouseTracking::ButtonEvent).as_deref(), Some("\x1b[<32;5;3M"));
+        assert_eq!(sgr(&hover, MouseTracking::ButtonEvent), None);
+        assert_eq!(sgr(&hover, MouseTracking::AnyEvent).as_deref(), Some("\x1b[<35;5;3M"));
+        
+        let ctrl_alt = Modifiers { ctrl: true, alt: true, ..Modifiers::NONE };
+        let wheel = event(Press(MouseButton::WheelDown), ctrl_alt);
+        assert_eq!(sgr(&wheel, MouseTracking::Normal).as_deref(), Some("\x1b[<89;5;3M"));
+        assert_eq!(sgr(&wheel, MouseTracking::X10).as_deref(), Some("\x1b[<65;5;3M"));
+        assert_eq!(sgr(&event(Release(MouseButton::WheelDown), ctrl_alt), MouseTracking::Normal), None);
+    }
+    
+    #[test]
+    fn encodings() {
+        let right = event(MouseEventKind::Press(MouseButton::Right), Modifiers::SHIFT);
+        let release = event(MouseEventKind::Release(MouseButton::Right), Modifiers::SHIFT);
+        
+        assert_eq!(encode(&right, MouseTracking::Normal, MouseEncoding::Default), Some(b"\x1b[M&%#".to_vec()));
+        assert_eq!(encode(&release, MouseTracking::Normal, MouseEncoding::Default), Some(b"\x1b[M'%#".to_vec()));
+        assert_eq!(
+            report(&release, MouseTracking::Normal, MouseEncoding::SgrPixels).as_deref(),
+            Some("\x1b[<6;37;41m")
+        );
+        
+        let far = MouseEvent { col: 300, ..right };
+        assert_eq!(encode(&far, MouseTracking::Normal, MouseEncoding::Default), None);
+        assert_eq!(
+            report(&far, MouseTracking::Normal, MouseEncoding::Sgr).as_deref(),
+            Some("\x1b[<6;301;3M")
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+
+/// A desktop notification requested by a program with OSC 9, OSC 777 or
+/// kitty's OSC 99.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Notification {
+    /// Identifier chosen by the program (OSC 99 `i=`), used to update a
+    /// notification that is already shown.
+    pub id: Option<String>,
+    pub title: String,
+    pub body: String,
+}
+
+pub trait Notifier: Send {
+    fn notify(&mut self, notification: &Notification) -> Result<()>;
+}
+
+/// Keeps the most recent notifications in memory, for tests and for systems
+/// without a notification service.
+#[derive(Debug, Default)]
+pub struct MemoryNotifier {
+    pub notifications: VecDeque<Notification>,
+}
+
+const MEMORY_NOTIFIER_LIMIT: usize = 100;
+
+impl Notifier for MemoryNotifier {
+    fn notify(&mut self, notification: &Notification) -> Result<()> {
+        if self.notifications.len() == MEMORY_NOTIFIER_LIMIT {
+            self.notifications.pop_front();
+        }
+        self.notifications.push_back(notification.clone());
+        Ok(())
+    }
+}
+
+/// Sends notifications to the desktop's `org.freedesktop.Notifications`
+/// service over the D-Bus session bus.
+#[cfg(target_os = "linux")]
+pub struct DbusNotifier {
+    connection: zbus::blocking::Connection,
+    /// Server ids of notifications shown for program-chosen ids, so that
+    /// sending the same id again replaces the notification.
+    shown: std::collections::HashMap<String, u32>,
+}
+
+#[cfg(target_os = "linux")]
+impl DbusNotifier {
+    pub fn new() -> Result<Self> {
+        use anyhow::Context;
+        
+        let connection = zbus::blocking::Connection::session().context("Failed to connect to the D-Bus session bus")?;
+        Ok(Self {
+            connection,
+            shown: std::collections::HashMap::new(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Notifier for DbusNotifier {
+    fn notify(&mut self, notification: &Notification) -> Result<()> {
+        use std::collections::HashMap;
+        use zbus::zvariant::Value;
+        
+        let replaces_id = notification
+            .id
+            .as_ref()
+            .and_then(|id| self.shown.get(id))
+            .copied()
+            .unwrap_or(0);
+        let actions: Vec<&str> = Vec::new();
+        let hints: HashMap<&str, Value> = HashMap::new();
+        
+        let reply = self.connection.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                "kitty-plus",
+                replaces_id,
+                "",
+                notification.title.as_str(),
+                notification.body.as_str(),
+                actions,
+                hints,
+                -1i32,
+            ),
+        )?;
+        
+        let server_id: u32 = reply.body().deserialize()?;
+        if let Some(id) = &notification.id {
+            self.shown.insert(id.clone(), server_id);
+        }
+        Ok(())
+    }
+}
+
+/// The desktop notification service when one is reachable, otherwise an
+/// in-memory notifier.
+pub fn system_or_memory() -> Box<dyn Notifier> {
+    #[cfg(target_os = "linux")]
+    match DbusNotifier::new() {
+        Ok(notifier) => return Box::new(notifier),
+        Err(e) => log::warn!("{:#}; desktop notifications are disabled", e),
+    }
+    
+    Box::<MemoryNotifier>::default()
+}
@@ -0,0 +1,135 @@
+use crate::config::ColorScheme;
+use crate::terminal::{Color, Rgb};
+
+/// The colors programs can refer to and change at runtime: the 256 indexed
+/// colors and the default foreground, background and cursor colors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 256],
+    pub foreground: Rgb,
+    pub background: Rgb,
+    pub cursor: Rgb,
+}
+
+impl Default for Palette {
+    /// xterm's default colors.
+    fn default() -> Self {
+        Self {
+            colors: std::array::from_fn(|index| Rgb::indexed(index as u8)),
+            foreground: Rgb::indexed(7),
+            background: Rgb::indexed(0),
+            cursor: Rgb::indexed(7),
+        }
+    }
+}
+
+impl Palette {
+    /// Applies a color scheme over xterm's defaults. Colors the scheme
+    /// doesn't set, or sets to something unparsable, keep their default.
+    pub fn from_scheme(scheme: &ColorScheme) -> Self {
+        let mut palette = Self::default();
+        let ansi = [
+            &scheme.black,
+            &scheme.red,
+            &scheme.green,
+            &scheme.yellow,
+            &scheme.blue,
+            &scheme.magenta,
+            &scheme.cyan,
+            &scheme.white,
+        ];
+        let slots = palette
+            .colors
+            .iter_mut()
+            .zip(ansi)
+            .chain([
+                (&mut palette.foreground, &scheme.foreground),
+                (&mut palette.background, &scheme.background),
+                (&mut palette.cursor, &scheme.cursor),
+            ]);
+        
+        for (slot, spec) in slots {
+            match parse_color(spec) {
+                Some(color) => *slot = color,
+                None => log::warn!("Invalid color {:?} in color scheme", spec),
+            }
+        }
+        palette
+    }
+    
+    /// The color a cell's foreground or background is drawn in, with
+    /// `default` standing in for [`Color::Default`].
+    pub fn resolve(&self, color: Color, default: Rgb) -> Rgb {
+        match color {
+            Color::Default => default,
+            Color::Indexed(index) => self.colors[index as usize],
+            Color::Rgb(rgb) => rgb,
+        }
+    }
+}
+
+/// Parses an X11 color specification as accepted by OSC 4 and 10-12:
+/// `rgb:r/g/b` with one to four hex digits per channel, or `#` followed by
+/// one to four hex digits per channel.
+pub fn parse_color(spec: &str) -> Option<Rgb> {
+    // Scales a channel of any width to eight bits
+    let scale = |hex: &str| -> Option<u8> {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (4 * hex.len())) - 1;
+        Some(((value * 255 + max / 2) / max) as u8)
+    };
+    
+    if let Some(rgb) = spec.strip_prefix("rgb:") {
+        let mut channels = rgb.split('/');
+        let (r, g, b) = (channels.next()?, channels.next()?, channels.next()?);
+        if channels.next().is_some() {
+            return None;
+        }
+        return Some(Rgb::new(scale(r)?, scale(g)?, scale(b)?));
+    }
+    
+    let hex = spec.strip_prefix('#')?;
+    if !hex.is_ascii() || hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 {
+        return None;
+    }
+    let width = hex.len() / 3;
+    // Wider channels keep their most significant bits, as in X11
+    let channel = |i: usize| match width {
+        1 => scale(&hex[i..i + 1]),
+        _ => u8::from_str_radix(&hex[i * width..i * width + 2], 16).ok(),
+    };
+    Some(Rgb::new(channel(0)?, channel(1)?, channel(2)?))
+}
+
+/// Formats a color the way xterm reports it, `rgb:rrrr/gggg/bbbb`.
+pub fn format_color(color: Rgb) -> String {
+    format!(
+        "rgb:{:04x}/{:04x}/{:04x}",
+        color.r as u16 * 257,
+        color.g as u16 * 257,
+        color.b as u16 * 257
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn parses_x11_color_specs() {
+        assert_eq!(parse_color("rgb:ff/80/00"), Some(Rgb::new(0xff, 0x80, 0x00)));
+        assert_eq!(parse_color("rgb:f/8/0"), Some(Rgb::new(0xff, 0x88, 0x00)));
+        assert_eq!(parse_color("rgb:ffff/8080/0000"), Some(Rgb::new(0xff, 0x80, 0x00)));
+        assert_eq!(parse_color("#1e1e2e"), Some(Rgb::new(0x1e, 0x1e, 0x2e)));
+        assert_eq!(parse_color("#fff"), Some(Rgb::new(0xff, 0xff, 0xff)));
+        assert_eq!(parse_color("#123456789abc"), Some(Rgb::new(0x12, 0x56, 0x9a)));
+        assert_eq!(parse_color("rgb:ff/80"), None);
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(parse_color("red"), None);
+        
+        assert_eq!(format_color(Rgb::new(0x1e, 0x1e, 0x2e)), "rgb:1e1e/1e1e/2e2e");
+    }
+}
@@ -0,0 +1,368 @@
+#[cfg(unix)]
+use anyhow::{anyhow, Context, Result};
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+#[cfg(unix)]
+use std::fs::File;
+#[cfg(unix)]
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+#[cfg(unix)]
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+#[cfg(unix)]
+use std::process::{Command, ExitStatus, Stdio};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use tokio::sync::mpsc;
+
+#[cfg(unix)]
+use crate::config::ShellConfig;
+
+/// Events produced by the child side of a PTY, in the order they happened.
+#[cfg(unix)]
+pub enum PtyEvent {
+    Output(Vec<u8>),
+    Exited(ExitStatus),
+}
+
+/// A shell running on a pseudo-terminal. Dropping it hangs up the child.
+#[cfg(unix)]
+pub struct Pty {
+    master: File,
+    pid: u32,
+    /// Input for the writer thread, so a child that stops reading can't block
+    /// the caller while the PTY buffer is full.
+    input: mpsc::UnboundedSender<Vec<u8>>,
+    /// Set by the reader thread before it reaps the child, after which the
+    /// pid may belong to another process and must not be signalled.
+    exited: Arc<AtomicBool>,
+}
+
+#[cfg(unix)]
+impl Pty {
+    /// Spawns the configured shell on a new PTY of the given size. Output and
+    /// the final exit status arrive on the returned channel.
+    pub fn spawn(
+        config: &ShellConfig,
+        rows: u16,
+        cols: u16,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<PtyEvent>)> {
+        let (master, slave) = open_pty(rows, cols)?;
+        let program = config.program();
+        
+        let mut child = {
+            let mut command = Command::new(&program);
+            command
+                .args(&config.args)
+                .envs(&config.env)
+                .env("TERM", &config.term)
+                .env("COLORTERM", "truecolor")
+                .env("TERM_PROGRAM", "kitty-plus")
+                .stdin(Stdio::from(slave.try_clone()?))
+                .stdout(Stdio::from(slave.try_clone()?))
+                .stderr(Stdio::from(slave));
+            if let Some(dir) = &config.working_directory {
+                command.current_dir(dir);
+            }
+            
+            // Make the child a session leader with the PTY as its controlling
+            // terminal so job control and SIGWINCH work.
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setsid() == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    if libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+            
+            // The command is dropped at the end of this block, closing our
+            // copies of the slave so reads see EOF once the child is gone.
+            command
+                .spawn()
+                .with_context(|| format!("Failed to spawn {}", program))?
+        };
+        
+        let pid = child.id();
+        let mut reader = master.try_clone()?;
+        let mut writer = master.try_clone()?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (input, mut pending) = mpsc::unbounded_channel::<Vec<u8>>();
+        let exited = Arc::new(AtomicBool::new(false));
+        let reaped = exited.clone();
+        
+        std::thread::Builder::new()
+            .name("pty-reader".to_string())
+            .spawn(move || {
+                let mut buf = vec![0u8; 64 * 1024];
+                loop {
+                    match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            if sender.send(PtyEvent::Output(buf[..n].to_vec())).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        // Linux reports EIO once the child side has hung up
+                        Err(_) => break,
+                    }
+                }
+                
+                // Reaping here, after the last read, keeps the exit event
+                // ordered after all of the child's output.
+                reaped.store(true, Ordering::SeqCst);
+                let status = child.wait().unwrap_or_else(|e| {
+                    log::error!("Failed to wait for child {}: {}", pid, e);
+                    ExitStatus::from_raw(0)
+                });
+                let _ = sender.send(PtyEvent::Exited(status));
+            })?;
+        
+        // Ends when the Pty is dropped or the child side goes away
+        std::thread::Builder::new()
+            .name("pty-writer".to_string())
+            .spawn(move || {
+                while let Some(data) = pending.blocking_recv() {
+                    if let Err(e) = writer.write_all(&data) {
+                        log::debug!("Stopped writing to child {}: {}", pid, e);
+                        break;
+                    }
+                }
+            })?;
+        
+        Ok((Self { master, pid, input, exited }, receiver))
+    }
+    
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+    
+    /// Queues input for the child without waiting for it to be read.
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        self.input
+            .send(data.to_vec())
+            .map_err(|_| anyhow!("PTY writer has stopped"))
+    }
+    
+    /// Updates the window size, which also sends SIGWINCH to the child.
+    pub fn resize(&self, rows: u16, cols: u16) -> Result<()> {
+        let size = window_size(rows, cols);
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+            return Err(io::Error::last_os_error()).context("Failed to resize PTY");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Pty {
+    fn drop(&mut self) {
+        if self.exited.load(Ordering::SeqCst) {
+            return;
+        }
+        unsafe {
+            libc::kill(self.pid as libc::pid_t, libc::SIGHUP);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn window_size(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+#[cfg(unix)]
+fn open_pty(rows: u16, cols: u16) -> Result<(File, OwnedFd)> {
+    // Both ends are opened close-on-exec so a shell spawned on another thread
+    // can never inherit them; the child gets the slave through its stdio.
+    let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if master == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to open PTY");
+    }
+    let master = unsafe { File::from_raw_fd(master) };
+    
+    if unsafe { libc::grantpt(master.as_raw_fd()) } == -1
+        || unsafe { libc::unlockpt(master.as_raw_fd()) } == -1
+    {
+        return Err(io::Error::last_os_error()).context("Failed to unlock PTY");
+    }
+    
+    let name = slave_name(&master)?;
+    let slave = unsafe { libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if slave == -1 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("Failed to open {}", name.to_string_lossy()));
+    }
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    
+    let size = window_size(rows, cols);
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to size PTY");
+    }
+    
+    Ok((master, slave))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn slave_name(master: &File) -> Result<CString> {
+    let mut buf = [0 as libc::c_char; 128];
+    let result = unsafe { libc::ptsname_r(master.as_raw_fd(), buf.as_mut_ptr(), buf.len()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result)).context("Failed to name PTY");
+    }
+    Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_owned())
+}
+
+/// `ptsname` returns a static buffer here, so calls are serialized.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android", target_os = "freebsd"))))]
+fn slave_name(master: &File) -> Result<CString> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let name = unsafe { libc::ptsname(master.as_raw_fd()) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error()).context("Failed to name PTY");
+    }
+    Ok(unsafe { CStr::from_ptr(name) }.to_owned())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    
+    fn shell(script: &str) -> ShellConfig {
+        ShellConfig {
+            program: Some("/bin/sh".to_string()),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..ShellConfig::default()
+        }
+    }
+    
+    /// Collects the child's output until it exits.
+    fn run(mut events: mpsc::UnboundedReceiver<PtyEvent>) -> (String, ExitStatus) {
+        let mut output = Vec::new();
+        while let Some(event) = events.blocking_recv() {
+            match event {
+                PtyEvent::Output(data) => output.extend(data),
+                PtyEvent::Exited(status) => return (String::from_utf8_lossy(&output).into_owned(), status),
+            }
+        }
+        panic!("The reader stopped without an exit status");
+    }
+    
+    #[test]
+    fn reports_output_then_exit_status() {
+        let (_pty, events) = Pty::spawn(&shell("printf ok; exit 3"), 24, 80).unwrap();
+        let (output, status) = run(events);
+        assert_eq!(output, "ok");
+        assert_eq!(status.code(), Some(3));
+    }
+    
+    #[test]
+    fn input_reaches_the_child() {
+        let (pty, events) = Pty::spawn(&shell("read line; echo \"got $line\""), 24, 80).unwrap();
+        pty.write(b"hello\n").unwrap();
+        let (output, _) = run(events);
+        assert!(output.contains("got hello"), "{:?}", output);
+    }
+    
+    #[test]
+    fn write_does_not_wait_for_the_child_to_read() {
+        let (pty, _events) = Pty::spawn(&shell("sleep 5"), 24, 80).unwrap();
+        // Far more than the kernel buffers, so a direct write would block
+        let start = std::time::Instant::now();
+        for _ in 0..64 {
+            pty.write(&[b'x'; 64 * 1024]).unwrap();
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+    
+    #[test]
+    fn both_ends_are_opened_close_on_exec() {
+        let (master, slave) = open_pty(24, 80).unwrap();
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+            assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+        }
+    }
+    
+    #[test]
+    fn reaped_child_is_not_signalled_on_drop() {
+        let (pty, events) = Pty::spawn(&shell("exit 0"), 24, 80).unwrap();
+        run(events);
+        assert!(pty.exited.load(Ordering::SeqCst));
+    }
+    
+    #[test]
+    fn resize_sets_the_window_size() {
+        let (pty, mut events) = Pty::spawn(&shell("stty size; read line; stty size"), 24, 80).unwrap();
+        let mut output = Vec::new();
+        while !output.ends_with(b"24 80\r\n") {
+            match events.blocking_recv() {
+                Some(PtyEvent::Output(data)) => output.extend(data),
+                _ => panic!("The shell exited early"),
+            }
+        }
+        
+        // The shell is waiting for a line, so the second stty sees the new size
+        pty.resize(30, 100).unwrap();
+        pty.write(b"\n").unwrap();
+        let (output, status) = run(events);
+        assert_eq!(output.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>(), ["30 100"]);
+        assert_eq!(status.code(), Some(0));
+    }
+}
+
+#[cfg(not(unix))]
+use anyhow::Result;
+#[cfg(not(unix))]
+use std::process::ExitStatus;
+#[cfg(not(unix))]
+use tokio::sync::mpsc;
+
+#[cfg(not(unix))]
+use crate::config::ShellConfig;
+
+#[cfg(not(unix))]
+pub enum PtyEvent {
+    Output(Vec<u8>),
+    Exited(ExitStatus),
+}
+
+#[cfg(not(unix))]
+pub struct Pty;
+
+#[cfg(not(unix))]
+impl Pty {
+    pub fn spawn(
+        _config: &ShellConfig,
+        _rows: u16,
+        _cols: u16,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<PtyEvent>)> {
+        Err(anyhow::anyhow!("PTY support is only available on Unix"))
+    }
+    
+    pub fn pid(&self) -> u32 {
+        0
+    }
+    
+    pub fn write(&self, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+    
+    pub fn resize(&self, _rows: u16, _cols: u16) -> Result<()> {
+        Ok(())
+    }
+}
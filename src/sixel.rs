@@ -0,0 +1,310 @@
+use crate::graphics::MAX_IMAGE_DIMENSION;
+
+/// Number of color registers, as reported by XTSMGRAPHICS.
+pub const COLOR_REGISTERS: usize = 256;
+
+/// The VT340's default color registers, in percent. The rest start black.
+const DEFAULT_COLORS: [(u32, u32, u32); 16] = [
+    (0, 0, 0),
+    (20, 20, 80),
+    (80, 13, 13),
+    (20, 80, 20),
+    (80, 20, 80),
+    (20, 80, 80),
+    (80, 80, 20),
+    (53, 53, 53),
+    (26, 26, 26),
+    (33, 33, 60),
+    (60, 26, 26),
+    (33, 60, 33),
+    (60, 33, 60),
+    (33, 60, 60),
+    (60, 60, 33),
+    (80, 80, 80),
+];
+
+/// The command whose numeric parameters are being collected.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// Sixel data, with no command pending.
+    Data,
+    /// `" Pan ; Pad ; Ph ; Pv`: raster attributes.
+    Raster,
+    /// `# Pc` selects a color register, `# Pc ; Pu ; Px ; Py ; Pz` also
+    /// defines it.
+    Color,
+    /// `! Pn`: repeats the next sixel.
+    Repeat,
+}
+
+/// Decodes the data of a sixel DCS (`DCS P1 ; P2 ; P3 q data ST`) into RGBA
+/// pixels. Each image gets its own color registers, as with xterm's
+/// `privateColorRegisters`.
+pub struct SixelDecoder {
+    colors: [[u8; 4]; COLOR_REGISTERS],
+    color: usize,
+    /// P2 = 1: pixels no sixel sets stay transparent instead of taking the
+    /// color of register 0.
+    transparent: bool,
+    command: Command,
+    params: Vec<u32>,
+    repeat: u32,
+    /// Position of the next sixel: a column, and the top row of its band.
+    x: u32,
+    y: u32,
+    /// Extent of the image so far.
+    width: u32,
+    height: u32,
+    /// Pixels, `stride` wide, grown as sixels land outside them.
+    pixels: Vec<u8>,
+    stride: u32,
+    rows: u32,
+    /// Largest pixel buffer allowed, in bytes.
+    limit: usize,
+}
+
+impl SixelDecoder {
+    /// Starts an image from the DCS parameters, keeping the pixel data
+    /// within `limit` bytes.
+    pub fn new(params: &[u16], limit: usize) -> Self {
+        let mut colors = [[0, 0, 0, 255]; COLOR_REGISTERS];
+        for (color, &(r, g, b)) in colors.iter_mut().zip(&DEFAULT_COLORS) {
+            *color = [percent(r), percent(g), percent(b), 255];
+        }
+        Self {
+            colors,
+            color: 0,
+            transparent: params.get(1) == Some(&1),
+            command: Command::Data,
+            params: Vec::new(),
+            repeat: 1,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+            stride: 0,
+            rows: 0,
+            limit,
+        }
+    }
+    
+    pub fn put(&mut self, byte: u8) {
+        if self.command != Command::Data {
+            match byte {
+                b'0'..=b'9' => {
+                    if self.params.is_empty() {
+                        self.params.push(0);
+                    }
+                    if let Some(param) = self.params.last_mut() {
+                        *param = param.saturating_mul(10).saturating_add((byte - b'0') as u32);
+                    }
+                    return;
+                }
+                b';' => {
+                    if self.params.is_empty() {
+                        self.params.push(0);
+                    }
+                    self.params.push(0);
+                    return;
+                }
+                _ => self.finish_command(),
+            }
+        }
+        
+        match byte {
+            b'?'..=b'~' => {
+                self.draw(byte - b'?');
+                self.repeat = 1;
+            }
+            b'"' => self.command = Command::Raster,
+            b'#' => self.command = Command::Color,
+            b'!' => self.command = Command::Repeat,
+            // Graphics carriage return and new line
+            b'$' => self.x = 0,
+            b'-' => {
+                self.x = 0;
+                self.y = self.y.saturating_add(6);
+            }
+            _ => {}
+        }
+    }
+    
+    /// Ends the image, returning its size and pixels, or `None` when it has
+    /// no pixels.
+    pub fn finish(mut self) -> Option<(u32, u32, Vec<u8>)> {
+        if self.command != Command::Data {
+            self.finish_command();
+        }
+        if self.width == 0 || self.height == 0 || !self.reserve(self.width, self.height) {
+            return None;
+        }
+        
+        let background = if self.transparent { [0; 4] } else { self.colors[0] };
+        let row_len = self.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row_len * self.height as usize);
+        for row in self.pixels.chunks_exact(self.stride as usize * 4).take(self.height as usize) {
+            pixels.extend(row[..row_len].chunks_exact(4).flat_map(|pixel| {
+                if pixel[3] == 0 { background } else { [pixel[0], pixel[1], pixel[2], pixel[3]] }
+            }));
+        }
+        Some((self.width, self.height, pixels))
+    }
+    
+    fn finish_command(&mut self) {
+        let params = std::mem::take(&mut self.params);
+        let param = |index: usize| params.get(index).copied().unwrap_or(0);
+        match self.command {
+            Command::Data => {}
+            // The aspect ratio is ignored, as in xterm; the size only
+            // presets the extent of the image
+            Command::Raster => {
+                let (width, height) = (param(2).min(MAX_IMAGE_DIMENSION), param(3).min(MAX_IMAGE_DIMENSION));
+                if self.reserve(width, height) {
+                    self.width = self.width.max(width);
+                    self.height = self.height.max(height);
+                }
+            }
+            Command::Color => {
+                self.color = param(0) as usize % COLOR_REGISTERS;
+                let color = match param(1) {
+                    1 => Some(hls_to_rgb(param(2), param(3), param(4))),
+                    2 => Some([percent(param(2)), percent(param(3)), percent(param(4))]),
+                    _ => None,
+                };
+                if let Some([r, g, b]) = color {
+                    self.colors[self.color] = [r, g, b, 255];
+                }
+            }
+            // A repeat count of zero means one
+            Command::Repeat => self.repeat = param(0).max(1),
+        }
+        self.command = Command::Data;
+    }
+    
+    /// Paints a sixel: bit `n` of `bits` is the pixel `n` rows below the top
+    /// of the band.
+    fn draw(&mut self, bits: u8) {
+        let repeat = self.repeat.min(MAX_IMAGE_DIMENSION.saturating_sub(self.x));
+        if repeat == 0 || self.y >= MAX_IMAGE_DIMENSION {
+            return;
+        }
+        let (right, bottom) = (self.x + repeat, (self.y + 6).min(MAX_IMAGE_DIMENSION));
+        if bits != 0 {
+            if !self.reserve(right, bottom) {
+                return;
+            }
+            let color = self.colors[self.color];
+            for row in (self.y..bottom).filter(|row| bits & 1 << (row - self.y) != 0) {
+                let start = (row as usize * self.stride as usize + self.x as usize) * 4;
+                for pixel in self.pixels[start..start + repeat as usize * 4].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&color);
+                }
+                self.height = self.height.max(row + 1);
+            }
+        }
+        self.x = right;
+        self.width = self.width.max(right);
+    }
+    
+    /// Grows the pixel buffer to hold `width` by `height` pixels, doubling
+    /// each dimension so that images without raster attributes don't copy
+    /// on every sixel. Returns false if that exceeds the limit.
+    fn reserve(&mut self, width: u32, height: u32) -> bool {
+        if width <= self.stride && height <= self.rows {
+            return true;
+        }
+        let (width, height) = (width.max(self.stride), height.max(self.rows));
+        if width as usize * height as usize * 4 > self.limit {
+            return false;
+        }
+        let grow = |needed: u32, current: u32| {
+            if needed == current { current } else { needed.max(current * 2).min(MAX_IMAGE_DIMENSION) }
+        };
+        let (mut stride, mut rows) = (grow(width, self.stride), grow(height, self.rows));
+        if stride as usize * rows as usize * 4 > self.limit {
+            (stride, rows) = (width, height);
+        }
+        
+        let mut pixels = vec![0; stride as usize * rows as usize * 4];
+        let old_row = self.stride as usize * 4;
+        for (row, old) in self.pixels.chunks_exact(old_row.max(1)).enumerate() {
+            let start = row * stride as usize * 4;
+            pixels[start..start + old_row].copy_from_slice(old);
+        }
+        self.pixels = pixels;
+        self.stride = stride;
+        self.rows = rows;
+        true
+    }
+}
+
+fn percent(value: u32) -> u8 {
+    ((value.min(100) * 255 + 50) / 100) as u8
+}
+
+/// Converts a DEC HLS color, whose hue starts at blue rather than red, to
+/// RGB.
+fn hls_to_rgb(hue: u32, lightness: u32, saturation: u32) -> [u8; 3] {
+    let hue = ((hue + 240) % 360) as f32;
+    let (lightness, saturation) = (lightness.min(100) as f32 / 100.0, saturation.min(100) as f32 / 100.0);
+    
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 / 60 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    [r, g, b].map(|channel| ((channel + m) * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn decode(params: &[u16], data: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+        let mut decoder = SixelDecoder::new(params, 1 << 20);
+        for &byte in data {
+            decoder.put(byte);
+        }
+        decoder.finish()
+    }
+    
+    #[test]
+    fn decodes_bands_colors_and_repeats() {
+        // Red top pixel, then three columns of green covering two rows,
+        // then a second band with a single blue pixel
+        let (width, height, pixels) = decode(&[0, 1], b"#1;2;100;0;0#2;2;0;100;0#1@$#2!3B-#3;1;0;50;100@").unwrap();
+        assert_eq!((width, height), (3, 7));
+        let pixel = |x: usize, y: usize| &pixels[(y * 3 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(0, 1), [0, 255, 0, 255]);
+        assert_eq!(pixel(2, 1), [0, 255, 0, 255]);
+        assert_eq!(pixel(1, 2), [0, 0, 0, 0]);
+        assert_eq!(pixel(0, 6), [0, 0, 255, 255]);
+        assert_eq!(pixel(1, 6), [0, 0, 0, 0]);
+    }
+    
+    #[test]
+    fn raster_attributes_and_background() {
+        let (width, height, pixels) = decode(&[0, 0], b"\"1;1;4;2#0;2;0;0;100#5~").unwrap();
+        assert_eq!((width, height), (4, 6));
+        // The unset pixels take register 0, which the image redefined
+        let cyan = [percent(20), percent(80), percent(80), 255];
+        for (index, pixel) in pixels.chunks_exact(4).enumerate() {
+            assert_eq!(pixel, if index % 4 == 0 { cyan } else { [0, 0, 255, 255] });
+        }
+        
+        assert_eq!(decode(&[], b"#1"), None);
+        let mut small = SixelDecoder::new(&[], 16);
+        small.put(b'!');
+        small.put(b'9');
+        small.put(b'~');
+        assert_eq!(small.finish(), None);
+    }
+}
@@ -1,14 +1,86 @@
 use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 use vte::{Params, Parser, Perform};
 
-use crate::config::Config;
+use crate::clipboard::{self, Clipboard, ClipboardRequest, Selection};
+use crate::config::{ClipboardPolicy, Config};
+use crate::graphics::{self, Action, GraphicsCommand, ImageStore, Placement};
+use crate::sixel::{self, SixelDecoder};
+use crate::core::SessionRecorder;
+use crate::hyperlink::{self, Hyperlink, HyperlinkTable, LinkId};
+use crate::keyboard::{self, KeyEvent, KeyboardFlags, Modifiers};
+use crate::layout::{Direction, LayoutKind, PaneId, PaneTree, Rect, SplitDirection};
+use crate::mouse::{self, MouseButton, MouseEncoding, MouseEvent, MouseEventKind, MouseTracking};
+use crate::notifications::{self, Notification, Notifier};
+use crate::palette::{self, Palette};
+use crate::pty::{Pty, PtyEvent};
 
+const TITLE_STACK_LIMIT: usize = 10;
+const KEYBOARD_STACK_LIMIT: usize = 8;
+/// Longest APC string kept; kitty graphics programs send larger images in
+/// chunks.
+const APC_LIMIT: usize = 4 << 20;
+/// How long a synchronized update (mode 2026) may hold back rendering, so a
+/// program that never ends its batch doesn't freeze the screen.
+const SYNCHRONIZED_UPDATE_TIMEOUT: Duration = Duration::from_millis(150);
+/// Chunked OSC 99 notifications kept at once; programs that never finish
+/// theirs can't grow the table without bound.
+const PARTIAL_NOTIFICATION_LIMIT: usize = 32;
+/// Size of the hyperlink table at which links no longer referenced by any
+/// cell are dropped.
+const HYPERLINK_GC_THRESHOLD: usize = 4096;
+
+/// All tabs of a window. Input and output are routed to the tab they belong
+/// to, so every tab keeps its own screen and process.
 pub struct Terminal {
+    config: Config,
+    tabs: Vec<Tab>,
+    active_tab: usize,
+    next_tab_id: usize,
+    next_pane_id: PaneId,
+    rows: usize,
+    cols: usize,
+    /// Size of a cell in pixels, handed to new panes for sizing images.
+    cell_size: (u32, u32),
+    clipboard: Box<dyn Clipboard>,
+    /// OSC 52 requests waiting for the user under the `ask` policy, at most
+    /// one per pane.
+    pending_clipboard: VecDeque<(PaneId, ClipboardRequest)>,
+    notifier: Box<dyn Notifier>,
+    pub recorder: SessionRecorder,
+    /// Called by the tasks reading the panes' programs after each chunk of
+    /// output, so the window can redraw.
+    wakeup: Option<Box<dyn Fn() + Send>>,
+}
+
+/// A single emulated terminal: parser, screen state and the shell feeding it.
+pub struct Emulator {
     parser: Parser,
-    state: TerminalState,
+    apc: ApcScanner,
+    pub state: TerminalState,
     config: Config,
+    pty: Option<Pty>,
+    /// Directory the shell starts in, overriding the configured one.
+    working_directory: Option<PathBuf>,
+    pub process_id: u32,
+    /// Set once the process has exited; the emulator is dead from then on.
+    pub exit_status: Option<ExitStatus>,
+    /// Button held down, reported with motion in button-event tracking.
+    mouse_button: Option<MouseButton>,
+    /// Cell of the last reported motion, so each cell is reported once.
+    mouse_cell: Option<(usize, usize)>,
+    /// Number of panes sharing the configured cache budget with this one.
+    cache_sharers: usize,
 }
 
 #[derive(Default)]
@@ -17,141 +89,4188 @@ pub struct TerminalState {
     pub cursor_y: usize,
     pub rows: usize,
     pub cols: usize,
-    pub buffer: Vec<Vec<Cell>>,
-    pub alt_buffer: Vec<Vec<Cell>>,
+    pub buffer: Vec<Row>,
+    pub alt_buffer: Vec<Row>,
     pub using_alt_buffer: bool,
-    pub tabs: Vec<Tab>,
-    pub active_tab: usize,
+    /// DECSC slots; each screen buffer has its own, as in xterm.
+    pub saved_cursor: Option<SavedCursor>,
+    pub alt_saved_cursor: Option<SavedCursor>,
+    /// Where the cursor was on the primary screen when the alternate screen
+    /// was entered, which anchors reflowing the primary screen on resize.
+    main_cursor: (usize, usize),
+    pub pen: Pen,
+    pub modes: Modes,
+    /// Set after printing in the last column; the wrap to the next line is
+    /// deferred until the next printable character (xterm's "pending wrap").
+    pub pending_wrap: bool,
+    /// Last printed character, repeated by REP.
+    pub last_printed: Option<char>,
+    /// Scrolling region set by DECSTBM, as a half-open range of rows.
+    pub scroll_top: usize,
+    pub scroll_bottom: usize,
+    /// Lines scrolled off the top of the primary screen, oldest first.
+    pub scrollback: VecDeque<Row>,
+    pub scrollback_limit: usize,
+    /// Lines dropped from the front of `scrollback` so far, which keeps
+    /// absolute line indices stable while the ring evicts.
+    pub scrollback_evicted: usize,
+    /// Window title set with OSC 0/2; `None` until a program sets one.
+    pub title: Option<String>,
+    /// Icon name set with OSC 0/1.
+    pub icon_name: Option<String>,
+    /// Entries saved by XTWINOPS 22 and restored by 23.
+    pub title_stack: Vec<Option<String>>,
+    pub icon_name_stack: Vec<Option<String>>,
+    /// Working directory reported by the shell with OSC 7.
+    pub cwd: Option<PathBuf>,
+    pub hyperlinks: HyperlinkTable,
+    /// Link opened by OSC 8 and applied to printed cells until it is closed.
+    pub active_link: Option<LinkId>,
+    /// Link under the mouse pointer, which the renderer underlines.
+    pub hovered_link: Option<LinkId>,
+    /// OSC 52 requests, handled by `Terminal` according to the policy.
+    pub clipboard_requests: Vec<ClipboardRequest>,
+    /// Bytes to send back to the program, such as query replies.
+    pub replies: Vec<u8>,
+    /// How many lines the view is scrolled back from the bottom.
+    pub display_offset: usize,
+    /// Absolute lines highlighted as selected.
+    pub selection: Option<Range<usize>>,
+    /// The command being entered or run, as reported by shell integration.
+    command: CommandTracker,
+    /// The most recent command that finished.
+    pub last_command: Option<FinishedCommand>,
+    /// Commands finished since `Terminal` last collected them.
+    pub finished_commands: Vec<FinishedCommand>,
+    /// Notifications waiting to be shown by `Terminal`.
+    pub notifications: Vec<Notification>,
+    /// OSC 99 notifications still receiving chunks, by their id.
+    partial_notifications: HashMap<String, Notification>,
+    /// Colors in effect, as changed by OSC 4 and 10-12.
+    pub palette: Palette,
+    /// Colors from the color scheme, restored by OSC 104 and 110-112.
+    default_palette: Palette,
+    /// Kitty keyboard protocol flags pushed by programs. Each screen has its
+    /// own stack; the top entry is in effect.
+    pub keyboard_flags: Vec<KeyboardFlags>,
+    pub alt_keyboard_flags: Vec<KeyboardFlags>,
+    /// When the program began a synchronized update (mode 2026), during
+    /// which the screen isn't redrawn.
+    pub synchronized_since: Option<Instant>,
+    /// Images sent with the kitty graphics protocol and where they are shown.
+    pub graphics: ImageStore,
+    /// Size of a cell in pixels.
+    pub cell_size: (u32, u32),
+    /// The sixel image being received, between DCS `q` and ST.
+    sixel: Option<SixelDecoder>,
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, PartialEq)]
 pub struct Cell {
+    /// First character of the grapheme cluster shown in this cell.
     pub c: char,
+    /// Combining marks, joiners and other characters that belong to the same
+    /// grapheme cluster as `c`.
+    pub extra: Option<Box<str>>,
+    pub width: CellWidth,
     pub fg: Color,
     pub bg: Color,
     pub attrs: Attributes,
+    pub link: Option<LinkId>,
 }
 
-#[derive(Clone, Copy, Default)]
-pub struct Color {
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum CellWidth {
+    #[default]
+    Single,
+    /// The left half of a double-width character.
+    Wide,
+    /// The right half of a double-width character; it holds no text.
+    Spacer,
+}
+
+#[derive(Clone, Default)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    /// Set when the text continues on the next row because it auto-wrapped,
+    /// so that reflow can rejoin the logical line.
+    pub wrapped: bool,
+    pub marks: LineMarks,
+}
+
+/// Shell integration marks (OSC 133) on a line. After a reflow they belong
+/// to the first row of their logical line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineMarks {
+    /// A prompt starts on this line (`A`).
+    pub prompt: bool,
+    /// Command output starts on this line (`C`).
+    pub output: bool,
+    /// A command finished on this line (`D`).
+    pub command_end: bool,
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct CommandTracker {
+    /// Absolute line and column where the command line starts (`B`).
+    input: Option<(usize, usize)>,
+    /// Text of the command line, captured when it starts running (`C`).
+    text: String,
+    output_start: Option<usize>,
+    started: Option<Instant>,
+}
+
+/// A command whose start and end were reported with OSC 133.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinishedCommand {
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+    /// Absolute lines holding the command's output.
+    pub output: Range<usize>,
+}
+
+/// The foreground or background color of a cell. Indexed colors are
+/// resolved through the palette when drawn, so changing the palette with
+/// OSC 4 recolors text already on screen.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Color {
+    /// The palette's default foreground or background.
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(Rgb),
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Rgb {
     pub r: u8,
     pub g: u8,
     pub b: u8,
-    pub a: u8,
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
 pub struct Attributes {
     pub bold: bool,
+    pub dim: bool,
     pub italic: bool,
     pub underline: bool,
     pub strikethrough: bool,
     pub blink: bool,
+    pub reverse: bool,
+    pub hidden: bool,
+}
+
+/// The attributes applied to newly printed cells, as set by SGR.
+#[derive(Clone, Copy, Default)]
+pub struct Pen {
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: Attributes,
+}
+
+/// Terminal modes toggled with SM/RM and DECSET/DECRST.
+#[derive(Clone, Copy)]
+pub struct Modes {
+    /// DECAWM: wrap onto the next line when printing past the right margin.
+    pub auto_wrap: bool,
+    /// IRM: printed characters shift the rest of the line right.
+    pub insert: bool,
+    /// DECCKM: unmodified cursor keys send SS3 instead of CSI sequences.
+    pub application_cursor: bool,
+    /// DECTCEM (25): the cursor is drawn.
+    pub cursor_visible: bool,
+    pub mouse_tracking: MouseTracking,
+    pub mouse_encoding: MouseEncoding,
+    /// 1004: report focus changes with `CSI I` and `CSI O`.
+    pub focus_events: bool,
+    /// 2004: wrap pastes in `CSI 200 ~` and `CSI 201 ~`.
+    pub bracketed_paste: bool,
+    /// DECSDM (80): sixel images show at the top left of the screen and
+    /// leave the cursor alone, instead of scrolling with the text.
+    pub sixel_display: bool,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            auto_wrap: true,
+            insert: false,
+            application_cursor: false,
+            cursor_visible: true,
+            mouse_tracking: MouseTracking::Off,
+            mouse_encoding: MouseEncoding::Default,
+            focus_events: false,
+            bracketed_paste: false,
+            sixel_display: false,
+        }
+    }
+}
+
+/// Cursor state stored by DECSC / SCOSC.
+#[derive(Clone, Copy, Default)]
+pub struct SavedCursor {
+    pub x: usize,
+    pub y: usize,
+    pub pen: Pen,
 }
 
 pub struct Tab {
+    /// Stable identifier; unlike the index it survives closing other tabs.
+    pub id: usize,
+    /// The focused pane's title, or the title the tab was created with.
     pub title: String,
+    default_title: String,
     pub has_activity: bool,
-    pub process_id: u32,
+    pub panes: Vec<Pane>,
+    pub layout: PaneTree,
 }
 
-impl Terminal {
-    pub fn new(config: Config) -> Result<Self> {
+pub struct Pane {
+    pub id: PaneId,
+    pub emulator: Emulator,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::Rgb(Rgb::new(r, g, b))
+    }
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+    
+    /// xterm's default color for a 256-color palette index.
+    pub fn indexed(index: u8) -> Self {
+        const ANSI: [(u8, u8, u8); 16] = [
+            (0x00, 0x00, 0x00), (0xcd, 0x00, 0x00), (0x00, 0xcd, 0x00), (0xcd, 0xcd, 0x00),
+            (0x00, 0x00, 0xee), (0xcd, 0x00, 0xcd), (0x00, 0xcd, 0xcd), (0xe5, 0xe5, 0xe5),
+            (0x7f, 0x7f, 0x7f), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+            (0x5c, 0x5c, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+        ];
+        
+        match index {
+            0..=15 => {
+                let (r, g, b) = ANSI[index as usize];
+                Self::new(r, g, b)
+            }
+            16..=231 => {
+                let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                let i = index - 16;
+                Self::new(level(i / 36), level((i / 6) % 6), level(i % 6))
+            }
+            232..=255 => {
+                let gray = 8 + (index - 232) * 10;
+                Self::new(gray, gray, gray)
+            }
+        }
+    }
+}
+
+impl Cell {
+    /// The full grapheme cluster stored in this cell.
+    pub fn grapheme(&self) -> String {
+        let mut text = String::from(self.c);
+        if let Some(extra) = &self.extra {
+            text.push_str(extra);
+        }
+        text
+    }
+    
+    fn push_char(&mut self, c: char) {
+        let mut extra = self.extra.take().map(String::from).unwrap_or_default();
+        extra.push(c);
+        self.extra = Some(extra.into_boxed_str());
+    }
+}
+
+impl Row {
+    pub fn new(cols: usize) -> Self {
+        Self::filled(cols, Cell::default())
+    }
+    
+    fn filled(cols: usize, cell: Cell) -> Self {
+        Self {
+            cells: vec![cell; cols],
+            wrapped: false,
+            marks: LineMarks::default(),
+        }
+    }
+    
+    /// Length of the row without trailing default cells.
+    fn content_len(&self) -> usize {
+        let blank = Cell::default();
+        self.cells.iter().rposition(|cell| *cell != blank).map_or(0, |i| i + 1)
+    }
+}
+
+impl LineMarks {
+    fn merge(&mut self, other: LineMarks) {
+        self.prompt |= other.prompt;
+        self.output |= other.output;
+        self.command_end |= other.command_end;
+        self.exit_code = self.exit_code.or(other.exit_code);
+    }
+}
+
+impl Emulator {
+    pub fn new(config: Config) -> Self {
         let mut state = TerminalState::default();
         state.rows = 24;
         state.cols = 80;
-        state.buffer = vec![vec![Cell::default(); state.cols]; state.rows];
-        state.alt_buffer = vec![vec![Cell::default(); state.cols]; state.rows];
-        
-        state.tabs.push(Tab {
-            title: "Terminal".to_string(),
-            has_activity: false,
-            process_id: 0,
-        });
+        state.scroll_bottom = state.rows;
+        state.buffer = vec![Row::new(state.cols); state.rows];
+        state.alt_buffer = vec![Row::new(state.cols); state.rows];
+        state.scrollback_limit = config.scrollback_capacity(state.cols, std::mem::size_of::<Cell>(), 1);
+        state.default_palette = Palette::from_scheme(&config.colors);
+        state.palette = state.default_palette.clone();
+        state.graphics = ImageStore::new(config.image_cache_bytes(1));
+        state.cell_size = graphics::DEFAULT_CELL_SIZE;
         
-        Ok(Self {
+        Self {
             parser: Parser::new(),
+            apc: ApcScanner::default(),
             state,
             config,
-        })
+            pty: None,
+            working_directory: None,
+            process_id: 0,
+            exit_status: None,
+            mouse_button: None,
+            mouse_cell: None,
+            cache_sharers: 1,
+        }
+    }
+    
+    /// Spawns the configured shell on a PTY sized to this screen. The caller
+    /// is responsible for feeding the returned events back in.
+    pub fn spawn_shell(&mut self) -> Result<mpsc::UnboundedReceiver<PtyEvent>> {
+        let mut shell = self.config.shell.clone();
+        if let Some(dir) = &self.working_directory {
+            shell.working_directory = Some(dir.clone());
+        }
+        
+        let (pty, events) = Pty::spawn(
+            &shell,
+            self.state.rows as u16,
+            self.state.cols as u16,
+        )?;
+        self.process_id = pty.pid();
+        self.pty = Some(pty);
+        Ok(events)
+    }
+    
+    /// Sends input (keystrokes, pastes, replies) to the shell.
+    pub fn write_to_pty(&self, data: &[u8]) -> Result<()> {
+        match &self.pty {
+            Some(pty) => pty.write(data),
+            None => Ok(()),
+        }
     }
     
     pub fn resize(&mut self, rows: usize, cols: usize) {
-        self.state.rows = rows;
-        self.state.cols = cols;
-        self.state.buffer.resize(rows, vec![Cell::default(); cols]);
-        for row in &mut self.state.buffer {
-            row.resize(cols, Cell::default());
+        let (rows, cols) = (rows.max(1), cols.max(1));
+        if (rows, cols) == (self.state.rows, self.state.cols) {
+            return;
+        }
+        
+        self.state.scrollback_limit = self
+            .config
+            .scrollback_capacity(cols, std::mem::size_of::<Cell>(), self.cache_sharers);
+        self.state.resize(rows, cols);
+        
+        if let Some(pty) = &self.pty {
+            if let Err(e) = pty.resize(self.state.rows as u16, self.state.cols as u16) {
+                log::warn!("{:#}", e);
+            }
         }
     }
     
     pub fn process_input(&mut self, data: &[u8]) {
         for byte in data {
-            self.parser.advance(self, *byte);
+            self.parser.advance(&mut self.state, *byte);
+            if let Some(apc) = self.apc.advance(*byte) {
+                self.state.apc_dispatch(&apc);
+            }
         }
+        self.flush_replies();
     }
     
-    pub fn create_new_tab(&mut self, title: String) {
-        self.state.tabs.push(Tab {
-            title,
-            has_activity: false,
-            process_id: 0,
-        });
+    /// Limits the scrollback and images to this pane's share of the cache
+    /// budget when `panes` panes are open, dropping whatever no longer fits.
+    pub fn set_cache_share(&mut self, panes: usize) {
+        self.cache_sharers = panes;
+        self.state.scrollback_limit = self
+            .config
+            .scrollback_capacity(self.state.cols, std::mem::size_of::<Cell>(), panes);
+        self.state.graphics.set_limit(self.config.image_cache_bytes(panes));
+        self.state.trim_scrollback();
     }
     
-    pub fn mark_tab_activity(&mut self, tab_index: usize) {
-        if tab_index != self.state.active_tab && tab_index < self.state.tabs.len() {
-            self.state.tabs[tab_index].has_activity = true;
+    /// Sets the size of a cell in pixels, which sizes images placed without
+    /// an explicit number of rows and columns.
+    pub fn set_cell_size(&mut self, width: u32, height: u32) {
+        self.state.cell_size = (width.max(1), height.max(1));
+    }
+    
+    /// Sends a key press or release to the shell, encoded the way the
+    /// program asked for.
+    pub fn send_key(&self, event: &KeyEvent) -> Result<()> {
+        let state = &self.state;
+        let bytes = keyboard::encode(event, state.current_keyboard_flags(), state.modes.application_cursor);
+        if bytes.is_empty() {
+            return Ok(());
         }
+        self.write_to_pty(&bytes)
     }
     
-    pub fn switch_tab(&mut self, tab_index: usize) {
-        if tab_index < self.state.tabs.len() {
-            self.state.active_tab = tab_index;
-            self.state.tabs[tab_index].has_activity = false;
+    /// Reports a mouse event to the program if it enabled mouse tracking.
+    /// Returns false when the event is left to the terminal, which is always
+    /// the case with shift held so that shift+click still selects text.
+    pub fn send_mouse(&mut self, mut event: MouseEvent) -> Result<bool> {
+        match event.kind {
+            MouseEventKind::Press(button) if !button.is_wheel() => self.mouse_button = Some(button),
+            MouseEventKind::Release(button) if self.mouse_button == Some(button) => self.mouse_button = None,
+            MouseEventKind::Motion(_) => event.kind = MouseEventKind::Motion(self.mouse_button),
+            _ => {}
+        }
+        
+        let modes = self.state.modes;
+        if modes.mouse_tracking == MouseTracking::Off || event.modifiers.shift {
+            return Ok(false);
+        }
+        
+        if let MouseEventKind::Motion(_) = event.kind {
+            let cell = Some((event.col, event.row));
+            if cell == self.mouse_cell && modes.mouse_encoding != MouseEncoding::SgrPixels {
+                return Ok(true);
+            }
+            self.mouse_cell = cell;
+        }
+        
+        if let Some(report) = mouse::encode(&event, modes.mouse_tracking, modes.mouse_encoding) {
+            self.write_to_pty(&report)?;
+        }
+        Ok(true)
+    }
+    
+    /// Sends pasted text to the shell. With bracketed paste the text is
+    /// wrapped in markers, and any end marker inside it is removed so the
+    /// text can't end the paste early and run commands.
+    pub fn paste(&self, text: &str) -> Result<()> {
+        self.write_to_pty(&encode_paste(text, self.state.modes.bracketed_paste))
+    }
+    
+    /// Tells the program the window gained or lost focus, if it asked.
+    pub fn send_focus(&self, focused: bool) -> Result<()> {
+        if !self.state.modes.focus_events {
+            return Ok(());
+        }
+        self.write_to_pty(if focused { b"\x1b[I" } else { b"\x1b[O" })
+    }
+    
+    /// Writes queued replies to the shell. Without a shell attached they
+    /// stay queued.
+    fn flush_replies(&mut self) {
+        if self.pty.is_none() || self.state.replies.is_empty() {
+            return;
+        }
+        let replies = std::mem::take(&mut self.state.replies);
+        if let Err(e) = self.write_to_pty(&replies) {
+            log::warn!("Failed to reply to pane process: {:#}", e);
         }
     }
+    
+    fn process_exited(&mut self, status: ExitStatus) {
+        self.state.replies.clear();
+        self.pty = None;
+        self.exit_status = Some(status);
+    }
+    
+    pub fn is_alive(&self) -> bool {
+        self.exit_status.is_none()
+    }
 }
 
-impl Perform for Terminal {
-    fn print(&mut self, c: char) {
-        let current_buffer = if self.state.using_alt_buffer {
-            &mut self.state.alt_buffer
-        } else {
-            &mut self.state.buffer
+impl Tab {
+    fn new(id: usize, title: String, pane: Pane) -> Self {
+        Self {
+            id,
+            title: title.clone(),
+            default_title: title,
+            has_activity: false,
+            layout: PaneTree::new(pane.id),
+            panes: vec![pane],
+        }
+    }
+    
+    pub fn focused_pane(&self) -> &Pane {
+        let focused = self.layout.focused();
+        self.panes
+            .iter()
+            .find(|pane| pane.id == focused)
+            .unwrap_or(&self.panes[0])
+    }
+    
+    pub fn focused_emulator(&self) -> &Emulator {
+        &self.focused_pane().emulator
+    }
+    
+    fn pane_mut(&mut self, pane_id: PaneId) -> Option<&mut Pane> {
+        self.panes.iter_mut().find(|pane| pane.id == pane_id)
+    }
+    
+    /// Working directory of the focused pane, as reported by its shell.
+    pub fn cwd(&self) -> Option<&Path> {
+        self.focused_emulator().state.cwd.as_deref()
+    }
+    
+    fn update_title(&mut self) {
+        self.title = self
+            .focused_emulator()
+            .state
+            .title
+            .clone()
+            .unwrap_or_else(|| self.default_title.clone());
+    }
+    
+    /// Resizes every visible pane to its rectangle in `area`. Panes hidden
+    /// by zoom keep their size until they are shown again.
+    fn relayout(&mut self, area: Rect) {
+        for (pane_id, rect) in self.layout.layout(area) {
+            if let Some(pane) = self.pane_mut(pane_id) {
+                pane.emulator.resize(rect.rows, rect.cols);
+            }
+        }
+    }
+}
+
+impl Terminal {
+    pub fn new(config: Config) -> Result<Self> {
+        let mut terminal = Self {
+            config,
+            tabs: Vec::new(),
+            active_tab: 0,
+            next_tab_id: 0,
+            next_pane_id: 0,
+            rows: 24,
+            cols: 80,
+            cell_size: graphics::DEFAULT_CELL_SIZE,
+            clipboard: clipboard::system_or_memory(),
+            pending_clipboard: VecDeque::new(),
+            notifier: notifications::system_or_memory(),
+            recorder: SessionRecorder::new(),
+            wakeup: None,
         };
+        terminal.create_new_tab("Terminal".to_string());
         
-        if self.state.cursor_y < self.state.rows && self.state.cursor_x < self.state.cols {
-            current_buffer[self.state.cursor_y][self.state.cursor_x].c = c;
-            self.state.cursor_x += 1;
-            
-            if self.state.cursor_x >= self.state.cols {
-                self.state.cursor_x = 0;
-                self.state.cursor_y += 1;
+        Ok(terminal)
+    }
+    
+    /// Spawns the configured shell for the pane with the given id and streams
+    /// its output into that pane from a background task.
+    pub async fn spawn_shell(terminal: Arc<Mutex<Terminal>>, pane_id: PaneId) -> Result<()> {
+        let mut events = {
+            let mut term = terminal.lock().await;
+            let pane = term
+                .find_pane_mut(pane_id)
+                .ok_or_else(|| anyhow::anyhow!("No pane with id {}", pane_id))?;
+            pane.emulator.spawn_shell()?
+        };
+        
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let mut term = terminal.lock().await;
+                match event {
+                    PtyEvent::Output(data) => term.process_output(pane_id, &data),
+                    PtyEvent::Exited(status) => term.process_exited(pane_id, status),
+                }
+                if let Some(wakeup) = &term.wakeup {
+                    wakeup();
+                }
             }
+        });
+        
+        Ok(())
+    }
+    
+    /// Creates a tab, switches to it and starts a shell in it.
+    pub async fn open_tab(terminal: Arc<Mutex<Terminal>>, title: String) -> Result<usize> {
+        let (tab_id, pane_id) = {
+            let mut term = terminal.lock().await;
+            let tab_id = term.create_new_tab(title);
+            let index = term.tabs.len() - 1;
+            term.switch_tab(index);
+            (tab_id, term.focused_pane_id())
+        };
+        Self::spawn_shell(terminal, pane_id).await?;
+        
+        Ok(tab_id)
+    }
+    
+    /// Splits the focused pane of the active tab and starts a shell in the
+    /// new pane.
+    pub async fn open_split(terminal: Arc<Mutex<Terminal>>, direction: SplitDirection) -> Result<PaneId> {
+        let pane_id = terminal
+            .lock()
+            .await
+            .split_pane(direction)
+            .ok_or_else(|| anyhow::anyhow!("No room to split the focused pane"))?;
+        Self::spawn_shell(terminal, pane_id).await?;
+        
+        Ok(pane_id)
+    }
+    
+    pub fn tabs(&self) -> &[Tab] {
+        &self.tabs
+    }
+    
+    pub fn active_tab(&self) -> usize {
+        self.active_tab
+    }
+    
+    pub fn focused_pane_id(&self) -> PaneId {
+        self.tabs[self.active_tab].layout.focused()
+    }
+    
+    /// The screen of the focused pane in the active tab.
+    pub fn active_state(&self) -> &TerminalState {
+        &self.tabs[self.active_tab].focused_emulator().state
+    }
+    
+    /// Screen of a pane in the active tab.
+    pub fn pane_state(&self, pane_id: PaneId) -> Option<&TerminalState> {
+        self.tabs[self.active_tab]
+            .panes
+            .iter()
+            .find(|pane| pane.id == pane_id)
+            .map(|pane| &pane.emulator.state)
+    }
+    
+    /// Cell rectangles of the visible panes in the active tab, for rendering.
+    pub fn pane_layout(&self) -> Vec<(PaneId, Rect)> {
+        self.tabs[self.active_tab].layout.layout(self.area())
+    }
+    
+    fn area(&self) -> Rect {
+        Rect::new(0, 0, self.cols, self.rows)
+    }
+    
+    fn find_pane_mut(&mut self, pane_id: PaneId) -> Option<&mut Pane> {
+        self.tabs.iter_mut().find_map(|tab| tab.pane_mut(pane_id))
+    }
+    
+    fn tab_index_of_pane(&self, pane_id: PaneId) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.layout.contains(pane_id))
+    }
+    
+    /// Creates a pane whose shell will start in the focused pane's working
+    /// directory, when that is known and still exists.
+    fn new_pane(&mut self) -> Pane {
+        let id = self.next_pane_id;
+        self.next_pane_id += 1;
+        
+        let mut emulator = Emulator::new(self.config.clone());
+        emulator.resize(self.rows, self.cols);
+        emulator.set_cell_size(self.cell_size.0, self.cell_size.1);
+        emulator.working_directory = self
+            .tabs
+            .get(self.active_tab)
+            .and_then(Tab::cwd)
+            .filter(|dir| dir.is_dir())
+            .map(Path::to_path_buf);
+        Pane { id, emulator }
+    }
+    
+    /// Sends input to the focused pane's shell.
+    pub fn write_to_pty(&self, data: &[u8]) -> Result<()> {
+        self.tabs[self.active_tab].focused_emulator().write_to_pty(data)
+    }
+    
+    /// Divides the cache budget among the panes of all tabs; called whenever
+    /// a pane is added or closed.
+    fn share_cache(&mut self) {
+        let panes = self.tabs.iter().map(|tab| tab.panes.len()).sum();
+        for pane in self.tabs.iter_mut().flat_map(|tab| tab.panes.iter_mut()) {
+            pane.emulator.set_cache_share(panes);
         }
     }
     
-    fn execute(&mut self, byte: u8) {
-        match byte {
-            b'\n' => {
-                self.state.cursor_x = 0;
-                self.state.cursor_y += 1;
+    /// Sets the size of a cell in pixels for every pane.
+    pub fn set_cell_size(&mut self, width: u32, height: u32) {
+        self.cell_size = (width, height);
+        for pane in self.tabs.iter_mut().flat_map(|tab| tab.panes.iter_mut()) {
+            pane.emulator.set_cell_size(width, height);
+        }
+    }
+    
+    /// Size of the terminal area in cells, as `(rows, cols)`.
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+    
+    /// The visible pane under a point, in pixels from the top left of the
+    /// terminal area, with the cell under it relative to the pane. Points in
+    /// the padding past the last whole cell belong to the nearest cell.
+    fn pane_at(&self, position: (f64, f64), cell_size: (f64, f64)) -> Option<(PaneId, Rect, usize, usize)> {
+        let col = ((position.0.max(0.0) / cell_size.0) as usize).min(self.cols.saturating_sub(1));
+        let row = ((position.1.max(0.0) / cell_size.1) as usize).min(self.rows.saturating_sub(1));
+        self.pane_layout()
+            .into_iter()
+            .find(|(_, rect)| (rect.x..rect.x + rect.cols).contains(&col) && (rect.y..rect.y + rect.rows).contains(&row))
+            .map(|(pane_id, rect)| (pane_id, rect, row - rect.y, col - rect.x))
+    }
+    
+    /// Reports a mouse event to the pane under the pointer. `position` is in
+    /// pixels from the top left of the terminal area and `cell_size` is the
+    /// size of one cell in pixels. Returns false when the pane's program
+    /// doesn't take the event, so the terminal can handle it itself.
+    pub fn send_mouse(
+        &mut self,
+        kind: MouseEventKind,
+        modifiers: Modifiers,
+        position: (f64, f64),
+        cell_size: (f64, f64),
+    ) -> Result<bool> {
+        let Some((pane_id, rect, row, col)) = self.pane_at(position, cell_size) else {
+            return Ok(false);
+        };
+        let Some(pane) = self.find_pane_mut(pane_id) else {
+            return Ok(false);
+        };
+        
+        // Pixel positions stay inside the pane too
+        let (width, height) = (rect.cols as f64 * cell_size.0, rect.rows as f64 * cell_size.1);
+        pane.emulator.send_mouse(MouseEvent {
+            kind,
+            modifiers,
+            col,
+            row,
+            x: (position.0 - rect.x as f64 * cell_size.0).clamp(0.0, width - 1.0),
+            y: (position.1 - rect.y as f64 * cell_size.1).clamp(0.0, height - 1.0),
+        })
+    }
+    
+    pub fn paste(&self, text: &str) -> Result<()> {
+        self.tabs[self.active_tab].focused_emulator().paste(text)
+    }
+    
+    pub fn paste_from_clipboard(&mut self) -> Result<()> {
+        let text = self.clipboard.load(Selection::Clipboard)?;
+        self.paste(&text)
+    }
+    
+    /// Reports window focus changes to the focused pane.
+    pub fn set_window_focus(&self, focused: bool) -> Result<()> {
+        self.tabs[self.active_tab].focused_emulator().send_focus(focused)
+    }
+    
+    /// Whether the visible panes can be drawn, which they can't while one of
+    /// them is in the middle of a synchronized update.
+    pub fn should_render(&mut self, now: Instant) -> bool {
+        let visible: Vec<PaneId> = self.pane_layout().into_iter().map(|(id, _)| id).collect();
+        let tab = &mut self.tabs[self.active_tab];
+        tab.panes
+            .iter_mut()
+            .filter(|pane| visible.contains(&pane.id))
+            .all(|pane| !pane.emulator.state.synchronized_update_pending(now))
+    }
+    
+    /// When the earliest synchronized update holding back rendering times
+    /// out, so the window can draw then even if the program never ends it.
+    pub fn synchronized_update_deadline(&self) -> Option<Instant> {
+        let visible: Vec<PaneId> = self.pane_layout().into_iter().map(|(id, _)| id).collect();
+        self.tabs[self.active_tab]
+            .panes
+            .iter()
+            .filter(|pane| visible.contains(&pane.id))
+            .filter_map(|pane| pane.emulator.state.synchronized_since)
+            .min()
+            .map(|since| since + SYNCHRONIZED_UPDATE_TIMEOUT)
+    }
+    
+    /// Sends a key event to the focused pane's shell.
+    pub fn send_key(&self, event: &KeyEvent) -> Result<()> {
+        self.tabs[self.active_tab].focused_emulator().send_key(event)
+    }
+    
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.rows = rows;
+        self.cols = cols;
+        let area = self.area();
+        for tab in &mut self.tabs {
+            tab.relayout(area);
+        }
+    }
+    
+    /// Feeds bytes to the focused pane.
+    pub fn process_input(&mut self, data: &[u8]) {
+        let pane_id = self.focused_pane_id();
+        if let Some(pane) = self.find_pane_mut(pane_id) {
+            pane.emulator.process_input(data);
+        }
+    }
+    
+    /// Feeds output from a pane's shell into that pane, lighting up the
+    /// activity indicator of its tab when it is in the background.
+    pub fn process_output(&mut self, pane_id: PaneId, data: &[u8]) {
+        if let Some(pane) = self.find_pane_mut(pane_id) {
+            pane.emulator.process_input(data);
+        }
+        self.handle_clipboard_requests(pane_id);
+        self.record_finished_commands(pane_id);
+        self.deliver_notifications(pane_id);
+        if let Some(index) = self.tab_index_of_pane(pane_id) {
+            self.tabs[index].update_title();
+            if self.config.features.activity_indicators {
+                self.mark_tab_activity(index);
             }
-            b'\r' => {
-                self.state.cursor_x = 0;
+        }
+    }
+    
+    pub fn set_notifier(&mut self, notifier: Box<dyn Notifier>) {
+        self.notifier = notifier;
+    }
+    
+    pub fn set_wakeup(&mut self, wakeup: Box<dyn Fn() + Send>) {
+        self.wakeup = Some(wakeup);
+    }
+    
+    /// Shows the notifications a pane asked for and flags its tab.
+    fn deliver_notifications(&mut self, pane_id: PaneId) {
+        let Some(pane) = self.find_pane_mut(pane_id) else {
+            return;
+        };
+        let notifications = std::mem::take(&mut pane.emulator.state.notifications);
+        if notifications.is_empty() {
+            return;
+        }
+        
+        for notification in &notifications {
+            if let Err(e) = self.notifier.notify(notification) {
+                log::warn!("Failed to show notification: {:#}", e);
             }
-            b'\t' => {
-                self.state.cursor_x = (self.state.cursor_x + 8) & !7;
+        }
+        if let Some(index) = self.tab_index_of_pane(pane_id) {
+            self.mark_tab_activity(index);
+        }
+    }
+    
+    fn record_finished_commands(&mut self, pane_id: PaneId) {
+        let Some(pane) = self.find_pane_mut(pane_id) else {
+            return;
+        };
+        for command in std::mem::take(&mut pane.emulator.state.finished_commands) {
+            if let Err(e) = self.recorder.record_command(command.command, command.exit_code, command.duration) {
+                log::warn!("Failed to record command: {:#}", e);
+            }
+        }
+    }
+    
+    /// Scrolls the focused pane to its previous or next prompt.
+    pub fn scroll_to_prompt(&mut self, forward: bool) -> bool {
+        let pane_id = self.focused_pane_id();
+        self.find_pane_mut(pane_id)
+            .is_some_and(|pane| pane.emulator.state.scroll_to_prompt(forward))
+    }
+    
+    pub fn select_last_command_output(&mut self) -> bool {
+        let pane_id = self.focused_pane_id();
+        self.find_pane_mut(pane_id)
+            .is_some_and(|pane| pane.emulator.state.select_last_command_output())
+    }
+    
+    /// Copies the output of the focused pane's last command to the clipboard.
+    pub fn copy_last_command_output(&mut self) -> Result<()> {
+        let Some(text) = self.active_state().last_command_output() else {
+            return Ok(());
+        };
+        self.clipboard.store(Selection::Clipboard, text)
+    }
+    
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn Clipboard>) {
+        self.clipboard = clipboard;
+    }
+    
+    /// Applies the OSC 52 policy to the clipboard requests a pane made.
+    fn handle_clipboard_requests(&mut self, pane_id: PaneId) {
+        let Some(pane) = self.find_pane_mut(pane_id) else {
+            return;
+        };
+        let requests = std::mem::take(&mut pane.emulator.state.clipboard_requests);
+        
+        for request in requests {
+            match (self.config.clipboard.osc52, &request) {
+                (ClipboardPolicy::Deny, _) | (ClipboardPolicy::WriteOnly, ClipboardRequest::Load(_)) => {
+                    log::debug!("Denied clipboard request from pane {}: {:?}", pane_id, request);
+                }
+                // A newer request replaces the one the pane is waiting on,
+                // so a program can't pile up prompts
+                (ClipboardPolicy::Ask, _) => {
+                    self.pending_clipboard.retain(|(pending, _)| *pending != pane_id);
+                    self.pending_clipboard.push_back((pane_id, request));
+                }
+                _ => self.run_clipboard_request(pane_id, request),
+            }
+        }
+    }
+    
+    /// The oldest clipboard request awaiting the user's decision.
+    pub fn pending_clipboard_request(&self) -> Option<&(PaneId, ClipboardRequest)> {
+        self.pending_clipboard.front()
+    }
+    
+    /// Carries out or drops the oldest pending clipboard request.
+    pub fn answer_clipboard_request(&mut self, allow: bool) {
+        if let Some((pane_id, request)) = self.pending_clipboard.pop_front() {
+            if allow {
+                self.run_clipboard_request(pane_id, request);
             }
-            _ => {}
         }
     }
     
-    fn hook(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _c: char) {}
-    fn put(&mut self, _byte: u8) {}
-    fn unhook(&mut self) {}
-    fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {}
-    fn csi_dispatch(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _c: char) {}
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, _byte: u8) {}
-}
\ No newline at end of file
+    fn run_clipboard_request(&mut self, pane_id: PaneId, request: ClipboardRequest) {
+        let max_bytes = self.config.clipboard.osc52_max_bytes;
+        match request {
+            ClipboardRequest::Store(_, text) if text.len() > max_bytes => {
+                log::warn!("Ignoring {} byte OSC 52 copy over the {} byte limit", text.len(), max_bytes);
+            }
+            ClipboardRequest::Store(selection, text) => {
+                if let Err(e) = self.clipboard.store(selection, text) {
+                    log::warn!("Failed to copy to clipboard: {:#}", e);
+                }
+            }
+            ClipboardRequest::Load(selection) => {
+                let text = match self.clipboard.load(selection) {
+                    Ok(text) if text.len() <= max_bytes => text,
+                    Ok(text) => {
+                        log::warn!("Not sending {} bytes of clipboard over the {} byte limit", text.len(), max_bytes);
+                        return;
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to read clipboard: {:#}", e);
+                        return;
+                    }
+                };
+                if let Some(pane) = self.find_pane_mut(pane_id) {
+                    let reply = format!("\x1b]52;{};{}\x1b\\", selection.osc52_code(), BASE64.encode(text));
+                    pane.emulator.state.replies.extend_from_slice(reply.as_bytes());
+                    pane.emulator.flush_replies();
+                }
+            }
+        }
+    }
+    
+    fn process_exited(&mut self, pane_id: PaneId, status: ExitStatus) {
+        log::info!("Process in pane {} exited with {}", pane_id, status);
+        if let Some(pane) = self.find_pane_mut(pane_id) {
+            pane.emulator.process_exited(status);
+        }
+        if let Some(index) = self.tab_index_of_pane(pane_id) {
+            self.mark_tab_activity(index);
+        }
+    }
+    
+    /// Adds a tab without starting a process in it and returns its id.
+    pub fn create_new_tab(&mut self, title: String) -> usize {
+        let id = self.next_tab_id;
+        self.next_tab_id += 1;
+        
+        let pane = self.new_pane();
+        let mut tab = Tab::new(id, title, pane);
+        let kind = self.config.layouts.initial();
+        tab.layout.set_kind(kind, self.config.layouts.options(kind));
+        self.tabs.push(tab);
+        self.share_cache();
+        id
+    }
+    
+    /// Closes a tab, hanging up its processes. The last tab is never closed.
+    pub fn close_tab(&mut self, tab_index: usize) {
+        if tab_index < self.tabs.len() && self.tabs.len() > 1 {
+            self.tabs.remove(tab_index);
+            self.share_cache();
+            // Goes through switch_tab so a tab that becomes active is seen
+            let active = if self.active_tab >= tab_index && self.active_tab > 0 {
+                self.active_tab - 1
+            } else {
+                self.active_tab
+            };
+            self.switch_tab(active);
+        }
+    }
+    
+    /// Splits the focused pane of the active tab without starting a process
+    /// and returns the new pane's id, or `None` when there is no room left.
+    pub fn split_pane(&mut self, direction: SplitDirection) -> Option<PaneId> {
+        let pane = self.new_pane();
+        let pane_id = pane.id;
+        let area = self.area();
+        let tab = &mut self.tabs[self.active_tab];
+        if !tab.layout.split(direction, pane_id, area) {
+            return None;
+        }
+        tab.panes.push(pane);
+        tab.relayout(area);
+        tab.update_title();
+        self.share_cache();
+        Some(pane_id)
+    }
+    
+    /// Closes the focused pane, or the whole tab when it is the last pane.
+    pub fn close_pane(&mut self) {
+        let area = self.area();
+        let tab = &mut self.tabs[self.active_tab];
+        let pane_id = tab.layout.focused();
+        if tab.layout.close(pane_id) {
+            tab.panes.retain(|pane| pane.id != pane_id);
+            tab.relayout(area);
+            tab.update_title();
+            self.share_cache();
+        } else {
+            self.close_tab(self.active_tab);
+        }
+    }
+    
+    pub fn focus_pane(&mut self, direction: Direction) {
+        let area = self.area();
+        let tab = &mut self.tabs[self.active_tab];
+        if tab.layout.focus_direction(direction, area) {
+            tab.relayout(area);
+            tab.update_title();
+        }
+    }
+    
+    /// Grows the focused pane along `direction` by `delta` of the split it
+    /// belongs to; a negative `delta` shrinks it.
+    pub fn resize_pane(&mut self, direction: SplitDirection, delta: f32) {
+        let area = self.area();
+        let tab = &mut self.tabs[self.active_tab];
+        if tab.layout.resize(direction, delta) {
+            tab.relayout(area);
+        }
+    }
+    
+    pub fn toggle_zoom(&mut self) {
+        let area = self.area();
+        let tab = &mut self.tabs[self.active_tab];
+        tab.layout.toggle_zoom();
+        tab.relayout(area);
+    }
+    
+    /// Updates the link under the pointer from its position, as in
+    /// `send_mouse`, and clears it in the other panes. Returns whether any
+    /// pane's hovered link changed, in which case the panes need redrawing.
+    pub fn hover(&mut self, position: (f64, f64), cell_size: (f64, f64)) -> bool {
+        let target = self.pane_at(position, cell_size);
+        let mut changed = false;
+        for pane in &mut self.tabs[self.active_tab].panes {
+            let state = &mut pane.emulator.state;
+            changed |= match target {
+                Some((pane_id, _, row, col)) if pane_id == pane.id => state.hover(row, col),
+                _ => state.hovered_link.take().is_some(),
+            };
+        }
+        changed
+    }
+    
+    /// The link under the pointer, in whichever pane of the active tab it is.
+    pub fn hovered_link(&self) -> Option<&Hyperlink> {
+        self.tabs[self.active_tab].panes.iter().find_map(|pane| {
+            let state = &pane.emulator.state;
+            state.hovered_link.and_then(|link| state.hyperlink(link))
+        })
+    }
+    
+    /// Opens the link under the pointer with the configured opener.
+    pub fn open_hovered_link(&self) -> Result<()> {
+        let Some(link) = self.hovered_link() else {
+            return Ok(());
+        };
+        hyperlink::open(&self.config.hyperlinks, &link.uri)
+    }
+    
+    /// Rearranges the panes of the active tab with another layout.
+    pub fn set_layout(&mut self, kind: LayoutKind) {
+        let area = self.area();
+        let options = self.config.layouts.options(kind);
+        let tab = &mut self.tabs[self.active_tab];
+        tab.layout.set_kind(kind, options);
+        tab.relayout(area);
+    }
+    
+    /// Switches the active tab to the next enabled layout.
+    pub fn next_layout(&mut self) {
+        let current = self.tabs[self.active_tab].layout.kind();
+        self.set_layout(self.config.layouts.next(current));
+    }
+    
+    pub fn mark_tab_activity(&mut self, tab_index: usize) {
+        if tab_index != self.active_tab && tab_index < self.tabs.len() {
+            self.tabs[tab_index].has_activity = true;
+        }
+    }
+    
+    pub fn switch_tab(&mut self, tab_index: usize) {
+        if tab_index < self.tabs.len() {
+            self.active_tab = tab_index;
+            self.tabs[tab_index].has_activity = false;
+        }
+    }
+}
+
+
+impl TerminalState {
+    /// Absolute index of the oldest line still held in scrollback.
+    pub fn first_line(&self) -> usize {
+        self.scrollback_evicted
+    }
+    
+    /// One past the absolute index of the bottom row of the primary screen.
+    pub fn end_line(&self) -> usize {
+        self.scrollback_evicted + self.scrollback.len() + self.buffer.len()
+    }
+    
+    /// Returns a line by absolute index, covering scrollback followed by the
+    /// primary screen. Lines that have been evicted return `None`.
+    pub fn line(&self, index: usize) -> Option<&Row> {
+        let index = index.checked_sub(self.scrollback_evicted)?;
+        match index.checked_sub(self.scrollback.len()) {
+            None => self.scrollback.get(index),
+            Some(row) => self.buffer.get(row),
+        }
+    }
+    
+    /// Resizes both screens. The primary screen and scrollback are reflowed
+    /// so wrapped lines follow the new width and the cursor stays on the same
+    /// character; the alternate screen is just cropped or padded, since
+    /// full-screen programs redraw it themselves.
+    fn resize(&mut self, rows: usize, cols: usize) {
+        let (x, y) = if self.using_alt_buffer { self.main_cursor } else { (self.cursor_x, self.cursor_y) };
+        
+        let mut lines: Vec<Row> = self.scrollback.drain(..).collect();
+        let cursor_line = lines.len() + y;
+        lines.append(&mut self.buffer);
+        let (mut lines, cursor_line, x) = reflow(lines, cols, cursor_line, x);
+        
+        // Keep the cursor on screen, preferring to push lines into scrollback
+        // rather than dropping the ones below the cursor.
+        let start = lines.len().saturating_sub(rows).min(cursor_line);
+        let mut screen = lines.split_off(start);
+        screen.truncate(rows);
+        screen.resize(rows, Row::new(cols));
+        self.buffer = screen;
+        self.scrollback = lines.into();
+        
+        let primary_cursor = (x.min(cols - 1), cursor_line - start);
+        if self.using_alt_buffer {
+            // The cursor saved by 1049 is where the anchor was, and follows it
+            if let Some(saved) = self.saved_cursor.as_mut().filter(|saved| (saved.x, saved.y) == (x, y)) {
+                (saved.x, saved.y) = primary_cursor;
+            }
+            self.main_cursor = primary_cursor;
+            for row in &mut self.alt_buffer {
+                row.cells.resize(cols, Cell::default());
+            }
+            self.alt_buffer.resize(rows, Row::new(cols));
+        } else {
+            (self.cursor_x, self.cursor_y) = primary_cursor;
+        }
+        
+        self.rows = rows;
+        self.cols = cols;
+        self.pending_wrap = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows;
+        self.move_cursor_to(self.cursor_x, self.cursor_y);
+        self.trim_scrollback();
+    }
+    
+    fn push_scrollback(&mut self, line: Row) {
+        self.scrollback.push_back(line);
+        self.trim_scrollback();
+    }
+    
+    fn trim_scrollback(&mut self) {
+        while self.scrollback.len() > self.scrollback_limit {
+            self.scrollback.pop_front();
+            self.scrollback_evicted += 1;
+        }
+        self.graphics.prune(self.scrollback_evicted);
+    }
+    
+    fn blank_line(&self) -> Row {
+        Row::filled(self.cols, self.blank_cell())
+    }
+    
+    /// Scrolls the scrolling region up by `count` lines. Lines leaving the
+    /// top of the primary screen go to scrollback, as in xterm; the alternate
+    /// screen and regions with a top margin have none.
+    fn scroll_up(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let keep = top == 0 && !self.using_alt_buffer;
+        let blank = self.blank_line();
+        for _ in 0..count.min(bottom - top) {
+            let line = self.grid_mut().remove(top);
+            self.grid_mut().insert(bottom - 1, blank.clone());
+            if keep {
+                self.push_scrollback(line);
+            }
+        }
+    }
+    
+    /// Scrolls the scrolling region down by `count` lines, dropping lines at
+    /// the bottom margin.
+    fn scroll_down(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let blank = self.blank_line();
+        for _ in 0..count.min(bottom - top) {
+            self.grid_mut().remove(bottom - 1);
+            self.grid_mut().insert(top, blank.clone());
+        }
+    }
+    
+    /// IND: moves down a line, scrolling when on the bottom margin.
+    fn linefeed(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor_y + 1 == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor_y + 1 < self.rows {
+            self.cursor_y += 1;
+        }
+    }
+    
+    /// RI: moves up a line, scrolling when on the top margin.
+    fn reverse_index(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor_y == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.cursor_y = self.cursor_y.saturating_sub(1);
+        }
+    }
+    
+    fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.rows);
+        if top + 1 < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.move_cursor_to(0, 0);
+        }
+    }
+    
+    fn save_cursor(&mut self) {
+        let saved = SavedCursor {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            pen: self.pen,
+        };
+        if self.using_alt_buffer {
+            self.alt_saved_cursor = Some(saved);
+        } else {
+            self.saved_cursor = Some(saved);
+        }
+    }
+    
+    /// DECRC: restores the cursor saved for the active screen, or homes the
+    /// cursor with default attributes when nothing was saved.
+    fn restore_cursor(&mut self) {
+        let saved = if self.using_alt_buffer {
+            self.alt_saved_cursor
+        } else {
+            self.saved_cursor
+        };
+        let saved = saved.unwrap_or_default();
+        self.pen = saved.pen;
+        self.move_cursor_to(saved.x, saved.y);
+    }
+    
+    /// XTWINOPS 22: saves the icon name (`which` 1), the window title (2) or
+    /// both (0). Like xterm, the stacks hold at most ten entries.
+    fn push_title(&mut self, which: u16) {
+        fn push(stack: &mut Vec<Option<String>>, entry: Option<String>) {
+            if stack.len() == TITLE_STACK_LIMIT {
+                stack.remove(0);
+            }
+            stack.push(entry);
+        }
+        
+        if which != 2 {
+            push(&mut self.icon_name_stack, self.icon_name.clone());
+        }
+        if which != 1 {
+            push(&mut self.title_stack, self.title.clone());
+        }
+    }
+    
+    /// XTWINOPS 23: restores what `push_title` saved.
+    fn pop_title(&mut self, which: u16) {
+        if which != 2 {
+            if let Some(icon_name) = self.icon_name_stack.pop() {
+                self.icon_name = icon_name;
+            }
+        }
+        if which != 1 {
+            if let Some(title) = self.title_stack.pop() {
+                self.title = title;
+            }
+        }
+    }
+    
+    fn open_link(&mut self, params: &[u8], uri: String) {
+        if self.hyperlinks.len() >= HYPERLINK_GC_THRESHOLD {
+            self.collect_hyperlinks();
+        }
+        
+        let id = String::from_utf8_lossy(params)
+            .split(':')
+            .find_map(|param| param.strip_prefix("id="))
+            .filter(|id| !id.is_empty())
+            .map(String::from);
+        self.active_link = Some(self.hyperlinks.insert(id, uri));
+    }
+    
+    /// Drops hyperlinks that no cell on either screen or in scrollback
+    /// refers to any more.
+    fn collect_hyperlinks(&mut self) {
+        let mut used = std::collections::HashSet::new();
+        used.extend(self.active_link);
+        for row in self.scrollback.iter().chain(&self.buffer).chain(&self.alt_buffer) {
+            used.extend(row.cells.iter().filter_map(|cell| cell.link));
+        }
+        self.hyperlinks.retain(|link| used.contains(&link));
+        if self.hovered_link.is_some_and(|link| !used.contains(&link)) {
+            self.hovered_link = None;
+        }
+    }
+    
+    /// The link of the cell at `row`, `col` of the visible screen.
+    pub fn link_at(&self, row: usize, col: usize) -> Option<LinkId> {
+        self.grid().get(row)?.cells.get(col)?.link
+    }
+    
+    pub fn hyperlink(&self, link: LinkId) -> Option<&Hyperlink> {
+        self.hyperlinks.get(link)
+    }
+    
+    /// Updates the link under the pointer. Returns whether it changed, in
+    /// which case the screen needs redrawing.
+    pub fn hover(&mut self, row: usize, col: usize) -> bool {
+        let link = self.link_at(row, col);
+        let changed = link != self.hovered_link;
+        self.hovered_link = link;
+        changed
+    }
+    
+    /// Whether the renderer should underline `cell` as a hovered link.
+    pub fn is_hovered(&self, cell: &Cell) -> bool {
+        cell.link.is_some() && cell.link == self.hovered_link
+    }
+    
+    /// Rows in view from the top, taking the scroll position into account,
+    /// with their absolute line. Rows of the alternate screen have none.
+    pub fn visible_rows(&self) -> Vec<(Option<usize>, &Row)> {
+        if self.using_alt_buffer {
+            return self.alt_buffer.iter().map(|row| (None, row)).collect();
+        }
+        let screen = self.screen_lines();
+        let top = screen.start - self.display_offset.min(self.scrollback.len());
+        (top..top + self.rows)
+            .filter_map(|line| self.line(line).map(|row| (Some(line), row)))
+            .collect()
+    }
+    
+    /// Absolute lines of the screen, below the scrollback.
+    fn screen_lines(&self) -> Range<usize> {
+        let top = self.scrollback_evicted + self.scrollback.len();
+        top..top + self.rows
+    }
+    
+    /// Image placements in view, bottom-most z-index first, for rendering.
+    pub fn visible_placements(&self) -> Vec<&Placement> {
+        let screen = self.screen_lines();
+        let top = screen.start.saturating_sub(self.display_offset);
+        self.graphics.visible(top..top + self.rows, self.using_alt_buffer)
+    }
+    
+    fn apc_dispatch(&mut self, data: &[u8]) {
+        match data.split_first() {
+            Some((b'G', command)) => self.graphics_command(command),
+            _ => log::trace!("Unhandled APC {:?}", String::from_utf8_lossy(data)),
+        }
+    }
+    
+    /// Runs a kitty graphics protocol command and answers it. Like kitty,
+    /// only commands that name an image get a response, deletions never do,
+    /// and `q` silences OK or all responses.
+    fn graphics_command(&mut self, data: &[u8]) {
+        let command = match GraphicsCommand::parse(data) {
+            Ok(command) => command,
+            Err(e) => return log::debug!("Invalid graphics command: {:#}", e),
+        };
+        let Some((mut command, received)) = self.graphics.receive(command) else {
+            return;
+        };
+        let result = received.and_then(|()| self.run_graphics_command(&mut command));
+        
+        let named = command.image_id != 0 || command.image_number != 0;
+        if !named || command.action == Action::Delete {
+            return;
+        }
+        let message = match result {
+            Ok(()) if command.quiet == 0 => "OK".to_string(),
+            Err(e) if command.quiet < 2 => {
+                log::debug!("Graphics command failed: {:#}", e);
+                let message = format!("{:#}", e);
+                let coded = message.split_once(':').is_some_and(|(code, _)| {
+                    code.len() > 1 && code.starts_with('E') && code.bytes().all(|b| b.is_ascii_uppercase())
+                });
+                if coded { message } else { format!("EINVAL:{}", message) }
+            }
+            _ => return,
+        };
+        self.reply(&command.response(&message));
+    }
+    
+    fn run_graphics_command(&mut self, command: &mut GraphicsCommand) -> Result<()> {
+        let limit = self.graphics.limit();
+        match command.action {
+            Action::Query => graphics::load(command, limit).map(|_| ()),
+            Action::Transmit | Action::TransmitAndPut => {
+                let (width, height, pixels) = graphics::load(command, limit)?;
+                let id = self
+                    .graphics
+                    .insert(command.image_id, command.image_number, width, height, pixels)?;
+                // Responses to numbered images tell the program the id chosen
+                if command.image_number != 0 {
+                    command.image_id = id;
+                }
+                if command.action == Action::TransmitAndPut {
+                    self.put_image(id, command)?;
+                }
+                Ok(())
+            }
+            Action::Put => {
+                let id = match (command.image_id, command.image_number) {
+                    (0, 0) => anyhow::bail!("EINVAL:Put needs an image id or number"),
+                    (0, number) => self
+                        .graphics
+                        .find_number(number)
+                        .ok_or_else(|| anyhow::anyhow!("ENOENT:No image with number {}", number))?,
+                    (id, _) => id,
+                };
+                command.image_id = id;
+                self.put_image(id, command)
+            }
+            Action::Delete => {
+                self.delete_images(command);
+                Ok(())
+            }
+        }
+    }
+    
+    /// Shows a finished sixel image. Normally it goes at the cursor, which
+    /// then moves to the line below the image, scrolling if needed; in
+    /// sixel display mode it goes at the top left and the cursor stays.
+    fn finish_sixel(&mut self) {
+        let Some((width, height, pixels)) = self.sixel.take().and_then(SixelDecoder::finish) else {
+            return;
+        };
+        let id = match self.graphics.insert(0, 0, width, height, pixels) {
+            Ok(id) => id,
+            Err(e) => return log::debug!("Dropping sixel image: {:#}", e),
+        };
+        
+        let (cell_width, cell_height) = self.cell_size;
+        let rows = height.div_ceil(cell_height.max(1)) as usize;
+        let cols = width.div_ceil(cell_width.max(1)) as usize;
+        let (line, col) = if self.modes.sixel_display {
+            (self.screen_lines().start, 0)
+        } else {
+            (self.cursor_line(), self.cursor_x.min(self.cols - 1))
+        };
+        self.graphics.place(Placement {
+            image_id: id,
+            id: 0,
+            line,
+            col,
+            rows,
+            cols,
+            z_index: 0,
+            source: (0, 0, width, height),
+            offset: (0, 0),
+            alt_screen: self.using_alt_buffer,
+        });
+        
+        if !self.modes.sixel_display {
+            for _ in 0..rows {
+                self.linefeed();
+            }
+            self.move_cursor_to(col, self.cursor_y);
+        }
+    }
+    
+    /// XTSMGRAPHICS: reports the number of sixel color registers or the
+    /// largest sixel image. Neither can be changed, so setting either
+    /// reports failure; ReGIS isn't supported.
+    fn graphics_attributes(&mut self, params: &Params) {
+        let (item, action) = (param_or(params, 0, 0), param_or(params, 1, 0));
+        let reply = match (item, action) {
+            (1, 1 | 2 | 4) => format!("0;{}", sixel::COLOR_REGISTERS),
+            (2, 1 | 2) => {
+                let (cell_width, cell_height) = self.cell_size;
+                let width = (self.cols as u32 * cell_width).min(graphics::MAX_IMAGE_DIMENSION);
+                let height = (self.rows as u32 * cell_height).min(graphics::MAX_IMAGE_DIMENSION);
+                format!("0;{};{}", width, height)
+            }
+            (2, 4) => format!("0;{0};{0}", graphics::MAX_IMAGE_DIMENSION),
+            (1 | 2, 3) => "3;0".to_string(),
+            (1 | 2, _) => "2;0".to_string(),
+            _ => "1;0".to_string(),
+        };
+        self.reply(&format!("\x1b[?{};{}S", item, reply));
+    }
+    
+    /// Shows an image at the cursor and, unless told not to, moves the
+    /// cursor past it: to the column after the image on its last row.
+    fn put_image(&mut self, id: u32, command: &GraphicsCommand) -> Result<()> {
+        let image = self
+            .graphics
+            .get(id)
+            .ok_or_else(|| anyhow::anyhow!("ENOENT:No image with id {}", id))?;
+        
+        // The part shown, clipped to the image
+        let x = command.x.min(image.width);
+        let y = command.y.min(image.height);
+        let w = if command.w == 0 { image.width - x } else { command.w.min(image.width - x) };
+        let h = if command.h == 0 { image.height - y } else { command.h.min(image.height - y) };
+        
+        let (cell_width, cell_height) = self.cell_size;
+        let cols = match command.cols {
+            0 => (w + command.offset_x).div_ceil(cell_width.max(1)),
+            cols => cols,
+        };
+        let rows = match command.rows {
+            0 => (h + command.offset_y).div_ceil(cell_height.max(1)),
+            rows => rows,
+        };
+        let (cols, rows) = (cols.max(1) as usize, rows.max(1) as usize);
+        
+        let col = self.cursor_x.min(self.cols - 1);
+        self.graphics.place(Placement {
+            image_id: id,
+            id: command.placement_id,
+            line: self.cursor_line(),
+            col,
+            rows,
+            cols,
+            z_index: command.z_index,
+            source: (x, y, w, h),
+            offset: (command.offset_x, command.offset_y),
+            alt_screen: self.using_alt_buffer,
+        });
+        
+        if !command.keep_cursor {
+            for _ in 1..rows {
+                self.linefeed();
+            }
+            self.move_cursor_to(col + cols, self.cursor_y);
+        }
+        Ok(())
+    }
+    
+    /// `a=d`: removes placements picked by the `d` key. Uppercase keys free
+    /// the image data as well once nothing shows the image any more.
+    fn delete_images(&mut self, command: &GraphicsCommand) {
+        let (screen, alt) = (self.screen_lines(), self.using_alt_buffer);
+        let cursor = (self.cursor_line(), self.cursor_x);
+        // `x` and `y` name a cell of the screen, counted from one
+        let cell = (
+            screen.start + command.y.saturating_sub(1) as usize,
+            command.x.saturating_sub(1) as usize,
+        );
+        let free = command.delete.is_ascii_uppercase();
+        let by_id = |graphics: &mut ImageStore, id: u32| {
+            if command.placement_id == 0 && free {
+                graphics.delete_image(id);
+            } else {
+                graphics.delete(|p| p.image_id == id && (command.placement_id == 0 || p.id == command.placement_id), free);
+            }
+        };
+        
+        match command.delete.to_ascii_lowercase() {
+            b'a' => self
+                .graphics
+                .delete(|p| p.alt_screen == alt && p.line < screen.end && p.lines().end > screen.start, free),
+            b'i' => by_id(&mut self.graphics, command.image_id),
+            b'n' => {
+                if let Some(id) = self.graphics.find_number(command.image_number) {
+                    by_id(&mut self.graphics, id);
+                }
+            }
+            b'c' => self.graphics.delete(|p| p.alt_screen == alt && p.intersects(cursor.0, cursor.1), free),
+            b'p' => self.graphics.delete(|p| p.alt_screen == alt && p.intersects(cell.0, cell.1), free),
+            b'q' => self.graphics.delete(
+                |p| p.alt_screen == alt && p.intersects(cell.0, cell.1) && p.z_index == command.z_index,
+                free,
+            ),
+            b'x' => self
+                .graphics
+                .delete(|p| p.alt_screen == alt && (p.col..p.col + p.cols).contains(&cell.1), free),
+            b'y' => self.graphics.delete(|p| p.alt_screen == alt && p.lines().contains(&cell.0), free),
+            b'z' => self.graphics.delete(|p| p.alt_screen == alt && p.z_index == command.z_index, free),
+            b'r' => {
+                for id in self.graphics.ids_in(command.x..command.y.saturating_add(1)) {
+                    by_id(&mut self.graphics, id);
+                }
+            }
+            other => log::debug!("Unsupported graphics deletion {}", other as char),
+        }
+    }
+    
+    /// Whether a synchronized update is holding back rendering. An update
+    /// that runs past the timeout is ended, as if the program had.
+    pub fn synchronized_update_pending(&mut self, now: Instant) -> bool {
+        match self.synchronized_since {
+            Some(since) if now.duration_since(since) < SYNCHRONIZED_UPDATE_TIMEOUT => true,
+            Some(_) => {
+                log::debug!("Synchronized update timed out");
+                self.synchronized_since = None;
+                false
+            }
+            None => false,
+        }
+    }
+    
+    /// Kitty keyboard protocol flags in effect on the current screen.
+    pub fn current_keyboard_flags(&self) -> KeyboardFlags {
+        let stack = if self.using_alt_buffer { &self.alt_keyboard_flags } else { &self.keyboard_flags };
+        stack.last().copied().unwrap_or_default()
+    }
+    
+    /// `CSI > flags u` pushes, `CSI < count u` pops, `CSI = flags ; mode u`
+    /// changes the top entry and `CSI ? u` queries it.
+    fn keyboard_protocol(&mut self, intermediate: u8, params: &Params) {
+        let current = self.current_keyboard_flags();
+        let stack = if self.using_alt_buffer {
+            &mut self.alt_keyboard_flags
+        } else {
+            &mut self.keyboard_flags
+        };
+        let flags = KeyboardFlags::from_bits_truncate(param_or(params, 0, 0));
+        match intermediate {
+            b'>' => {
+                // Like kitty, a full stack forgets its oldest entry
+                if stack.len() == KEYBOARD_STACK_LIMIT {
+                    stack.remove(0);
+                }
+                stack.push(flags);
+            }
+            b'<' => {
+                let count = (param_or(params, 0, 1) as usize).min(stack.len());
+                stack.truncate(stack.len() - count);
+            }
+            b'=' => {
+                let updated = match param_or(params, 1, 1) {
+                    1 => flags,
+                    2 => current.union(flags),
+                    3 => current.difference(flags),
+                    mode => return log::trace!("Unknown keyboard flags mode {}", mode),
+                };
+                match stack.last_mut() {
+                    Some(top) => *top = updated,
+                    None => stack.push(updated),
+                }
+            }
+            _ => self.reply(&format!("\x1b[?{}u", current.bits())),
+        }
+    }
+    
+    fn reply(&mut self, reply: &str) {
+        self.replies.extend_from_slice(reply.as_bytes());
+    }
+    
+    /// DSR: status (5) and cursor position (6) reports. The private form of
+    /// the position report is DECXCPR.
+    fn device_status_report(&mut self, kind: u16, private: bool) {
+        let prefix = if private { "?" } else { "" };
+        match kind {
+            5 => self.reply("\x1b[0n"),
+            6 => {
+                let (row, col) = (self.cursor_y + 1, self.cursor_x.min(self.cols - 1) + 1);
+                self.reply(&format!("\x1b[{}{};{}R", prefix, row, col));
+            }
+            _ => log::trace!("Unhandled DSR {}{}", prefix, kind),
+        }
+    }
+    
+    /// Queues an OSC reply, ending it the way the request was ended.
+    fn osc_reply(&mut self, body: &str, bell_terminated: bool) {
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+        self.replies.extend_from_slice(format!("\x1b]{}{}", body, terminator).as_bytes());
+    }
+    
+    /// OSC 4: `index ; spec` pairs, where a `?` spec asks for the color.
+    fn set_palette_colors(&mut self, args: &[&[u8]], bell_terminated: bool) {
+        for pair in args.chunks_exact(2) {
+            let Some(index) = std::str::from_utf8(pair[0]).ok().and_then(|index| index.parse::<u8>().ok()) else {
+                continue;
+            };
+            match pair[1] {
+                b"?" => {
+                    let color = palette::format_color(self.palette.colors[index as usize]);
+                    self.osc_reply(&format!("4;{};{}", index, color), bell_terminated);
+                }
+                spec => match std::str::from_utf8(spec).ok().and_then(palette::parse_color) {
+                    Some(color) => self.palette.colors[index as usize] = color,
+                    None => log::debug!("Ignoring invalid color for OSC 4 {}", index),
+                },
+            }
+        }
+    }
+    
+    /// OSC 10, 11 and 12 set or query the foreground, background and cursor
+    /// colors. Like xterm, extra arguments apply to the following ones, so
+    /// `10;?;?` queries both the foreground and the background.
+    fn set_dynamic_colors(&mut self, command: &[u8], args: &[&[u8]], bell_terminated: bool) {
+        let first = if command == b"10" { 10 } else if command == b"11" { 11 } else { 12 };
+        for (which, spec) in (first..=12).zip(args) {
+            let color = match which {
+                10 => &mut self.palette.foreground,
+                11 => &mut self.palette.background,
+                _ => &mut self.palette.cursor,
+            };
+            if *spec == b"?" {
+                let reply = format!("{};{}", which, palette::format_color(*color));
+                self.osc_reply(&reply, bell_terminated);
+            } else if let Some(parsed) = std::str::from_utf8(spec).ok().and_then(palette::parse_color) {
+                *color = parsed;
+            }
+        }
+    }
+    
+    fn notify(&mut self, mut notification: Notification) {
+        if notification.title.is_empty() {
+            notification.title = std::mem::take(&mut notification.body);
+        }
+        if !notification.title.is_empty() {
+            self.notifications.push(notification);
+        }
+    }
+    
+    /// kitty's OSC 99: `99 ; key=value:... ; payload`. The title and body may
+    /// arrive in several chunks; the notification is shown once one with
+    /// `d=1` (the default) completes it.
+    fn kitty_notification(&mut self, args: &[&[u8]]) {
+        let Some((metadata, payload)) = args.split_first() else {
+            return;
+        };
+        let metadata = String::from_utf8_lossy(metadata);
+        let (mut id, mut done, mut part, mut encoded) = (None, true, "title", false);
+        for (key, value) in metadata.split(':').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "i" => id = Some(value.to_string()),
+                "d" => done = value != "0",
+                "p" => part = value,
+                "e" => encoded = value == "1",
+                _ => {}
+            }
+        }
+        
+        let text = if encoded {
+            match BASE64.decode(payload.join(&b';')).map(String::from_utf8) {
+                Ok(Ok(text)) => text,
+                _ => {
+                    log::debug!("Ignoring OSC 99 with invalid base64 payload");
+                    return;
+                }
+            }
+        } else {
+            osc_text(payload)
+        };
+        
+        let key = id.clone().unwrap_or_default();
+        if !self.partial_notifications.contains_key(&key)
+            && self.partial_notifications.len() >= PARTIAL_NOTIFICATION_LIMIT
+        {
+            self.partial_notifications.clear();
+        }
+        let notification = self.partial_notifications.entry(key.clone()).or_default();
+        notification.id = id;
+        match part {
+            "title" => notification.title.push_str(&text),
+            "body" => notification.body.push_str(&text),
+            _ => log::trace!("Unhandled OSC 99 payload type {}", part),
+        }
+        
+        if done {
+            if let Some(notification) = self.partial_notifications.remove(&key) {
+                self.notify(notification);
+            }
+        }
+    }
+    
+    /// Absolute index of the cursor's line on the primary screen.
+    fn cursor_line(&self) -> usize {
+        self.scrollback_evicted + self.scrollback.len() + self.cursor_y
+    }
+    
+    /// Text between two absolute positions (line, column), with a newline
+    /// after every line that did not wrap. Trailing blanks are dropped.
+    pub fn text(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        let mut text = String::new();
+        for index in start.0..=end.0 {
+            let Some(row) = self.line(index) else {
+                continue;
+            };
+            let from = if index == start.0 { start.1 } else { 0 };
+            let to = if index == end.0 { end.1.min(row.cells.len()) } else { row.cells.len() };
+            
+            let mut line = String::new();
+            for cell in row.cells.get(from..to).unwrap_or_default() {
+                match cell.c {
+                    _ if cell.width == CellWidth::Spacer => {}
+                    '\0' => line.push(' '),
+                    _ => line.push_str(&cell.grapheme()),
+                }
+            }
+            
+            if row.wrapped && index != end.0 {
+                text.push_str(&line);
+            } else {
+                text.push_str(line.trim_end());
+                if index != end.0 {
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+    
+    /// OSC 133 shell integration: `A` starts a prompt, `B` the command line,
+    /// `C` the command's output and `D[;exit code]` ends the command.
+    fn shell_mark(&mut self, args: &[&[u8]]) {
+        if self.using_alt_buffer {
+            return;
+        }
+        let (line, y) = (self.cursor_line(), self.cursor_y);
+        
+        match args.first().and_then(|kind| kind.first()) {
+            Some(b'A') => {
+                // Shells that never report the end of a command still get
+                // its output recorded when the next prompt appears.
+                self.finish_command(None);
+                self.buffer[y].marks.prompt = true;
+            }
+            Some(b'B') => self.command.input = Some((line, self.cursor_x)),
+            Some(b'C') => {
+                let text = self
+                    .command
+                    .input
+                    .map(|start| self.text(start, (line, self.cursor_x)))
+                    .unwrap_or_default();
+                self.command = CommandTracker {
+                    input: None,
+                    text: text.trim().to_string(),
+                    output_start: Some(line),
+                    started: Some(Instant::now()),
+                };
+                self.buffer[y].marks.output = true;
+            }
+            Some(b'D') => {
+                let exit_code = args
+                    .get(1)
+                    .and_then(|code| std::str::from_utf8(code).ok()?.parse().ok());
+                let marks = &mut self.buffer[y].marks;
+                marks.command_end = true;
+                marks.exit_code = exit_code;
+                self.finish_command(exit_code);
+            }
+            _ => log::trace!("Unhandled OSC 133 {:?}", args),
+        }
+    }
+    
+    fn finish_command(&mut self, exit_code: Option<i32>) {
+        let command = std::mem::take(&mut self.command);
+        let (Some(start), Some(started)) = (command.output_start, command.started) else {
+            return;
+        };
+        
+        let end = self.cursor_line() + usize::from(self.cursor_x > 0);
+        let finished = FinishedCommand {
+            command: command.text,
+            exit_code,
+            duration: started.elapsed(),
+            output: start..end.max(start),
+        };
+        self.finished_commands.push(finished.clone());
+        self.last_command = Some(finished);
+    }
+    
+    /// Scrolls the view so that the previous (or, with `forward`, the next)
+    /// prompt is at the top. Returns whether the view moved.
+    pub fn scroll_to_prompt(&mut self, forward: bool) -> bool {
+        if self.using_alt_buffer {
+            return false;
+        }
+        let screen_top = self.scrollback_evicted + self.scrollback.len();
+        let view_top = screen_top - self.display_offset.min(self.scrollback.len());
+        let is_prompt = |index: &usize| self.line(*index).is_some_and(|row| row.marks.prompt);
+        
+        let target = if forward {
+            if self.display_offset == 0 {
+                return false;
+            }
+            (view_top + 1..screen_top).find(is_prompt).unwrap_or(screen_top)
+        } else {
+            match (self.first_line()..view_top).rev().find(is_prompt) {
+                Some(line) => line,
+                None => return false,
+            }
+        };
+        self.display_offset = screen_top - target;
+        true
+    }
+    
+    /// Text of the most recent command's output.
+    pub fn last_command_output(&self) -> Option<String> {
+        let output = &self.last_command.as_ref()?.output;
+        if output.is_empty() {
+            return Some(String::new());
+        }
+        Some(self.text((output.start, 0), (output.end - 1, usize::MAX)))
+    }
+    
+    pub fn select_last_command_output(&mut self) -> bool {
+        self.selection = self.last_command.as_ref().map(|command| command.output.clone());
+        self.selection.is_some()
+    }
+    
+    /// Switches to the alternate screen, clearing it. Scrollback is left
+    /// alone since nothing scrolls into it while the alternate screen is up.
+    fn enter_alt_screen(&mut self) {
+        if !self.using_alt_buffer {
+            self.main_cursor = (self.cursor_x, self.cursor_y);
+            self.graphics.delete(|placement| placement.alt_screen, true);
+            self.alt_buffer = vec![self.blank_line(); self.rows];
+            self.using_alt_buffer = true;
+        }
+    }
+    
+    fn leave_alt_screen(&mut self) {
+        self.using_alt_buffer = false;
+    }
+    
+    fn set_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            4 => self.modes.insert = enabled,
+            _ => log::trace!("Unhandled mode {} = {}", mode, enabled),
+        }
+    }
+    
+    /// State of an ANSI mode for DECRQM, or `None` if it isn't supported.
+    fn mode(&self, mode: u16) -> Option<bool> {
+        match mode {
+            4 => Some(self.modes.insert),
+            _ => None,
+        }
+    }
+    
+    /// State of a DEC private mode for DECRQM, or `None` if it isn't
+    /// supported.
+    fn private_mode(&self, mode: u16) -> Option<bool> {
+        match mode {
+            1 => Some(self.modes.application_cursor),
+            7 => Some(self.modes.auto_wrap),
+            25 => Some(self.modes.cursor_visible),
+            9 => Some(self.modes.mouse_tracking == MouseTracking::X10),
+            1000 => Some(self.modes.mouse_tracking == MouseTracking::Normal),
+            1002 => Some(self.modes.mouse_tracking == MouseTracking::ButtonEvent),
+            1003 => Some(self.modes.mouse_tracking == MouseTracking::AnyEvent),
+            1006 => Some(self.modes.mouse_encoding == MouseEncoding::Sgr),
+            1016 => Some(self.modes.mouse_encoding == MouseEncoding::SgrPixels),
+            1004 => Some(self.modes.focus_events),
+            2004 => Some(self.modes.bracketed_paste),
+            80 => Some(self.modes.sixel_display),
+            2026 => Some(self.synchronized_since.is_some()),
+            47 | 1047 | 1049 => Some(self.using_alt_buffer),
+            _ => None,
+        }
+    }
+    
+    /// DECRPM: answers a DECRQM query with 1 (set), 2 (reset) or 0 (not
+    /// recognized).
+    fn report_mode(&mut self, mode: u16, private: bool) {
+        let state = if private { self.private_mode(mode) } else { self.mode(mode) };
+        let value = match state {
+            Some(true) => 1,
+            Some(false) => 2,
+            None => 0,
+        };
+        let prefix = if private { "?" } else { "" };
+        self.reply(&format!("\x1b[{}{};{}$y", prefix, mode, value));
+    }
+    
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        match (mode, enabled) {
+            (1, _) => self.modes.application_cursor = enabled,
+            (25, _) => self.modes.cursor_visible = enabled,
+            // Mouse tracking modes replace each other; resetting one only
+            // turns tracking off if it is the one in effect
+            (9 | 1000 | 1002 | 1003, _) => {
+                let tracking = match mode {
+                    9 => MouseTracking::X10,
+                    1000 => MouseTracking::Normal,
+                    1002 => MouseTracking::ButtonEvent,
+                    _ => MouseTracking::AnyEvent,
+                };
+                if enabled {
+                    self.modes.mouse_tracking = tracking;
+                } else if self.modes.mouse_tracking == tracking {
+                    self.modes.mouse_tracking = MouseTracking::Off;
+                }
+            }
+            (1004, _) => self.modes.focus_events = enabled,
+            (2004, _) => self.modes.bracketed_paste = enabled,
+            (80, _) => self.modes.sixel_display = enabled,
+            (2026, true) => self.synchronized_since = Some(Instant::now()),
+            (2026, false) => self.synchronized_since = None,
+            (1006 | 1016, _) => {
+                let encoding = if mode == 1006 { MouseEncoding::Sgr } else { MouseEncoding::SgrPixels };
+                if enabled {
+                    self.modes.mouse_encoding = encoding;
+                } else if self.modes.mouse_encoding == encoding {
+                    self.modes.mouse_encoding = MouseEncoding::Default;
+                }
+            }
+            (7, _) => {
+                self.modes.auto_wrap = enabled;
+                self.pending_wrap = false;
+            }
+            (47 | 1047, true) => self.enter_alt_screen(),
+            (47 | 1047, false) => self.leave_alt_screen(),
+            (1049, true) => {
+                if !self.using_alt_buffer {
+                    self.save_cursor();
+                    self.enter_alt_screen();
+                }
+            }
+            (1049, false) => {
+                if self.using_alt_buffer {
+                    self.leave_alt_screen();
+                    self.restore_cursor();
+                }
+            }
+            _ => log::trace!("Unhandled DEC private mode {} = {}", mode, enabled),
+        }
+    }
+    
+    fn cursor_in_region(&self) -> bool {
+        (self.scroll_top..self.scroll_bottom).contains(&self.cursor_y)
+    }
+    
+    /// IL: inserts blank lines at the cursor, pushing lines below it towards
+    /// the bottom margin.
+    fn insert_lines(&mut self, count: usize) {
+        if !self.cursor_in_region() {
+            return;
+        }
+        let (y, bottom) = (self.cursor_y, self.scroll_bottom);
+        let blank = self.blank_line();
+        for _ in 0..count.min(bottom - y) {
+            self.grid_mut().remove(bottom - 1);
+            self.grid_mut().insert(y, blank.clone());
+        }
+        self.cursor_x = 0;
+    }
+    
+    /// DL: deletes lines at the cursor, pulling up lines from the bottom
+    /// margin.
+    fn delete_lines(&mut self, count: usize) {
+        if !self.cursor_in_region() {
+            return;
+        }
+        let (y, bottom) = (self.cursor_y, self.scroll_bottom);
+        let blank = self.blank_line();
+        for _ in 0..count.min(bottom - y) {
+            self.grid_mut().remove(y);
+            self.grid_mut().insert(bottom - 1, blank.clone());
+        }
+        self.cursor_x = 0;
+    }
+    
+    /// ICH: shifts the rest of the line right, inserting blanks at the cursor.
+    fn insert_chars(&mut self, count: usize) {
+        let (x, y, cols) = (self.cursor_x, self.cursor_y, self.cols);
+        // Inserting at the right half of a wide character would split it, so
+        // both halves are cleared first, as when printing over it. With the
+        // range empty, the start and end checks each clear one half.
+        self.clear_wide_overlap(y, x, x);
+        let blank = self.blank_cell();
+        let line = &mut self.grid_mut()[y].cells;
+        let count = count.min(cols - x);
+        line.truncate(cols - count);
+        line.splice(x..x, std::iter::repeat_n(blank.clone(), count));
+        // A wide character pushed against the margin loses its spacer
+        if line[cols - 1].width == CellWidth::Wide {
+            line[cols - 1] = blank;
+        }
+    }
+    
+    /// DCH: deletes cells at the cursor, shifting the rest of the line left.
+    fn delete_chars(&mut self, count: usize) {
+        let (x, y, cols) = (self.cursor_x, self.cursor_y, self.cols);
+        let count = count.min(cols - x);
+        self.clear_wide_overlap(y, x, x + count);
+        let blank = self.blank_cell();
+        let line = &mut self.grid_mut()[y].cells;
+        line.drain(x..x + count);
+        line.resize(cols, blank);
+    }
+    
+    fn grid(&self) -> &Vec<Row> {
+        if self.using_alt_buffer {
+            &self.alt_buffer
+        } else {
+            &self.buffer
+        }
+    }
+    
+    fn grid_mut(&mut self) -> &mut Vec<Row> {
+        if self.using_alt_buffer {
+            &mut self.alt_buffer
+        } else {
+            &mut self.buffer
+        }
+    }
+    
+    fn move_cursor_to(&mut self, x: usize, y: usize) {
+        self.pending_wrap = false;
+        self.cursor_x = x.min(self.cols.saturating_sub(1));
+        self.cursor_y = y.min(self.rows.saturating_sub(1));
+    }
+    
+    fn move_cursor_by(&mut self, dx: isize, dy: isize) {
+        let x = self.cursor_x.saturating_add_signed(dx);
+        let y = self.cursor_y.saturating_add_signed(dy);
+        self.move_cursor_to(x, y);
+    }
+    
+    /// An empty cell carrying the current background (xterm's BCE behavior).
+    fn blank_cell(&self) -> Cell {
+        Cell {
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
+    }
+    
+    /// Blanks the other half of any wide character that straddles the edges
+    /// of `start..end`, so overwriting part of it never leaves half behind.
+    fn clear_wide_overlap(&mut self, row: usize, start: usize, end: usize) {
+        let blank = self.blank_cell();
+        let cells = &mut self.grid_mut()[row].cells;
+        if cells.get(start).is_some_and(|cell| cell.width == CellWidth::Spacer) && start > 0 {
+            cells[start - 1] = blank.clone();
+        }
+        if cells.get(end).is_some_and(|cell| cell.width == CellWidth::Spacer) {
+            cells[end] = blank;
+        }
+    }
+    
+    /// Position of the cell written before the cursor, following soft wraps
+    /// and skipping wide-character spacers.
+    fn previous_cell(&self) -> Option<(usize, usize)> {
+        let (mut x, mut y) = (self.cursor_x, self.cursor_y);
+        if self.pending_wrap {
+            // The cursor is still on the cell that was just written
+        } else if x > 0 {
+            x -= 1;
+        } else if y > 0 && self.grid()[y - 1].wrapped {
+            (x, y) = (self.cols - 1, y - 1);
+        } else {
+            return None;
+        }
+        
+        if self.grid()[y].cells[x].width == CellWidth::Spacer && x > 0 {
+            x -= 1;
+        }
+        Some((x, y))
+    }
+    
+    /// Appends `c` to the previous cell when it continues that cell's
+    /// grapheme cluster (combining marks, ZWJ sequences, flags, ...).
+    fn append_to_previous(&mut self, c: char, width: usize) -> bool {
+        let Some((x, y)) = self.previous_cell() else {
+            return false;
+        };
+        let cell = &self.grid()[y].cells[x];
+        if cell.c == '\0' {
+            return false;
+        }
+        
+        let joins = width == 0 || (!c.is_ascii() && {
+            let mut text = cell.grapheme();
+            text.push(c);
+            text.graphemes(true).nth(1).is_none()
+        });
+        if joins {
+            self.grid_mut()[y].cells[x].push_char(c);
+        }
+        joins
+    }
+    
+    fn erase_cells(&mut self, row: usize, start: usize, end: usize) {
+        if row < self.rows {
+            self.clear_wide_overlap(row, start, end);
+        }
+        let blank = self.blank_cell();
+        if let Some(line) = self.grid_mut().get_mut(row) {
+            let end = end.min(line.cells.len());
+            for cell in line.cells.iter_mut().take(end).skip(start) {
+                *cell = blank.clone();
+            }
+            // Erasing through the end of the row breaks the logical line
+            if end == line.cells.len() {
+                line.wrapped = false;
+            }
+        }
+    }
+    
+    /// Blanks a whole row, dropping its shell integration marks too.
+    fn erase_row(&mut self, row: usize) {
+        self.erase_cells(row, 0, self.cols);
+        self.grid_mut()[row].marks = LineMarks::default();
+    }
+    
+    fn erase_in_display(&mut self, mode: u16) {
+        let (x, y) = (self.cursor_x, self.cursor_y);
+        let (rows, cols) = (self.rows, self.cols);
+        match mode {
+            // Cursor to end of screen
+            0 => {
+                self.erase_cells(y, x, cols);
+                for row in y + 1..rows {
+                    self.erase_row(row);
+                }
+            }
+            // Start of screen to cursor
+            1 => {
+                for row in 0..y {
+                    self.erase_row(row);
+                }
+                self.erase_cells(y, 0, x + 1);
+            }
+            // Whole screen, including the images on it
+            2 => {
+                for row in 0..rows {
+                    self.erase_row(row);
+                }
+                let (screen, alt) = (self.screen_lines(), self.using_alt_buffer);
+                self.graphics.delete(
+                    |placement| placement.alt_screen == alt && placement.line < screen.end && placement.lines().end > screen.start,
+                    false,
+                );
+            }
+            // Scrollback only (xterm's "clear saved lines")
+            3 => {
+                self.scrollback_evicted += self.scrollback.len();
+                self.scrollback.clear();
+                self.graphics.prune(self.scrollback_evicted);
+            }
+            _ => {}
+        }
+    }
+    
+    fn erase_in_line(&mut self, mode: u16) {
+        let (x, y) = (self.cursor_x, self.cursor_y);
+        match mode {
+            0 => self.erase_cells(y, x, self.cols),
+            1 => self.erase_cells(y, 0, x + 1),
+            2 => self.erase_cells(y, 0, self.cols),
+            _ => {}
+        }
+    }
+    
+    fn set_graphics_rendition(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.pen = Pen::default();
+            return;
+        }
+        
+        let mut iter = params.iter();
+        while let Some(param) = iter.next() {
+            let attrs = &mut self.pen.attrs;
+            match param {
+                [0] => self.pen = Pen::default(),
+                [1] => attrs.bold = true,
+                [2] => attrs.dim = true,
+                [3] => attrs.italic = true,
+                [4, ..] | [21] => attrs.underline = param.get(1) != Some(&0),
+                [5] | [6] => attrs.blink = true,
+                [7] => attrs.reverse = true,
+                [8] => attrs.hidden = true,
+                [9] => attrs.strikethrough = true,
+                [22] => {
+                    attrs.bold = false;
+                    attrs.dim = false;
+                }
+                [23] => attrs.italic = false,
+                [24] => attrs.underline = false,
+                [25] => attrs.blink = false,
+                [27] => attrs.reverse = false,
+                [28] => attrs.hidden = false,
+                [29] => attrs.strikethrough = false,
+                [n @ 30..=37] => self.pen.fg = Color::Indexed((n - 30) as u8),
+                [38, sub @ ..] => {
+                    if let Some(color) = parse_extended_color(sub, &mut iter) {
+                        self.pen.fg = color;
+                    }
+                }
+                [39] => self.pen.fg = Color::default(),
+                [n @ 40..=47] => self.pen.bg = Color::Indexed((n - 40) as u8),
+                [48, sub @ ..] => {
+                    if let Some(color) = parse_extended_color(sub, &mut iter) {
+                        self.pen.bg = color;
+                    }
+                }
+                [49] => self.pen.bg = Color::default(),
+                [n @ 90..=97] => self.pen.fg = Color::Indexed((n - 90 + 8) as u8),
+                [n @ 100..=107] => self.pen.bg = Color::Indexed((n - 100 + 8) as u8),
+                _ => log::trace!("Unhandled SGR {:?}", param),
+            }
+        }
+    }
+}
+
+/// Rewraps `lines` to `cols` columns, joining rows that were soft-wrapped and
+/// splitting long logical lines again. Blank rows below the cursor are
+/// dropped. Returns the new rows along with the cursor's new row and column.
+fn reflow(lines: Vec<Row>, cols: usize, cursor_line: usize, cursor_x: usize) -> (Vec<Row>, usize, usize) {
+    let last = lines
+        .iter()
+        .rposition(|row| row.content_len() > 0 || row.marks != LineMarks::default())
+        .map_or(cursor_line, |i| i.max(cursor_line));
+    
+    let mut result = Vec::with_capacity(lines.len());
+    let mut cursor = (0, 0);
+    let mut logical: Vec<Cell> = Vec::new();
+    let mut marks = LineMarks::default();
+    let mut cursor_offset = None;
+    
+    let mut lines = lines.into_iter().take(last + 1).enumerate().peekable();
+    while let Some((index, mut row)) = lines.next() {
+        if index == cursor_line {
+            cursor_offset = Some(logical.len() + cursor_x);
+        }
+        marks.merge(row.marks);
+        if let Some((_, next)) = lines.peek().filter(|_| row.wrapped) {
+            // Drop the padding left where a wide character didn't fit
+            let next_is_wide = next.cells.first().is_some_and(|cell| cell.width == CellWidth::Wide);
+            if next_is_wide && row.cells.last() == Some(&Cell::default()) {
+                row.cells.pop();
+            }
+            logical.append(&mut row.cells);
+            continue;
+        }
+        
+        row.cells.truncate(row.content_len());
+        logical.append(&mut row.cells);
+        
+        let (mut rows, row, col) = split_line(&mut logical, cols, cursor_offset.take());
+        if let Some(first) = rows.first_mut() {
+            first.marks = std::mem::take(&mut marks);
+        }
+        if let Some(row) = row {
+            cursor = (result.len() + row, col);
+        }
+        result.extend(rows);
+    }
+    
+    (result, cursor.0, cursor.1)
+}
+
+/// Splits a logical line into rows of `cols` cells, moving wide characters
+/// that would straddle a row boundary onto the next row. Also maps
+/// `cursor_offset` into the returned rows.
+fn split_line(logical: &mut Vec<Cell>, cols: usize, cursor_offset: Option<usize>) -> (Vec<Row>, Option<usize>, usize) {
+    let mut rows = Vec::new();
+    let mut current = Vec::with_capacity(cols);
+    let mut cursor = (None, 0);
+    let len = logical.len();
+    
+    let flush = |current: &mut Vec<Cell>, rows: &mut Vec<Row>| {
+        let mut cells = std::mem::replace(current, Vec::with_capacity(cols));
+        cells.resize(cols, Cell::default());
+        rows.push(Row {
+            cells,
+            wrapped: true,
+            marks: LineMarks::default(),
+        });
+    };
+    
+    for (i, cell) in logical.drain(..).enumerate() {
+        let wide_at_margin = cell.width == CellWidth::Wide && current.len() + 1 == cols && cols > 1;
+        if current.len() == cols || wide_at_margin {
+            flush(&mut current, &mut rows);
+        }
+        if cursor_offset == Some(i) {
+            cursor = (Some(rows.len()), current.len());
+        }
+        current.push(cell);
+    }
+    
+    // The cursor may sit past the end of the text
+    if let Some(offset) = cursor_offset.filter(|offset| *offset >= len) {
+        let mut col = current.len() + offset - len;
+        while col >= cols {
+            flush(&mut current, &mut rows);
+            col -= cols;
+        }
+        cursor = (Some(rows.len()), col);
+    }
+    
+    flush(&mut current, &mut rows);
+    if let Some(last) = rows.last_mut() {
+        last.wrapped = false;
+    }
+    (rows, cursor.0, cursor.1)
+}
+
+/// Parses the color following SGR 38/48, either from `:` subparameters
+/// (`38:5:n`, `38:2:r:g:b`, `38:2:cs:r:g:b`) or from the following `;`
+/// separated parameters (`38;5;n`, `38;2;r;g;b`).
+fn parse_extended_color<'a>(sub: &[u16], iter: &mut impl Iterator<Item = &'a [u16]>) -> Option<Color> {
+    let channel = |v: u16| v.min(255) as u8;
+    
+    if !sub.is_empty() {
+        return match sub {
+            [5, index, ..] => Some(Color::Indexed(channel(*index))),
+            [2, _, r, g, b, ..] => Some(Color::rgb(channel(*r), channel(*g), channel(*b))),
+            [2, r, g, b] => Some(Color::rgb(channel(*r), channel(*g), channel(*b))),
+            _ => None,
+        };
+    }
+    
+    let mut next = || iter.next().and_then(|p| p.first().copied());
+    match next()? {
+        5 => Some(Color::Indexed(channel(next()?))),
+        2 => {
+            let (r, g, b) = (next()?, next()?, next()?);
+            Some(Color::rgb(channel(r), channel(g), channel(b)))
+        }
+        _ => None,
+    }
+}
+
+/// Joins OSC parameters back into the text they were split from; titles may
+/// contain semicolons.
+fn osc_text(params: &[&[u8]]) -> String {
+    String::from_utf8_lossy(&params.join(&b';')).into_owned()
+}
+
+/// Extracts the path from an OSC 7 `file://host/path` URL, undoing percent
+/// encoding.
+fn parse_file_url(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let encoded = &rest[rest.find('/')?..];
+    
+    let mut path = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            path.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            path.push(byte);
+        }
+    }
+    String::from_utf8(path).ok().map(PathBuf::from)
+}
+
+/// Returns the first value of the parameter at `index`, or `default` when it
+/// is missing or zero (ECMA-48 treats an explicit 0 as the default too).
+fn param_or(params: &Params, index: usize, default: u16) -> u16 {
+    match params.iter().nth(index).and_then(|p| p.first().copied()) {
+        Some(0) | None => default,
+        Some(value) => value,
+    }
+}
+
+/// Picks APC strings out of the byte stream. vte consumes them without
+/// reporting them, so this runs alongside the parser.
+#[derive(Default)]
+struct ApcScanner {
+    state: ApcState,
+    data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Default)]
+enum ApcState {
+    #[default]
+    Ground,
+    Escape,
+    Apc,
+    ApcEscape,
+}
+
+impl ApcScanner {
+    /// Feeds one byte, returning the contents of an APC string it ends.
+    fn advance(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match (self.state, byte) {
+            (ApcState::Apc, 0x1b) => self.state = ApcState::ApcEscape,
+            (ApcState::ApcEscape, b'\\') => {
+                self.state = ApcState::Ground;
+                return Some(std::mem::take(&mut self.data));
+            }
+            // CAN and SUB cancel the string
+            (ApcState::Apc, 0x18 | 0x1a) => {
+                self.state = ApcState::Ground;
+                self.data.clear();
+            }
+            (ApcState::Apc, _) if self.data.len() == APC_LIMIT => {
+                log::debug!("Dropping APC string longer than {} bytes", APC_LIMIT);
+                self.state = ApcState::Ground;
+                self.data = Vec::new();
+            }
+            (ApcState::Apc, _) => self.data.push(byte),
+            (ApcState::Escape | ApcState::ApcEscape, b'_') => {
+                self.state = ApcState::Apc;
+                self.data.clear();
+            }
+            (_, 0x1b) => self.state = ApcState::Escape,
+            _ => self.state = ApcState::Ground,
+        }
+        None
+    }
+}
+
+/// Pasted text as sent to the program. Without bracketed paste, newlines
+/// become carriage returns as if typed.
+fn encode_paste(text: &str, bracketed: bool) -> Vec<u8> {
+    if !bracketed {
+        return text.replace("\r\n", "\r").replace('\n', "\r").into_bytes();
+    }
+    
+    let mut text = text.to_string();
+    // Removing one marker can join the pieces of another
+    while text.contains("\x1b[201~") {
+        text = text.replace("\x1b[201~", "");
+    }
+    format!("\x1b[200~{}\x1b[201~", text).into_bytes()
+}
+
+/// The crate version as reported by secondary DA, e.g. 1.2.3 as 10203.
+fn version_number() -> u32 {
+    env!("CARGO_PKG_VERSION")
+        .split('.')
+        .take(3)
+        .map(|part| part.parse::<u32>().unwrap_or(0))
+        .fold(0, |number, part| number * 100 + part.min(99))
+}
+
+impl Perform for TerminalState {
+    fn print(&mut self, c: char) {
+        let width = c.width().unwrap_or(0);
+        if self.append_to_previous(c, width) || width == 0 {
+            return;
+        }
+        let width = width.min(self.cols);
+        let cols = self.cols;
+        
+        // Wrap when the previous character filled the last column, or early
+        // when a wide character doesn't fit in what is left of the row.
+        if self.pending_wrap || self.cursor_x + width > cols {
+            if self.modes.auto_wrap {
+                let y = self.cursor_y;
+                self.grid_mut()[y].wrapped = true;
+                self.cursor_x = 0;
+                self.linefeed();
+            } else {
+                self.cursor_x = cols - width;
+            }
+            self.pending_wrap = false;
+        }
+        
+        if self.modes.insert {
+            self.insert_chars(width);
+        }
+        
+        let (x, y) = (self.cursor_x, self.cursor_y);
+        self.clear_wide_overlap(y, x, x + width);
+        
+        let pen = self.pen;
+        let cell = Cell {
+            c,
+            extra: None,
+            width: if width == 2 { CellWidth::Wide } else { CellWidth::Single },
+            fg: pen.fg,
+            bg: pen.bg,
+            attrs: pen.attrs,
+            link: self.active_link,
+        };
+        let cells = &mut self.grid_mut()[y].cells;
+        if width == 2 {
+            cells[x + 1] = Cell {
+                c: '\0',
+                width: CellWidth::Spacer,
+                ..cell.clone()
+            };
+        }
+        cells[x] = cell;
+        self.last_printed = Some(c);
+        
+        if x + width < cols {
+            self.cursor_x = x + width;
+        } else {
+            self.cursor_x = cols - 1;
+            self.pending_wrap = self.modes.auto_wrap;
+        }
+    }
+    
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // LF / VT / FF; the PTY line discipline supplies the CR
+            b'\n' | 0x0b | 0x0c => self.linefeed(),
+            b'\r' => {
+                self.pending_wrap = false;
+                self.cursor_x = 0;
+            }
+            b'\t' => {
+                self.pending_wrap = false;
+                self.cursor_x = ((self.cursor_x + 8) & !7).min(self.cols - 1);
+            }
+            0x08 => {
+                self.pending_wrap = false;
+                self.cursor_x = self.cursor_x.saturating_sub(1);
+            }
+            _ => {}
+        }
+    }
+    
+    fn hook(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, c: char) {
+        match (intermediates, c) {
+            ([], 'q') => {
+                let params: Vec<u16> = params.iter().map(|param| param[0]).collect();
+                self.sixel = Some(SixelDecoder::new(&params, self.graphics.limit()));
+            }
+            _ => log::trace!("Unhandled DCS {:?} {:?} {}", intermediates, params, c),
+        }
+    }
+    
+    fn put(&mut self, byte: u8) {
+        if let Some(sixel) = &mut self.sixel {
+            sixel.put(byte);
+        }
+    }
+    
+    fn unhook(&mut self) {
+        self.finish_sixel();
+    }
+    
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        let Some((&command, args)) = params.split_first() else {
+            return;
+        };
+        
+        match command {
+            // Icon name and window title; an empty one restores the default
+            b"0" | b"1" | b"2" => {
+                let text = osc_text(args);
+                let text = (!text.is_empty()).then_some(text);
+                if command != b"2" {
+                    self.icon_name = text.clone();
+                }
+                if command != b"1" {
+                    self.title = text;
+                }
+            }
+            // Hyperlink: `8 ; params ; uri`, where an empty URI closes it
+            b"8" => match args.split_first() {
+                Some((params, uri)) if !uri.is_empty() && !uri[0].is_empty() => {
+                    self.open_link(params, osc_text(uri));
+                }
+                _ => self.active_link = None,
+            },
+            b"133" => self.shell_mark(args),
+            // Palette and dynamic colors, and their resets
+            b"4" => self.set_palette_colors(args, bell_terminated),
+            b"10" | b"11" | b"12" => self.set_dynamic_colors(command, args, bell_terminated),
+            b"104" => match args {
+                [] | [b""] => self.palette.colors = self.default_palette.colors,
+                _ => {
+                    for index in args.iter().filter_map(|arg| std::str::from_utf8(arg).ok()?.parse::<u8>().ok()) {
+                        self.palette.colors[index as usize] = self.default_palette.colors[index as usize];
+                    }
+                }
+            },
+            b"110" => self.palette.foreground = self.default_palette.foreground,
+            b"111" => self.palette.background = self.default_palette.background,
+            b"112" => self.palette.cursor = self.default_palette.cursor,
+            // Notifications. ConEmu uses numbered OSC 9 subcommands, which
+            // are not messages.
+            b"9" => match args {
+                [kind, _, ..] if !kind.is_empty() && kind.iter().all(u8::is_ascii_digit) => {
+                    log::trace!("Unhandled ConEmu OSC 9 {}", String::from_utf8_lossy(kind));
+                }
+                _ => self.notify(Notification {
+                    title: osc_text(args),
+                    ..Default::default()
+                }),
+            },
+            b"777" => match args {
+                [b"notify", title, body @ ..] => self.notify(Notification {
+                    title: osc_text(&[title]),
+                    body: osc_text(body),
+                    ..Default::default()
+                }),
+                _ => log::trace!("Unhandled OSC 777 {:?}", args.first().map(|kind| String::from_utf8_lossy(kind))),
+            },
+            b"99" => self.kitty_notification(args),
+            // Clipboard: `52 ; targets ; base64 data`, or `?` to read it
+            b"52" => match args {
+                [targets, b"?"] => {
+                    let selection = Selection::from_osc52(targets);
+                    self.clipboard_requests.push(ClipboardRequest::Load(selection));
+                }
+                [targets, data] => match BASE64.decode(data).map(String::from_utf8) {
+                    Ok(Ok(text)) => {
+                        let selection = Selection::from_osc52(targets);
+                        self.clipboard_requests.push(ClipboardRequest::Store(selection, text));
+                    }
+                    _ => log::debug!("Ignoring OSC 52 with invalid data"),
+                },
+                _ => log::debug!("Ignoring malformed OSC 52"),
+            },
+            b"7" => match parse_file_url(&osc_text(args)) {
+                Some(path) => self.cwd = Some(path),
+                None => log::debug!("Ignoring invalid OSC 7 URL {:?}", osc_text(args)),
+            },
+            _ => log::trace!("Unhandled OSC {}", String::from_utf8_lossy(command)),
+        }
+    }
+    
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, c: char) {
+        if ignore {
+            return;
+        }
+        
+        match (intermediates, c) {
+            // DECSET / DECRST
+            ([b'?'], 'h' | 'l') => {
+                for param in params.iter() {
+                    self.set_private_mode(param[0], c == 'h');
+                }
+                return;
+            }
+            ([b'?'], 'n') => return self.device_status_report(param_or(params, 0, 0), true),
+            ([b'?'], 'S') => return self.graphics_attributes(params),
+            ([intermediate @ (b'?' | b'>' | b'<' | b'=')], 'u') => return self.keyboard_protocol(*intermediate, params),
+            // DECRQM
+            ([b'$'], 'p') => return self.report_mode(param_or(params, 0, 0), false),
+            ([b'?', b'$'], 'p') => return self.report_mode(param_or(params, 0, 0), true),
+            // Secondary DA: VT220-class, then the version as major*10000 +
+            // minor*100 + patch, then no ROM cartridge
+            ([b'>'], 'c') => {
+                if param_or(params, 0, 0) == 0 {
+                    self.reply(&format!("\x1b[>1;{};0c", version_number()));
+                }
+                return;
+            }
+            // XTVERSION
+            ([b'>'], 'q') => {
+                if param_or(params, 0, 0) == 0 {
+                    self.reply(concat!("\x1bP>|kitty-plus(", env!("CARGO_PKG_VERSION"), ")\x1b\\"));
+                }
+                return;
+            }
+            ([], _) => {}
+            _ => {
+                log::trace!("Unhandled CSI {:?} {:?} {}", intermediates, params, c);
+                return;
+            }
+        }
+        
+        let n = param_or(params, 0, 1) as isize;
+        match c {
+            // CUU / CUD stop at the margins when starting inside the region
+            'A' => {
+                let top = if self.cursor_y >= self.scroll_top { self.scroll_top } else { 0 };
+                let y = self.cursor_y.saturating_sub(n as usize).max(top);
+                self.move_cursor_to(self.cursor_x, y);
+            }
+            'B' | 'e' => {
+                let bottom = if self.cursor_y < self.scroll_bottom {
+                    self.scroll_bottom
+                } else {
+                    self.rows
+                };
+                let y = self.cursor_y.saturating_add(n as usize).min(bottom - 1);
+                self.move_cursor_to(self.cursor_x, y);
+            }
+            // CUF / CUB
+            'C' | 'a' => self.move_cursor_by(n, 0),
+            'D' => self.move_cursor_by(-n, 0),
+            // CNL / CPL
+            'E' => {
+                self.cursor_x = 0;
+                self.move_cursor_by(0, n);
+            }
+            'F' => {
+                self.cursor_x = 0;
+                self.move_cursor_by(0, -n);
+            }
+            // CHA / HPA
+            'G' | '`' => {
+                let col = param_or(params, 0, 1) as usize - 1;
+                self.move_cursor_to(col, self.cursor_y);
+            }
+            // CUP / HVP
+            'H' | 'f' => {
+                let row = param_or(params, 0, 1) as usize - 1;
+                let col = param_or(params, 1, 1) as usize - 1;
+                self.move_cursor_to(col, row);
+            }
+            // VPA
+            'd' => {
+                let row = param_or(params, 0, 1) as usize - 1;
+                self.move_cursor_to(self.cursor_x, row);
+            }
+            // ED / EL
+            'J' => self.erase_in_display(param_or(params, 0, 0)),
+            'K' => self.erase_in_line(param_or(params, 0, 0)),
+            // IL / DL / ICH / DCH / ECH
+            'L' => self.insert_lines(n as usize),
+            'M' => self.delete_lines(n as usize),
+            '@' => self.insert_chars(n as usize),
+            'P' => self.delete_chars(n as usize),
+            'X' => {
+                let (x, y) = (self.cursor_x, self.cursor_y);
+                self.erase_cells(y, x, x.saturating_add(n as usize));
+            }
+            // SU / SD
+            'S' => self.scroll_up(n as usize),
+            'T' => self.scroll_down(n as usize),
+            // DECSTBM
+            'r' => {
+                let top = param_or(params, 0, 1) as usize - 1;
+                let bottom = param_or(params, 1, self.rows as u16) as usize;
+                self.set_scroll_region(top, bottom);
+            }
+            // SGR
+            'm' => self.set_graphics_rendition(params),
+            // SCOSC / SCORC
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            // SM / RM
+            'h' | 'l' => {
+                for param in params.iter() {
+                    self.set_mode(param[0], c == 'h');
+                }
+            }
+            // REP
+            'b' => {
+                if let Some(last) = self.last_printed {
+                    for _ in 0..(n as usize).min(self.rows * self.cols) {
+                        self.print(last);
+                    }
+                }
+            }
+            // Primary DA: VT220 with sixel graphics and ANSI color
+            'c' => {
+                if param_or(params, 0, 0) == 0 {
+                    self.reply("\x1b[?62;4;22c");
+                }
+            }
+            'n' => self.device_status_report(param_or(params, 0, 0), false),
+            // XTWINOPS, of which only the title stack is supported
+            't' => match param_or(params, 0, 0) {
+                22 => self.push_title(param_or(params, 1, 0)),
+                23 => self.pop_title(param_or(params, 1, 0)),
+                _ => log::trace!("Unhandled XTWINOPS {:?}", params),
+            },
+            _ => log::trace!("Unhandled CSI {:?} {}", params, c),
+        }
+    }
+    
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        match (intermediates, byte) {
+            // IND / RI / NEL
+            ([], b'D') => self.linefeed(),
+            ([], b'M') => self.reverse_index(),
+            ([], b'E') => {
+                self.cursor_x = 0;
+                self.linefeed();
+            }
+            // DECSC / DECRC
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            _ => log::trace!("Unhandled ESC {:?} {}", intermediates, byte as char),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn terminal(rows: usize, cols: usize) -> Emulator {
+        let mut terminal = Emulator::new(Config::default());
+        terminal.resize(rows, cols);
+        terminal
+    }
+    
+    fn screen(terminal: &Emulator) -> Vec<String> {
+        terminal
+            .state
+            .buffer
+            .iter()
+            .map(|row| {
+                let line: String = row
+                    .cells
+                    .iter()
+                    .filter(|cell| cell.width != CellWidth::Spacer)
+                    .map(|cell| if cell.c == '\0' { " ".to_string() } else { cell.grapheme() })
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+    
+    fn numbered(terminal: &mut Emulator, lines: usize) {
+        for i in 1..=lines {
+            if i > 1 {
+                terminal.process_input(b"\r\n");
+            }
+            terminal.process_input(i.to_string().as_bytes());
+        }
+    }
+    
+    /// The rows in view, which are the alternate screen's while it is active.
+    fn visible_screen(terminal: &Emulator) -> Vec<String> {
+        terminal
+            .state
+            .visible_rows()
+            .into_iter()
+            .map(|(_, row)| {
+                let line: String = row.cells.iter().map(|cell| if cell.c == '\0' { ' ' } else { cell.c }).collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+    
+    #[test]
+    fn cursor_movement_clamps_and_defaults() {
+        let mut t = terminal(4, 5);
+        let mut check = |input: &[u8], y: usize, x: usize| {
+            t.process_input(input);
+            assert_eq!((t.state.cursor_y, t.state.cursor_x), (y, x), "{:?}", String::from_utf8_lossy(input));
+        };
+        
+        // CUP / HVP
+        check(b"\x1b[10;10H", 3, 4);
+        check(b"\x1b[H", 0, 0);
+        check(b"\x1b[2;3f", 1, 2);
+        check(b"\x1b[0;0H", 0, 0);
+        check(b"\x1b[3H", 2, 0);
+        // CUD / CUU
+        check(b"\x1b[B", 3, 0);
+        check(b"\x1b[9B", 3, 0);
+        check(b"\x1b[0A", 2, 0);
+        check(b"\x1b[9A", 0, 0);
+        // CUF / CUB
+        check(b"\x1b[C", 0, 1);
+        check(b"\x1b[0C", 0, 2);
+        check(b"\x1b[9C", 0, 4);
+        check(b"\x1b[2D", 0, 2);
+        check(b"\x1b[9D", 0, 0);
+        // CHA / VPA
+        check(b"\x1b[3G", 0, 2);
+        check(b"\x1b[99G", 0, 4);
+        check(b"\x1b[G", 0, 0);
+        check(b"\x1b[3d", 2, 0);
+        check(b"\x1b[99d", 3, 0);
+        check(b"\x1b[0d", 0, 0);
+    }
+    
+    #[test]
+    fn erase_in_display_and_line() {
+        for alt in [false, true] {
+            let mut t = terminal(3, 4);
+            if alt {
+                t.process_input(b"\x1b[?1049h");
+            }
+            let mut erase = |sequence: &[u8]| {
+                t.process_input(b"\x1b[Haaaa\r\nbbbb\r\ncccc\x1b[2;2H");
+                t.process_input(sequence);
+                assert_eq!((t.state.cursor_y, t.state.cursor_x), (1, 1));
+                visible_screen(&t)
+            };
+            
+            assert_eq!(erase(b"\x1b[K"), ["aaaa", "b", "cccc"]);
+            assert_eq!(erase(b"\x1b[0K"), ["aaaa", "b", "cccc"]);
+            assert_eq!(erase(b"\x1b[1K"), ["aaaa", "  bb", "cccc"]);
+            assert_eq!(erase(b"\x1b[2K"), ["aaaa", "", "cccc"]);
+            assert_eq!(erase(b"\x1b[J"), ["aaaa", "b", ""]);
+            assert_eq!(erase(b"\x1b[0J"), ["aaaa", "b", ""]);
+            assert_eq!(erase(b"\x1b[1J"), ["", "  bb", "cccc"]);
+            assert_eq!(erase(b"\x1b[2J"), ["", "", ""]);
+        }
+    }
+    
+    #[test]
+    fn erase_scrollback_keeps_the_screen() {
+        let mut t = terminal(3, 4);
+        numbered(&mut t, 5);
+        assert_eq!(t.state.scrollback.len(), 2);
+        t.process_input(b"\x1b[3J");
+        assert!(t.state.scrollback.is_empty());
+        assert_eq!(screen(&t), ["3", "4", "5"]);
+        
+        // On the alternate screen it still clears the main scrollback
+        numbered(&mut t, 3);
+        t.process_input(b"\x1b[?1049h\x1b[Halt\x1b[3J");
+        assert!(t.state.scrollback.is_empty());
+        assert_eq!(visible_screen(&t), ["alt", "", ""]);
+        t.process_input(b"\x1b[?1049l");
+        assert_eq!(screen(&t), ["51", "2", "3"]);
+    }
+    
+    #[test]
+    fn linefeed_scrolls_only_inside_region() {
+        let mut term = terminal(5, 10);
+        numbered(&mut term, 5);
+        term.process_input(b"\x1b[2;4r\x1b[4;1H\n\n");
+        
+        assert_eq!(screen(&term), ["1", "4", "", "", "5"]);
+        assert_eq!(term.state.cursor_y, 3);
+        assert!(term.state.scrollback.is_empty());
+    }
+    
+    #[test]
+    fn full_screen_scroll_fills_scrollback() {
+        let mut term = terminal(3, 10);
+        numbered(&mut term, 5);
+        
+        assert_eq!(screen(&term), ["3", "4", "5"]);
+        assert_eq!(term.state.scrollback.len(), 2);
+        assert_eq!(term.state.line(0).unwrap().cells[0].c, '1');
+        assert_eq!(term.state.line(3).unwrap().cells[0].c, '4');
+    }
+    
+    #[test]
+    fn invalid_region_is_ignored() {
+        let mut term = terminal(5, 10);
+        term.process_input(b"\x1b[3;3r");
+        
+        assert_eq!((term.state.scroll_top, term.state.scroll_bottom), (0, 5));
+    }
+    
+    #[test]
+    fn reverse_index_at_top_margin_scrolls_down() {
+        let mut term = terminal(5, 10);
+        numbered(&mut term, 5);
+        term.process_input(b"\x1b[2;4r\x1b[2;1H\x1bM");
+        
+        assert_eq!(screen(&term), ["1", "", "2", "3", "5"]);
+        assert_eq!(term.state.cursor_y, 1);
+    }
+    
+    #[test]
+    fn reverse_index_above_region_moves_up() {
+        let mut term = terminal(5, 10);
+        numbered(&mut term, 5);
+        term.process_input(b"\x1b[3;5r\x1b[2;1H\x1bM\x1bM");
+        
+        assert_eq!(screen(&term), ["1", "2", "3", "4", "5"]);
+        assert_eq!(term.state.cursor_y, 0);
+    }
+    
+    #[test]
+    fn index_and_next_line() {
+        let mut term = terminal(3, 10);
+        term.process_input(b"ab\x1bDc\x1bEd");
+        
+        assert_eq!(screen(&term), ["ab", "  c", "d"]);
+    }
+    
+    #[test]
+    fn insert_lines_within_region() {
+        let mut term = terminal(5, 10);
+        numbered(&mut term, 5);
+        term.process_input(b"\x1b[1;4r\x1b[2;3H\x1b[2L");
+        
+        assert_eq!(screen(&term), ["1", "", "", "2", "5"]);
+        assert_eq!(term.state.cursor_x, 0);
+    }
+    
+    #[test]
+    fn delete_lines_within_region() {
+        let mut term = terminal(5, 10);
+        numbered(&mut term, 5);
+        term.process_input(b"\x1b[1;4r\x1b[2;1H\x1b[M");
+        
+        assert_eq!(screen(&term), ["1", "3", "4", "", "5"]);
+    }
+    
+    #[test]
+    fn insert_lines_outside_region_is_ignored() {
+        let mut term = terminal(5, 10);
+        numbered(&mut term, 5);
+        term.process_input(b"\x1b[1;3r\x1b[5;1H\x1b[L");
+        
+        assert_eq!(screen(&term), ["1", "2", "3", "4", "5"]);
+    }
+    
+    #[test]
+    fn insert_and_delete_chars() {
+        let mut term = terminal(2, 6);
+        term.process_input(b"abcdef\x1b[1;2H\x1b[2@");
+        assert_eq!(screen(&term)[0], "a  bcd");
+        
+        term.process_input(b"\x1b[3P");
+        assert_eq!(screen(&term)[0], "acd");
+    }
+    
+    #[test]
+    fn insert_chars_inside_wide_character_clears_it() {
+        let mut term = terminal(2, 6);
+        term.process_input("a界b\x1b[1;3H\x1b[@".as_bytes());
+        assert_eq!(screen(&term)[0], "a   b");
+        let widths: Vec<CellWidth> = term.state.buffer[0].cells.iter().map(|cell| cell.width).collect();
+        assert!(widths.iter().all(|&width| width == CellWidth::Single), "{:?}", widths);
+        assert_eq!((term.state.cursor_y, term.state.cursor_x), (0, 2));
+        
+        // From the wide character's first half it shifts whole
+        term.process_input("\x1b[H界\x1b[H\x1b[@".as_bytes());
+        assert_eq!(screen(&term)[0], " 界  b");
+    }
+    
+    #[test]
+    fn erase_chars_does_not_shift() {
+        let mut term = terminal(2, 6);
+        term.process_input(b"abcdef\x1b[1;2H\x1b[2X");
+        
+        assert_eq!(screen(&term)[0], "a  def");
+    }
+    
+    #[test]
+    fn scroll_up_and_down_within_region() {
+        let mut term = terminal(5, 10);
+        numbered(&mut term, 5);
+        term.process_input(b"\x1b[2;4r\x1b[S");
+        assert_eq!(screen(&term), ["1", "3", "4", "", "5"]);
+        
+        term.process_input(b"\x1b[2T");
+        assert_eq!(screen(&term), ["1", "", "", "3", "5"]);
+    }
+    
+    #[test]
+    fn cursor_up_stops_at_top_margin() {
+        let mut term = terminal(5, 10);
+        term.process_input(b"\x1b[3;5r\x1b[4;1H\x1b[10A");
+        
+        assert_eq!(term.state.cursor_y, 2);
+    }
+    
+    #[test]
+    fn alt_screen_1049_restores_main_screen_and_cursor() {
+        let mut term = terminal(3, 10);
+        term.process_input(b"shell\x1b[?1049h\x1b[2;3Hvim");
+        assert!(term.state.using_alt_buffer);
+        assert_eq!(term.state.alt_buffer[1].cells[2].c, 'v');
+        
+        term.process_input(b"\x1b[?1049l");
+        assert!(!term.state.using_alt_buffer);
+        assert_eq!(screen(&term), ["shell", "", ""]);
+        assert_eq!((term.state.cursor_x, term.state.cursor_y), (5, 0));
+    }
+    
+    #[test]
+    fn alt_screen_does_not_touch_scrollback() {
+        let mut term = terminal(2, 10);
+        term.process_input(b"\x1b[?1049h");
+        numbered(&mut term, 5);
+        
+        assert!(term.state.scrollback.is_empty());
+        term.process_input(b"\x1b[?1049l\x1b[?47h");
+        assert!(term.state.alt_buffer.iter().flat_map(|row| &row.cells).all(|cell| cell.c == '\0'));
+    }
+    
+    #[test]
+    fn decsc_decrc_round_trip_pen() {
+        let mut term = terminal(3, 10);
+        term.process_input(b"\x1b[2;4H\x1b[1m\x1b7\x1b[H\x1b[0m\x1b8x");
+        
+        assert_eq!(term.state.buffer[1].cells[3].c, 'x');
+        assert!(term.state.buffer[1].cells[3].attrs.bold);
+    }
+    
+    #[test]
+    fn resize_reflows_wrapped_lines() {
+        let mut term = terminal(4, 6);
+        term.process_input(b"abcdefgh\r\nxy");
+        assert_eq!(screen(&term), ["abcdef", "gh", "xy", ""]);
+        
+        term.resize(4, 4);
+        assert_eq!(screen(&term), ["abcd", "efgh", "xy", ""]);
+        assert_eq!((term.state.cursor_x, term.state.cursor_y), (2, 2));
+        
+        term.resize(4, 10);
+        assert_eq!(screen(&term), ["abcdefgh", "xy", "", ""]);
+        assert_eq!((term.state.cursor_x, term.state.cursor_y), (2, 1));
+    }
+    
+    #[test]
+    fn resize_moves_overflow_into_scrollback_and_back() {
+        let mut term = terminal(3, 4);
+        term.process_input(b"abcdefg\r\nxy");
+        
+        term.resize(3, 2);
+        assert_eq!(screen(&term), ["g", "xy", ""]);
+        assert_eq!(term.state.scrollback.len(), 3);
+        assert_eq!((term.state.cursor_x, term.state.cursor_y), (0, 2));
+        
+        term.resize(3, 4);
+        assert_eq!(screen(&term), ["abcd", "efg", "xy"]);
+        assert!(term.state.scrollback.is_empty());
+    }
+    
+    #[test]
+    fn resize_keeps_alt_screen_unwrapped() {
+        let mut term = terminal(3, 6);
+        term.process_input(b"abcdefgh\x1b[?1049h\x1b[Habcdef");
+        
+        term.resize(4, 3);
+        assert_eq!(term.state.alt_buffer.len(), 4);
+        assert!(term.state.alt_buffer.iter().all(|row| row.cells.len() == 3));
+        assert_eq!(term.state.alt_buffer[0].cells[2].c, 'c');
+        
+        term.process_input(b"\x1b[?1049l");
+        assert_eq!(screen(&term), ["abc", "def", "gh", ""]);
+        assert_eq!((term.state.cursor_x, term.state.cursor_y), (2, 2));
+    }
+    
+    #[test]
+    fn resize_on_alt_screen_47_keeps_main_cursor_line() {
+        for mode in ["47", "1047"] {
+            let mut term = terminal(3, 6);
+            term.process_input(format!("1\r\n2\r\n3\x1b[?{}h\x1b[Halt", mode).as_bytes());
+            
+            // Lines above the primary cursor go to scrollback, not the
+            // ones at the alternate screen's cursor
+            term.resize(2, 6);
+            term.process_input(format!("\x1b[?{}l", mode).as_bytes());
+            assert_eq!(screen(&term), ["2", "3"], "mode {}", mode);
+            assert_eq!(term.state.scrollback.len(), 1);
+        }
+    }
+    
+    #[test]
+    fn wide_chars_take_two_cells() {
+        let mut term = terminal(2, 6);
+        term.process_input("a漢b".as_bytes());
+        
+        let cells = &term.state.buffer[0].cells;
+        assert_eq!(cells[1].width, CellWidth::Wide);
+        assert_eq!(cells[2].width, CellWidth::Spacer);
+        assert_eq!(cells[3].c, 'b');
+        assert_eq!(term.state.cursor_x, 4);
+    }
+    
+    #[test]
+    fn combining_marks_join_previous_cell() {
+        let mut term = terminal(2, 6);
+        term.process_input("e\u{301}x".as_bytes());
+        
+        assert_eq!(term.state.buffer[0].cells[0].grapheme(), "e\u{301}");
+        assert_eq!(term.state.buffer[0].cells[1].c, 'x');
+        assert_eq!(term.state.cursor_x, 2);
+    }
+    
+    #[test]
+    fn zwj_sequences_form_one_cell() {
+        let mut term = terminal(2, 10);
+        term.process_input("👩\u{200d}💻!".as_bytes());
+        
+        assert_eq!(term.state.buffer[0].cells[0].grapheme(), "👩\u{200d}💻");
+        assert_eq!(screen(&term)[0], "👩\u{200d}💻!");
+    }
+    
+    #[test]
+    fn overwriting_half_of_wide_char_clears_other_half() {
+        let mut term = terminal(2, 6);
+        term.process_input("漢字\x1b[1;2Hx".as_bytes());
+        assert_eq!(screen(&term)[0], " x字");
+        
+        term.process_input("\x1b[1;4Hy".as_bytes());
+        assert_eq!(screen(&term)[0], " x y");
+        assert!(term.state.buffer[0].cells.iter().all(|cell| cell.width == CellWidth::Single));
+    }
+    
+    #[test]
+    fn wide_char_at_margin_wraps_early() {
+        let mut term = terminal(2, 3);
+        term.process_input("ab漢".as_bytes());
+        
+        assert_eq!(screen(&term), ["ab", "漢"]);
+        assert!(term.state.buffer[0].wrapped);
+    }
+    
+    #[test]
+    fn reflow_keeps_wide_chars_whole() {
+        let mut term = terminal(3, 6);
+        term.process_input("a漢字b".as_bytes());
+        
+        term.resize(3, 4);
+        assert_eq!(screen(&term), ["a漢", "字b", ""]);
+        
+        term.resize(3, 2);
+        term.resize(3, 6);
+        assert_eq!(screen(&term), ["a漢字b", "", ""]);
+    }
+    
+    #[test]
+    fn full_width_line_then_crlf_advances_once() {
+        let mut term = terminal(3, 5);
+        term.process_input(b"abcde\r\nfg");
+        
+        assert_eq!(screen(&term), ["abcde", "fg", ""]);
+        assert!(!term.state.buffer[0].wrapped);
+    }
+    
+    #[test]
+    fn pending_wrap_keeps_cursor_in_last_column() {
+        let mut term = terminal(3, 5);
+        term.process_input(b"abcde");
+        
+        assert_eq!((term.state.cursor_x, term.state.cursor_y), (4, 0));
+        assert!(term.state.pending_wrap);
+        
+        term.process_input(b"f");
+        assert_eq!(screen(&term), ["abcde", "f", ""]);
+        assert!(term.state.buffer[0].wrapped);
+        assert_eq!((term.state.cursor_x, term.state.cursor_y), (1, 1));
+    }
+    
+    #[test]
+    fn prompt_redraw_at_right_margin() {
+        let mut term = terminal(3, 5);
+        // A prompt that exactly fills the row is redrawn in place with CR + EL
+        term.process_input(b"$ abc\r\x1b[K$ xyz");
+        
+        assert_eq!(screen(&term), ["$ xyz", "", ""]);
+        assert_eq!(term.state.cursor_y, 0);
+        
+        term.process_input(b"\r\n");
+        assert_eq!(term.state.cursor_y, 1);
+    }
+    
+    #[test]
+    fn cursor_movement_cancels_pending_wrap() {
+        let mut term = terminal(3, 5);
+        term.process_input(b"abcde\x1b[1;5HX\x08Y");
+        
+        assert_eq!(screen(&term), ["abcYX", "", ""]);
+        assert_eq!(term.state.cursor_y, 0);
+    }
+    
+    #[test]
+    fn combining_mark_after_last_column_joins_that_cell() {
+        let mut term = terminal(2, 3);
+        term.process_input("abe\u{301}".as_bytes());
+        
+        assert_eq!(term.state.buffer[0].cells[2].grapheme(), "e\u{301}");
+        assert!(term.state.pending_wrap);
+    }
+    
+    #[test]
+    fn decawm_off_overwrites_last_column() {
+        let mut term = terminal(2, 5);
+        term.process_input(b"\x1b[?7labcdefg");
+        assert_eq!(screen(&term), ["abcdg", ""]);
+        
+        term.process_input(b"\x1b[?7h\rabcdefg");
+        assert_eq!(screen(&term), ["abcde", "fg"]);
+    }
+    
+    #[test]
+    fn insert_mode_shifts_line() {
+        let mut term = terminal(2, 6);
+        term.process_input(b"abcd\x1b[1;2H\x1b[4hXY\x1b[4lZ");
+        
+        assert_eq!(screen(&term)[0], "aXYZcd");
+    }
+    
+    #[test]
+    fn rep_repeats_last_character() {
+        let mut term = terminal(2, 6);
+        term.process_input(b"x\x1b[3by\x1b[b");
+        
+        assert_eq!(screen(&term)[0], "xxxxyy");
+        assert!(term.state.pending_wrap);
+    }
+    
+    #[test]
+    fn tabs_keep_separate_screens() {
+        let mut term = Terminal::new(Config::default()).unwrap();
+        let first = term.focused_pane_id();
+        term.create_new_tab("second".to_string());
+        let second = term.tabs()[1].layout.focused();
+        
+        term.process_output(first, b"one");
+        term.process_output(second, b"two");
+        
+        assert_eq!(screen(term.tabs()[0].focused_emulator())[0], "one");
+        assert_eq!(screen(term.tabs()[1].focused_emulator())[0], "two");
+        assert!(!term.tabs()[0].has_activity);
+        assert!(term.tabs()[1].has_activity);
+        
+        term.switch_tab(1);
+        assert!(!term.tabs()[1].has_activity);
+        assert_eq!(term.active_state().buffer[0].cells[0].c, 't');
+    }
+    
+    #[test]
+    fn closing_the_active_tab_clears_activity_on_the_next_one() {
+        let mut term = Terminal::new(Config::default()).unwrap();
+        let first = term.focused_pane_id();
+        term.create_new_tab("second".to_string());
+        term.switch_tab(1);
+        term.process_output(first, b"busy");
+        assert!(term.tabs()[0].has_activity);
+        
+        term.close_tab(1);
+        assert_eq!(term.active_tab(), 0);
+        assert!(!term.tabs()[0].has_activity);
+    }
+    
+    #[test]
+    fn panes_share_the_cache_budget() {
+        let mut config = Config::default();
+        config.scrollback.lines = usize::MAX;
+        let mut term = Terminal::new(config.clone()).unwrap();
+        let first = term.focused_pane_id();
+        let limit = |term: &Terminal, pane: PaneId, panes: usize| {
+            let state = term.pane_state(pane).unwrap();
+            assert_eq!(state.scrollback_limit, config.scrollback_capacity(state.cols, std::mem::size_of::<Cell>(), panes));
+        };
+        limit(&term, first, 1);
+        
+        let second = term.split_pane(SplitDirection::Horizontal).unwrap();
+        term.create_new_tab("third".to_string());
+        limit(&term, first, 3);
+        limit(&term, second, 3);
+        
+        term.close_tab(1);
+        limit(&term, first, 2);
+    }
+    
+    #[test]
+    fn osc_sets_title_and_icon_name() {
+        let mut t = terminal(2, 10);
+        t.process_input(b"\x1b]0;both\x07");
+        assert_eq!(t.state.title.as_deref(), Some("both"));
+        assert_eq!(t.state.icon_name.as_deref(), Some("both"));
+        
+        t.process_input(b"\x1b]2;vim; file.rs\x1b\\\x1b]1;icon\x07");
+        assert_eq!(t.state.title.as_deref(), Some("vim; file.rs"));
+        assert_eq!(t.state.icon_name.as_deref(), Some("icon"));
+        
+        t.process_input(b"\x1b]2;\x07");
+        assert_eq!(t.state.title, None);
+    }
+    
+    #[test]
+    fn title_stack_push_and_pop() {
+        let mut t = terminal(2, 10);
+        t.process_input(b"\x1b]0;shell\x07\x1b[22;0t\x1b]0;vim\x07\x1b[22;2t\x1b]2;other\x07");
+        
+        t.process_input(b"\x1b[23;2t");
+        assert_eq!(t.state.title.as_deref(), Some("vim"));
+        t.process_input(b"\x1b[23;0t");
+        assert_eq!(t.state.title.as_deref(), Some("shell"));
+        assert_eq!(t.state.icon_name.as_deref(), Some("shell"));
+        
+        for _ in 0..20 {
+            t.process_input(b"\x1b[22t");
+        }
+        assert_eq!(t.state.title_stack.len(), TITLE_STACK_LIMIT);
+    }
+    
+    #[test]
+    fn osc8_tags_cells_with_links() {
+        let mut t = terminal(2, 20);
+        t.process_input(b"\x1b]8;id=a;https://x.test/?q=1;2\x1b\\ab\x1b]8;;\x1b\\ c");
+        t.process_input(b"\x1b]8;id=a;https://x.test/?q=1;2\x07d\x1b]8;;\x07");
+        
+        let cells = &t.state.buffer[0].cells;
+        let link = cells[0].link.expect("link");
+        assert_eq!(t.state.hyperlink(link).unwrap().uri, "https://x.test/?q=1;2");
+        assert_eq!(cells[1].link, Some(link));
+        assert_eq!(cells[2].link, None);
+        assert_eq!(cells[4].link, Some(link));
+        
+        assert!(t.state.hover(0, 4));
+        assert!(t.state.is_hovered(&t.state.buffer[0].cells[0]));
+        assert!(!t.state.is_hovered(&t.state.buffer[0].cells[3]));
+        assert!(t.state.hover(0, 2));
+        assert_eq!(t.state.hovered_link, None);
+    }
+    
+    #[test]
+    fn unreferenced_hyperlinks_are_collected() {
+        let mut t = terminal(2, 10);
+        for i in 0..HYPERLINK_GC_THRESHOLD {
+            t.process_input(format!("\x1b]8;;https://x.test/{}\x07x\r", i).as_bytes());
+        }
+        t.process_input(b"\x1b]8;;\x07");
+        let kept = t.state.buffer[0].cells[0].link.unwrap();
+        
+        t.process_input(b"\x1b]8;;https://y.test\x07");
+        assert_eq!(t.state.hyperlinks.len(), 2);
+        assert!(t.state.hyperlink(kept).is_some());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn hovered_link_opens_with_the_configured_opener() {
+        let opened = std::env::temp_dir().join(format!("kitty-plus-opened-{}", std::process::id()));
+        let mut config = Config::default();
+        config.hyperlinks.opener = "/bin/sh".to_string();
+        config.hyperlinks.opener_args = vec![
+            "-c".to_string(),
+            format!("printf %s \"$1\" > {}", opened.display()),
+            "sh".to_string(),
+        ];
+        let mut term = Terminal::new(config).unwrap();
+        term.resize(4, 20);
+        term.process_input(b"see \x1b]8;;https://example.com\x07here\x1b]8;;\x07");
+        
+        let cell_size = (8.0, 16.0);
+        assert!(!term.hover((8.0, 4.0), cell_size));
+        assert!(term.hovered_link().is_none());
+        assert!(term.hover((40.0, 4.0), cell_size));
+        assert_eq!(term.hovered_link().map(|link| link.uri.as_str()), Some("https://example.com"));
+        term.open_hovered_link().unwrap();
+        
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut uri = String::new();
+        while uri.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            uri = std::fs::read_to_string(&opened).unwrap_or_default();
+        }
+        let _ = std::fs::remove_file(&opened);
+        assert_eq!(uri, "https://example.com");
+        
+        // Moving off the link clears it
+        assert!(term.hover((100.0, 4.0), cell_size));
+        assert!(term.hovered_link().is_none());
+    }
+    
+    fn clipboard_terminal(policy: ClipboardPolicy) -> Terminal {
+        let mut config = Config::default();
+        config.clipboard.osc52 = policy;
+        config.clipboard.osc52_max_bytes = 8;
+        let mut term = Terminal::new(config).unwrap();
+        term.set_clipboard(Box::<clipboard::MemoryClipboard>::default());
+        term
+    }
+    
+    #[test]
+    fn osc52_copies_and_replies_per_policy() {
+        let mut term = clipboard_terminal(ClipboardPolicy::Allow);
+        let pane = term.focused_pane_id();
+        
+        term.process_output(pane, b"\x1b]52;c;aGVsbG8=\x07\x1b]52;p;d29ybGQ=\x07");
+        assert_eq!(term.clipboard.load(Selection::Clipboard).unwrap(), "hello");
+        assert_eq!(term.clipboard.load(Selection::Primary).unwrap(), "world");
+        
+        // Over the 8 byte limit
+        term.process_output(pane, b"\x1b]52;c;dG9vIGxvbmcgdGV4dA==\x07");
+        assert_eq!(term.clipboard.load(Selection::Clipboard).unwrap(), "hello");
+        
+        term.process_output(pane, b"\x1b]52;c;?\x07");
+        assert_eq!(term.active_state().replies, b"\x1b]52;c;aGVsbG8=\x1b\\");
+        
+        let mut term = clipboard_terminal(ClipboardPolicy::WriteOnly);
+        term.process_output(pane, b"\x1b]52;c;aGk=\x07\x1b]52;c;?\x07");
+        assert_eq!(term.clipboard.load(Selection::Clipboard).unwrap(), "hi");
+        assert!(term.active_state().replies.is_empty());
+        
+        let mut term = clipboard_terminal(ClipboardPolicy::Deny);
+        term.process_output(pane, b"\x1b]52;c;aGk=\x07");
+        assert_eq!(term.clipboard.load(Selection::Clipboard).unwrap(), "");
+    }
+    
+    #[test]
+    fn osc52_waits_for_user_under_ask_policy() {
+        let mut term = clipboard_terminal(ClipboardPolicy::Ask);
+        let pane = term.focused_pane_id();
+        let other = term.split_pane(SplitDirection::Horizontal).unwrap();
+        term.process_output(pane, b"\x1b]52;c;aGk=\x07");
+        term.process_output(other, b"\x1b]52;c;?\x07");
+        
+        // The pane's second request replaces its first
+        term.process_output(pane, b"\x1b]52;c;Ynll\x07");
+        assert_eq!(term.pending_clipboard.len(), 2);
+        assert_eq!(term.pending_clipboard_request(), Some(&(other, ClipboardRequest::Load(Selection::Clipboard))));
+        term.answer_clipboard_request(false);
+        assert_eq!(
+            term.pending_clipboard_request(),
+            Some(&(pane, ClipboardRequest::Store(Selection::Clipboard, "bye".to_string())))
+        );
+        term.answer_clipboard_request(true);
+        assert_eq!(term.pending_clipboard_request(), None);
+        assert_eq!(term.clipboard.load(Selection::Clipboard).unwrap(), "bye");
+        assert!(term.pane_state(other).unwrap().replies.is_empty());
+    }
+    
+    const PROMPT: &[u8] = b"\x1b]133;A\x07$ \x1b]133;B\x07";
+    
+    #[test]
+    fn osc133_marks_prompts_and_commands() {
+        let mut t = terminal(4, 10);
+        t.process_input(PROMPT);
+        t.process_input(b"ls -a\r\n\x1b]133;C\x07one\r\ntwo\r\n\x1b]133;D;2\x07");
+        t.process_input(PROMPT);
+        
+        let marks: Vec<_> = t.state.buffer.iter().map(|row| row.marks).collect();
+        assert!(marks[0].prompt && marks[1].output);
+        assert!(marks[3].prompt && marks[3].command_end);
+        assert_eq!(marks[3].exit_code, Some(2));
+        
+        let command = t.state.finished_commands.pop().unwrap();
+        assert_eq!(command.command, "ls -a");
+        assert_eq!(command.exit_code, Some(2));
+        assert_eq!(command.output, 1..3);
+        assert_eq!(t.state.last_command_output().as_deref(), Some("one\ntwo"));
+        
+        assert!(t.state.select_last_command_output());
+        assert_eq!(t.state.selection, Some(1..3));
+    }
+    
+    #[test]
+    fn prompt_marks_survive_reflow_and_allow_jumping() {
+        let mut t = terminal(3, 10);
+        for command in ["a", "b", "c"] {
+            t.process_input(PROMPT);
+            t.process_input(format!("{}\r\n\x1b]133;C\x07out\r\n\x1b]133;D;0\x07", command).as_bytes());
+        }
+        t.process_input(PROMPT);
+        
+        t.resize(3, 3);
+        let prompts: Vec<_> = (t.state.first_line()..t.state.end_line())
+            .filter(|&i| t.state.line(i).unwrap().marks.prompt)
+            .collect();
+        assert_eq!(prompts.len(), 4);
+        assert!(prompts.iter().all(|&i| t.state.text((i, 0), (i, 1)) == "$"));
+        
+        assert!(t.state.scroll_to_prompt(false));
+        assert!(t.state.scroll_to_prompt(false));
+        let top = t.state.end_line() - t.state.rows - t.state.display_offset;
+        assert_eq!(top, prompts[0]);
+        
+        assert!(t.state.scroll_to_prompt(true));
+        assert!(t.state.scroll_to_prompt(true));
+        assert_eq!(t.state.display_offset, 0);
+        assert!(!t.state.scroll_to_prompt(true));
+    }
+    
+    #[test]
+    fn osc_notifications_are_queued() {
+        let mut t = terminal(2, 10);
+        t.process_input(b"\x1b]9;build done\x07\x1b]9;4;1;50\x07");
+        t.process_input(b"\x1b]777;notify;Tests;12 passed; 0 failed\x1b\\");
+        t.process_input(b"\x1b]99;i=1:d=0;Hello\x1b\\\x1b]99;i=1:p=body:e=1;d29ybGQ=\x1b\\");
+        
+        let notification = |title: &str, body: &str, id: Option<&str>| Notification {
+            id: id.map(String::from),
+            title: title.to_string(),
+            body: body.to_string(),
+        };
+        assert_eq!(
+            t.state.notifications,
+            [
+                notification("build done", "", None),
+                notification("Tests", "12 passed; 0 failed", None),
+                notification("Hello", "world", Some("1")),
+            ]
+        );
+    }
+    
+    #[test]
+    fn notifications_reach_notifier_and_flag_tab() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<std::sync::Mutex<Vec<Notification>>>);
+        
+        impl Notifier for Shared {
+            fn notify(&mut self, notification: &Notification) -> Result<()> {
+                self.0.lock().unwrap().push(notification.clone());
+                Ok(())
+            }
+        }
+        
+        let mut config = Config::default();
+        config.features.activity_indicators = false;
+        let mut term = Terminal::new(config).unwrap();
+        let shown = Shared::default();
+        term.set_notifier(Box::new(shown.clone()));
+        term.create_new_tab("background".to_string());
+        let pane = term.tabs()[1].layout.focused();
+        
+        term.process_output(pane, b"plain output");
+        assert!(!term.tabs()[1].has_activity);
+        
+        term.process_output(pane, b"\x1b]9;done\x07");
+        assert_eq!(shown.0.lock().unwrap().len(), 1);
+        assert!(term.tabs()[1].has_activity);
+    }
+    
+    #[test]
+    fn answers_device_attribute_and_status_queries() {
+        let mut t = terminal(5, 10);
+        t.process_input(b"\x1b[c\x1b[>c\x1b[5n");
+        assert_eq!(
+            std::mem::take(&mut t.state.replies),
+            format!("\x1b[?62;4;22c\x1b[>1;{};0c\x1b[0n", version_number()).as_bytes()
+        );
+        
+        t.process_input(b"\x1b[3;4H\x1b[6n\x1b[?6n");
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b[3;4R\x1b[?3;4R");
+        
+        // Pending wrap still reports the last column
+        t.process_input(b"\x1b[1;1Habcdefghij\x1b[6n");
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b[1;10R");
+        
+        t.process_input(b"\x1b[>q");
+        let version = String::from_utf8(std::mem::take(&mut t.state.replies)).unwrap();
+        assert!(version.starts_with("\x1bP>|kitty-plus(") && version.ends_with(")\x1b\\"));
+    }
+    
+    #[test]
+    fn keyboard_flags_are_stacked_per_screen() {
+        let mut t = terminal(5, 10);
+        t.process_input(b"\x1b[?u\x1b[>1u\x1b[>3u\x1b[?u");
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b[?0u\x1b[?3u");
+        
+        t.process_input(b"\x1b[=8;2u\x1b[?u\x1b[=2;3u\x1b[?u\x1b[=31u\x1b[?u");
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b[?11u\x1b[?9u\x1b[?31u");
+        
+        // The alternate screen starts without flags and keeps its own stack
+        t.process_input(b"\x1b[?1049h\x1b[?u\x1b[>4u\x1b[?1049l\x1b[?u");
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b[?0u\x1b[?31u");
+        
+        t.process_input(b"\x1b[<u\x1b[?u\x1b[<5u\x1b[?u");
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b[?1u\x1b[?0u");
+        assert_eq!(t.state.alt_keyboard_flags, [KeyboardFlags::REPORT_ALTERNATE_KEYS]);
+        
+        for _ in 0..KEYBOARD_STACK_LIMIT + 2 {
+            t.process_input(b"\x1b[>1u");
+        }
+        assert_eq!(t.state.keyboard_flags.len(), KEYBOARD_STACK_LIMIT);
+    }
+    
+    #[test]
+    fn mouse_modes_are_tracked_and_queryable() {
+        let mut t = terminal(5, 10);
+        let press = MouseEvent {
+            kind: MouseEventKind::Press(MouseButton::Left),
+            modifiers: Modifiers::NONE,
+            col: 1,
+            row: 1,
+            x: 10.0,
+            y: 20.0,
+        };
+        assert!(!t.send_mouse(press).unwrap());
+        
+        t.process_input(b"\x1b[?1000h\x1b[?1002h\x1b[?1006h\x1b[?1000l");
+        assert_eq!(t.state.modes.mouse_tracking, MouseTracking::ButtonEvent);
+        assert_eq!(t.state.modes.mouse_encoding, MouseEncoding::Sgr);
+        t.process_input(b"\x1b[?1002$p\x1b[?1000$p\x1b[?1006$p\x1b[?1016$p");
+        assert_eq!(
+            std::mem::take(&mut t.state.replies),
+            b"\x1b[?1002;1$y\x1b[?1000;2$y\x1b[?1006;1$y\x1b[?1016;2$y"
+        );
+        
+        assert!(t.send_mouse(press).unwrap());
+        assert!(!t.send_mouse(MouseEvent { modifiers: Modifiers::SHIFT, ..press }).unwrap());
+        
+        t.process_input(b"\x1b[?1002l\x1b[?1006l");
+        assert_eq!(t.state.modes.mouse_tracking, MouseTracking::Off);
+        assert_eq!(t.state.modes.mouse_encoding, MouseEncoding::Default);
+    }
+    
+    #[test]
+    fn mouse_positions_past_the_grid_go_to_the_edge_pane() {
+        let mut term = Terminal::new(Config::default()).unwrap();
+        term.resize(4, 10);
+        let right = term.split_pane(SplitDirection::Horizontal).unwrap();
+        term.process_input(b"\x1b[?1003h");
+        
+        let cell_size = (8.0, 16.0);
+        let moved = term.send_mouse(MouseEventKind::Motion(None), Modifiers::NONE, (500.0, 500.0), cell_size);
+        assert!(moved.unwrap());
+        let emulator = &term.find_pane_mut(right).unwrap().emulator;
+        assert_eq!(emulator.mouse_cell, Some((4, 3)));
+        
+        let left = term.tabs[0].panes[0].id;
+        assert!(term.pane_at((-5.0, 20.0), cell_size).is_some_and(|(pane_id, ..)| pane_id == left));
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shell_output_wakes_the_window() {
+        let mut config = Config::default();
+        config.shell.program = Some("/bin/sh".to_string());
+        config.shell.args = vec!["-c".to_string(), "printf hi".to_string()];
+        let terminal = Arc::new(Mutex::new(Terminal::new(config).unwrap()));
+        let (sender, mut wakeups) = mpsc::unbounded_channel();
+        terminal.lock().await.set_wakeup(Box::new(move || {
+            let _ = sender.send(());
+        }));
+        
+        let pane_id = terminal.lock().await.focused_pane_id();
+        Terminal::spawn_shell(terminal.clone(), pane_id).await.unwrap();
+        let woken = tokio::time::timeout(Duration::from_secs(5), wakeups.recv()).await;
+        assert_eq!(woken, Ok(Some(())));
+    }
+    
+    #[test]
+    fn synchronized_updates_hold_back_rendering_until_timeout() {
+        let mut term = Terminal::new(Config::default()).unwrap();
+        assert!(term.should_render(Instant::now()));
+        
+        assert_eq!(term.synchronized_update_deadline(), None);
+        
+        term.process_input(b"\x1b[?2026h\x1b[?2026$p");
+        let now = Instant::now();
+        assert_eq!(term.active_state().replies, b"\x1b[?2026;1$y");
+        let since = term.active_state().synchronized_since.unwrap();
+        assert_eq!(term.synchronized_update_deadline(), Some(since + SYNCHRONIZED_UPDATE_TIMEOUT));
+        assert!(!term.should_render(now));
+        assert!(term.should_render(now + SYNCHRONIZED_UPDATE_TIMEOUT));
+        assert!(term.active_state().synchronized_since.is_none());
+        
+        term.process_input(b"\x1b[?2026h\x1b[?2026l");
+        assert!(term.should_render(now));
+    }
+    
+    #[test]
+    fn cursor_visibility_is_tracked_and_queryable() {
+        let mut t = terminal(5, 10);
+        assert!(t.state.modes.cursor_visible);
+        t.process_input(b"\x1b[?25l\x1b[?25$p");
+        assert!(!t.state.modes.cursor_visible);
+        t.process_input(b"\x1b[?25h\x1b[?25$p");
+        assert!(t.state.modes.cursor_visible);
+        assert_eq!(t.state.replies, b"\x1b[?25;2$y\x1b[?25;1$y");
+    }
+    
+    #[test]
+    fn paste_and_focus_modes_are_queryable() {
+        let mut t = terminal(5, 10);
+        t.process_input(b"\x1b[?2004h\x1b[?1004h\x1b[?2004$p\x1b[?1004$p\x1b[?1004l\x1b[?1004$p");
+        assert!(t.state.modes.bracketed_paste);
+        assert!(!t.state.modes.focus_events);
+        assert_eq!(t.state.replies, b"\x1b[?2004;1$y\x1b[?1004;1$y\x1b[?1004;2$y");
+        
+        assert_eq!(encode_paste("ls\r\nrm -rf x\n", false), b"ls\rrm -rf x\r");
+        assert_eq!(
+            encode_paste("a\x1b[201~\nb\x1b[20\x1b[201~1~", true),
+            b"\x1b[200~a\nb\x1b[201~"
+        );
+    }
+    
+    #[test]
+    fn reports_mode_states() {
+        let mut t = terminal(5, 10);
+        t.process_input(b"\x1b[?7$p\x1b[?1049$p\x1b[4$p\x1b[?9999$p");
+        assert_eq!(
+            std::mem::take(&mut t.state.replies),
+            b"\x1b[?7;1$y\x1b[?1049;2$y\x1b[4;2$y\x1b[?9999;0$y"
+        );
+        
+        t.process_input(b"\x1b[?7l\x1b[?1049h\x1b[4h\x1b[?7$p\x1b[?1049$p\x1b[4$p");
+        assert_eq!(t.state.replies, b"\x1b[?7;2$y\x1b[?1049;1$y\x1b[4;1$y");
+    }
+    
+    #[test]
+    fn sgr_sets_and_resets_attributes() {
+        let mut t = terminal(2, 10);
+        t.process_input(b"\x1b[1;2;3;4;5;7;8;9m");
+        let attrs = t.state.pen.attrs;
+        assert!(attrs.bold && attrs.dim && attrs.italic && attrs.underline);
+        assert!(attrs.blink && attrs.reverse && attrs.hidden && attrs.strikethrough);
+        
+        t.process_input(b"\x1b[22;23;24;25;27;28;29m");
+        assert_eq!(t.state.pen.attrs, Attributes::default());
+        
+        // 21 is a double underline, which is drawn as a single one
+        t.process_input(b"\x1b[21m");
+        assert!(t.state.pen.attrs.underline);
+        t.process_input(b"\x1b[4:0m");
+        assert!(!t.state.pen.attrs.underline);
+        t.process_input(b"\x1b[4:3;6m");
+        assert!(t.state.pen.attrs.underline && t.state.pen.attrs.blink);
+        t.process_input(b"\x1b[m");
+        assert_eq!(t.state.pen.attrs, Attributes::default());
+    }
+    
+    #[test]
+    fn sgr_colors() {
+        let mut t = terminal(2, 10);
+        let mut check = |input: &[u8], fg: Color, bg: Color| {
+            t.process_input(input);
+            assert_eq!((t.state.pen.fg, t.state.pen.bg), (fg, bg), "{:?}", String::from_utf8_lossy(input));
+        };
+        
+        check(b"\x1b[31;42m", Color::Indexed(1), Color::Indexed(2));
+        check(b"\x1b[90;107m", Color::Indexed(8), Color::Indexed(15));
+        check(b"\x1b[97;100m", Color::Indexed(15), Color::Indexed(8));
+        check(b"\x1b[39;49m", Color::Default, Color::Default);
+        check(b"\x1b[38;5;200;48;5;17m", Color::Indexed(200), Color::Indexed(17));
+        check(b"\x1b[38;2;1;2;3;48;2;4;5;6m", Color::rgb(1, 2, 3), Color::rgb(4, 5, 6));
+        check(b"\x1b[38;2;300;0;999m", Color::rgb(255, 0, 255), Color::rgb(4, 5, 6));
+        
+        // Colon subparameters, with or without a color space
+        check(b"\x1b[38:5:9;48:5:300m", Color::Indexed(9), Color::Indexed(255));
+        check(b"\x1b[38:2:7:8:9;48:2:0:10:11:12m", Color::rgb(7, 8, 9), Color::rgb(10, 11, 12));
+        check(b"\x1b[38:2::1:2:3m", Color::rgb(1, 2, 3), Color::rgb(10, 11, 12));
+        
+        // Malformed and truncated forms leave the colors alone
+        check(b"\x1b[31;41m", Color::Indexed(1), Color::Indexed(1));
+        check(b"\x1b[38m", Color::Indexed(1), Color::Indexed(1));
+        check(b"\x1b[38;5m", Color::Indexed(1), Color::Indexed(1));
+        check(b"\x1b[48;2;1;2m", Color::Indexed(1), Color::Indexed(1));
+        check(b"\x1b[38;7;1m", Color::Indexed(1), Color::Indexed(1));
+        check(b"\x1b[38:5m", Color::Indexed(1), Color::Indexed(1));
+        check(b"\x1b[48:2:1:2m", Color::Indexed(1), Color::Indexed(1));
+        check(b"\x1b[38:3:1:2:3m", Color::Indexed(1), Color::Indexed(1));
+    }
+    
+    #[test]
+    fn palette_follows_color_scheme_and_osc_4() {
+        let mut t = terminal(2, 10);
+        t.process_input(b"\x1b[31ma\x1b]4;1;rgb:00/ff/00\x07\x1b[31mb\x1b[38;5;1mc");
+        
+        // Cells keep the index, so text printed before OSC 4 changes too
+        let cells = &t.state.buffer[0].cells;
+        assert!(cells[..3].iter().all(|cell| cell.fg == Color::Indexed(1)));
+        let resolve = |cell: &Cell| t.state.palette.resolve(cell.fg, t.state.palette.foreground);
+        assert_eq!(resolve(&cells[0]), Rgb::new(0x00, 0xff, 0x00));
+        assert_eq!(resolve(&cells[3]), Rgb::new(0xcd, 0xd6, 0xf4));
+        
+        t.process_input(b"\x1b]4;1;?;200;?\x1b\\");
+        assert_eq!(
+            std::mem::take(&mut t.state.replies),
+            b"\x1b]4;1;rgb:0000/ffff/0000\x1b\\\x1b]4;200;rgb:ffff/0000/d7d7\x1b\\"
+        );
+        
+        t.process_input(b"\x1b]104;1\x07\x1b]4;1;?\x07");
+        assert_eq!(t.state.replies, b"\x1b]4;1;rgb:f3f3/8b8b/a8a8\x07");
+    }
+    
+    #[test]
+    fn dynamic_colors_can_be_queried_set_and_reset() {
+        let mut t = terminal(2, 10);
+        t.process_input(b"\x1b]11;?\x07");
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b]11;rgb:1e1e/1e1e/2e2e\x07");
+        
+        t.process_input(b"\x1b]10;#ffffff;#000000\x07\x1b]10;?;?\x07");
+        assert_eq!(
+            std::mem::take(&mut t.state.replies),
+            b"\x1b]10;rgb:ffff/ffff/ffff\x07\x1b]11;rgb:0000/0000/0000\x07"
+        );
+        
+        t.process_input(b"\x1b]110\x07\x1b]111\x07\x1b]12;rgb:1/2/3\x07\x1b]112\x07");
+        assert_eq!(t.state.palette, t.state.default_palette);
+    }
+    
+    fn transmit(t: &mut Emulator, keys: &str, width: usize, height: usize) {
+        use base64::Engine;
+        
+        let pixels = base64::engine::general_purpose::STANDARD.encode(vec![0x80; width * height * 4]);
+        let command = format!("\x1b_G{},s={},v={};{}\x1b\\", keys, width, height, pixels);
+        t.process_input(command.as_bytes());
+    }
+    
+    #[test]
+    fn graphics_transmit_and_put_moves_cursor_past_image() {
+        let mut t = terminal(5, 10);
+        t.process_input(b"ab");
+        transmit(&mut t, "a=T,i=1", 25, 30);
+        assert_eq!(std::mem::take(&mut t.state.replies), b"\x1b_Gi=1;OK\x1b\\");
+        
+        let placements = t.state.visible_placements();
+        assert_eq!(placements.len(), 1);
+        assert_eq!((placements[0].line, placements[0].col), (0, 2));
+        assert_eq!((placements[0].rows, placements[0].cols), (2, 3));
+        assert_eq!((t.state.cursor_y, t.state.cursor_x), (1, 5));
+        
+        // Placing again with C=1 and a size in cells keeps the cursor
+        t.process_input(b"\x1b_Ga=p,i=1,p=2,c=4,r=1,C=1,q=1\x1b\\");
+        assert!(t.state.replies.is_empty());
+        assert_eq!(t.state.visible_placements().len(), 2);
+        assert_eq!((t.state.cursor_y, t.state.cursor_x), (1, 5));
+    }
+    
+    #[test]
+    fn graphics_uploads_may_be_chunked_and_numbered() {
+        use base64::Engine;
+        
+        let mut t = terminal(5, 10);
+        let pixels = base64::engine::general_purpose::STANDARD.encode([1, 2, 3, 4, 5, 6]);
+        let (first, rest) = pixels.split_at(4);
+        t.process_input(format!("\x1b_Ga=t,I=5,f=24,s=2,v=1,m=1;{}\x1b\\", first).as_bytes());
+        assert!(t.state.replies.is_empty());
+        t.process_input(format!("\x1b_Gm=0;{}\x1b\\", rest).as_bytes());
+        
+        let id = t.state.graphics.find_number(5).unwrap();
+        assert_eq!(t.state.graphics.get(id).unwrap().pixels, [1, 2, 3, 255, 4, 5, 6, 255]);
+        assert_eq!(t.state.replies, format!("\x1b_Gi={},I=5;OK\x1b\\", id).into_bytes());
+        assert!(t.state.visible_placements().is_empty());
+    }
+    
+    #[test]
+    fn graphics_errors_are_reported_unless_quiet() {
+        let mut t = terminal(5, 10);
+        t.process_input(b"\x1b_Ga=p,i=3\x1b\\");
+        assert!(std::mem::take(&mut t.state.replies).starts_with(b"\x1b_Gi=3;ENOENT:"));
+        
+        t.process_input(b"\x1b_Ga=q,i=4,f=24,s=2,v=2;AAAA\x1b\\");
+        assert!(std::mem::take(&mut t.state.replies).starts_with(b"\x1b_Gi=4;ENODATA:"));
+        
+        t.process_input(b"\x1b_Ga=q,i=4,q=2,f=24,s=2,v=2;AAAA\x1b\\");
+        t.process_input(b"\x1b_Ga=p,q=2,i=3\x1b\\");
+        assert!(t.state.replies.is_empty());
+        assert!(t.state.graphics.get(4).is_none());
+    }
+    
+    #[test]
+    fn graphics_deletion_by_id_cursor_and_screen() {
+        let mut t = terminal(5, 10);
+        transmit(&mut t, "a=T,i=1,q=1", 10, 20);
+        transmit(&mut t, "a=T,i=2,q=1", 10, 20);
+        t.process_input(b"\x1b[1;1H");
+        transmit(&mut t, "a=T,i=3,q=1,C=1", 10, 20);
+        assert_eq!(t.state.visible_placements().len(), 3);
+        
+        // Lowercase keeps the image data, uppercase frees it
+        t.process_input(b"\x1b_Ga=d,d=i,i=1\x1b\\");
+        assert!(t.state.graphics.get(1).is_some());
+        t.process_input(b"\x1b_Ga=d,d=C\x1b\\");
+        assert!(t.state.graphics.get(3).is_none());
+        assert_eq!(t.state.visible_placements().len(), 1);
+        assert!(t.state.replies.is_empty());
+        
+        t.process_input(b"\x1b[2J");
+        assert!(t.state.visible_placements().is_empty());
+        assert!(t.state.graphics.get(2).is_some());
+    }
+    
+    #[test]
+    fn graphics_placements_scroll_with_text() {
+        let mut t = terminal(3, 10);
+        t.state.scrollback_limit = 2;
+        transmit(&mut t, "a=T,i=1,q=1", 10, 20);
+        t.process_input(b"\r\n\n\n");
+        assert!(t.state.visible_placements().is_empty());
+        t.state.display_offset = 1;
+        assert_eq!(t.state.visible_placements().len(), 1);
+        
+        // Scrolled out of the scrollback entirely
+        t.state.display_offset = 0;
+        t.process_input(b"\n\n");
+        assert!(t.state.graphics.visible(0..usize::MAX, false).is_empty());
+        assert!(t.state.graphics.get(1).is_some());
+    }
+    
+    #[test]
+    fn visible_rows_follow_scroll_position() {
+        let mut t = terminal(3, 10);
+        numbered(&mut t, 5);
+        let text = |t: &Emulator| -> Vec<(Option<usize>, String)> {
+            t.state
+                .visible_rows()
+                .into_iter()
+                .map(|(line, row)| (line, row.cells.iter().map(|cell| cell.c).filter(|c| !matches!(c, ' ' | '\0')).collect()))
+                .collect()
+        };
+        assert_eq!(text(&t), [(Some(2), "3".into()), (Some(3), "4".into()), (Some(4), "5".into())]);
+        
+        t.state.display_offset = 2;
+        assert_eq!(text(&t)[0], (Some(0), "1".to_string()));
+        
+        t.process_input(b"\x1b[?1049h\x1b[Hx");
+        assert_eq!(text(&t)[0], (None, "x".to_string()));
+    }
+    
+    #[test]
+    fn sixel_images_are_placed_at_cursor() {
+        let mut t = terminal(4, 10);
+        t.process_input(b"ab\r\n  ");
+        // 12 pixels wide and 24 high: two cells by two with 10x20 cells
+        t.process_input(b"\x1bPq#1!12~-!12~-!12~-!12~\x1b\\");
+        let placements = t.state.visible_placements();
+        assert_eq!(placements.len(), 1);
+        assert_eq!((placements[0].line, placements[0].col), (1, 2));
+        assert_eq!((placements[0].rows, placements[0].cols), (2, 2));
+        assert_eq!(t.state.graphics.get(placements[0].image_id).unwrap().height, 24);
+        assert_eq!((t.state.cursor_y, t.state.cursor_x), (3, 2));
+        
+        // The image scrolls along when it pushes the text up
+        t.process_input(b"\x1bPq!12~-!12~\x1b\\");
+        assert_eq!((t.state.cursor_y, t.state.cursor_x), (3, 2));
+        assert_eq!(t.state.visible_placements().len(), 2);
+        assert_eq!(t.state.visible_placements()[0].line, 1);
+        
+        // Sixel display mode puts images at the top left and keeps the cursor
+        t.process_input(b"\x1b[?80h\x1bPq~\x1b\\");
+        let screen_top = t.state.screen_lines().start;
+        assert!(t.state.visible_placements().iter().any(|p| (p.line, p.col) == (screen_top, 0)));
+        assert_eq!((t.state.cursor_y, t.state.cursor_x), (3, 2));
+    }
+    
+    #[test]
+    fn sixel_capabilities_are_reported() {
+        let mut t = terminal(24, 80);
+        t.process_input(b"\x1b[c\x1b[?1;1S\x1b[?2;1S\x1b[?2;4S\x1b[?1;3;16S\x1b[?3;1S");
+        assert_eq!(
+            String::from_utf8_lossy(&t.state.replies),
+            "\x1b[?62;4;22c\x1b[?1;0;256S\x1b[?2;0;800;480S\x1b[?2;0;10000;10000S\x1b[?1;3;0S\x1b[?3;1;0S"
+        );
+    }
+    
+    #[test]
+    fn osc7_tracks_working_directory() {
+        let mut t = terminal(2, 10);
+        t.process_input(b"\x1b]7;file://host/home/me/my%20dir\x07");
+        assert_eq!(t.state.cwd, Some(PathBuf::from("/home/me/my dir")));
+        
+        t.process_input(b"\x1b]7;/not/a/url\x07");
+        assert_eq!(t.state.cwd, Some(PathBuf::from("/home/me/my dir")));
+    }
+    
+    #[test]
+    fn tab_follows_focused_pane_title_and_directory() {
+        let mut term = Terminal::new(Config::default()).unwrap();
+        let first = term.focused_pane_id();
+        let dir = std::env::temp_dir();
+        let osc7 = format!("\x1b]7;file://{}\x07", dir.display());
+        
+        term.process_output(first, b"\x1b]2;editor\x07");
+        term.process_output(first, osc7.as_bytes());
+        assert_eq!(term.tabs()[0].title, "editor");
+        assert_eq!(term.tabs()[0].cwd(), Some(dir.as_path()));
+        
+        term.split_pane(SplitDirection::Horizontal);
+        assert_eq!(term.tabs()[0].title, "Terminal");
+        assert_eq!(term.tabs()[0].focused_emulator().working_directory, Some(dir.clone()));
+        
+        term.focus_pane(Direction::Left);
+        assert_eq!(term.tabs()[0].title, "editor");
+    }
+}
@@ -1,22 +1,45 @@
 use anyhow::Result;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 use winit::{
-    event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, MouseScrollDelta, StartCause, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder},
+    keyboard::ModifiersState,
+    window::{Window, WindowBuilder},
 };
 
+use crate::clipboard::ClipboardRequest;
 use crate::config::Config;
+use crate::keyboard::{self, Key, KeyEvent, KeyEventKind, NamedKey};
+use crate::mouse::{MouseButton, MouseEventKind};
 use crate::terminal::Terminal;
 use crate::gpu::GpuRenderer;
-use super::command_palette::CommandPalette;
+use super::command_palette::{CommandAction, CommandPalette};
+
+const WINDOW_TITLE: &str = "Kitty Plus - Modern Terminal";
+
+/// Events sent to the event loop from outside of it.
+#[derive(Debug)]
+enum UserEvent {
+    /// A pane's program produced output.
+    Output,
+}
 
 pub struct App {
     terminal: Arc<Mutex<Terminal>>,
     config: Config,
     command_palette: CommandPalette,
     gpu_renderer: Option<GpuRenderer>,
+    modifiers: ModifiersState,
+    window_size: PhysicalSize<u32>,
+    cursor_position: PhysicalPosition<f64>,
+    /// When to draw a frame held back by a synchronized update that hasn't
+    /// ended yet.
+    redraw_at: Option<Instant>,
+    /// Question about a clipboard request shown in the window title.
+    clipboard_prompt: Option<String>,
 }
 
 impl App {
@@ -26,52 +49,169 @@ impl App {
             config: config.clone(),
             command_palette: CommandPalette::new(config.clone()),
             gpu_renderer: None,
+            modifiers: ModifiersState::empty(),
+            window_size: PhysicalSize::new(1, 1),
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            redraw_at: None,
+            clipboard_prompt: None,
         })
     }
     
     pub async fn run(mut self) -> Result<()> {
-        let event_loop = EventLoop::new()?;
+        let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build()?;
         let window = WindowBuilder::new()
-            .with_title("Kitty Plus - Modern Terminal")
+            .with_title(WINDOW_TITLE)
             .with_inner_size(winit::dpi::LogicalSize::new(1024, 768))
             .build(&event_loop)?;
         
+        self.window_size = window.inner_size();
         if self.config.performance.gpu_acceleration {
-            self.gpu_renderer = Some(GpuRenderer::new(&window).await?);
+            self.gpu_renderer = Some(GpuRenderer::new(&window, &self.config.font).await?);
         }
+        self.fit_terminal();
+        
+        // Output arrives on the tasks reading the shells, which wake the
+        // event loop to draw it
+        let proxy = event_loop.create_proxy();
+        tokio::task::block_in_place(|| self.terminal.blocking_lock()).set_wakeup(Box::new(move || {
+            let _ = proxy.send_event(UserEvent::Output);
+        }));
         
         event_loop.run(move |event, _, control_flow| {
-            *control_flow = ControlFlow::Wait;
+            *control_flow = match self.redraw_at {
+                Some(deadline) => ControlFlow::WaitUntil(deadline),
+                None => ControlFlow::Wait,
+            };
             
             match event {
+                Event::UserEvent(UserEvent::Output) => {
+                    self.update_clipboard_prompt(&window);
+                    window.request_redraw();
+                }
+                
+                Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                    self.redraw_at = None;
+                    *control_flow = ControlFlow::Wait;
+                    window.request_redraw();
+                }
+                
                 Event::WindowEvent {
                     event: WindowEvent::CloseRequested,
                     ..
                 } => *control_flow = ControlFlow::Exit,
                 
                 Event::WindowEvent {
-                    event: WindowEvent::KeyboardInput { input, .. },
+                    event: WindowEvent::ModifiersChanged(modifiers),
+                    ..
+                } => self.modifiers = modifiers.state(),
+                
+                Event::WindowEvent {
+                    event: WindowEvent::KeyboardInput { event, .. },
                     ..
                 } => {
-                    self.handle_keyboard_input(input);
+                    self.handle_keyboard_input(event);
+                    self.update_clipboard_prompt(&window);
+                    window.request_redraw();
                 }
                 
                 Event::WindowEvent {
                     event: WindowEvent::Resized(size),
                     ..
                 } => {
+                    self.window_size = size;
                     if let Some(renderer) = &mut self.gpu_renderer {
                         renderer.resize(size.width, size.height);
                     }
+                    self.fit_terminal();
+                    window.request_redraw();
+                }
+                
+                Event::WindowEvent {
+                    event: WindowEvent::Focused(focused),
+                    ..
+                } => {
+                    let terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+                    if let Err(e) = terminal.set_window_focus(focused) {
+                        log::warn!("Failed to report focus change: {:#}", e);
+                    }
+                }
+                
+                Event::WindowEvent {
+                    event: WindowEvent::CursorMoved { position, .. },
+                    ..
+                } => {
+                    self.cursor_position = position;
+                    self.handle_mouse(MouseEventKind::Motion(None));
+                    if self.hover_link() {
+                        window.request_redraw();
+                    }
+                }
+                
+                Event::WindowEvent {
+                    event: WindowEvent::MouseInput { state, button, .. },
+                    ..
+                } => {
+                    let button = match button {
+                        winit::event::MouseButton::Left => MouseButton::Left,
+                        winit::event::MouseButton::Middle => MouseButton::Middle,
+                        winit::event::MouseButton::Right => MouseButton::Right,
+                        _ => return,
+                    };
+                    // Ctrl+click opens the link under the pointer instead of
+                    // reaching the program
+                    if state == ElementState::Pressed && button == MouseButton::Left && self.modifiers.control_key() {
+                        let hovered = tokio::task::block_in_place(|| self.terminal.blocking_lock()).hovered_link().is_some();
+                        if hovered {
+                            self.run_action(CommandAction::OpenLink);
+                            return;
+                        }
+                    }
+                    self.handle_mouse(match state {
+                        ElementState::Pressed => MouseEventKind::Press(button),
+                        ElementState::Released => MouseEventKind::Release(button),
+                    });
+                    window.request_redraw();
+                }
+                
+                Event::WindowEvent {
+                    event: WindowEvent::MouseWheel { delta, .. },
+                    ..
+                } => {
+                    let (x, y) = match delta {
+                        MouseScrollDelta::LineDelta(x, y) => (x as f64, y as f64),
+                        MouseScrollDelta::PixelDelta(position) => {
+                            let terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+                            let (cell_width, cell_height) = self.cell_size(&terminal);
+                            (position.x / cell_width, position.y / cell_height)
+                        }
+                    };
+                    let (horizontal, vertical) = (x.round() as i64, y.round() as i64);
+                    let buttons = [
+                        (vertical, MouseButton::WheelUp, MouseButton::WheelDown),
+                        (horizontal, MouseButton::WheelLeft, MouseButton::WheelRight),
+                    ];
+                    for (lines, positive, negative) in buttons {
+                        let button = if lines > 0 { positive } else { negative };
+                        for _ in 0..lines.unsigned_abs() {
+                            self.handle_mouse(MouseEventKind::Press(button));
+                        }
+                    }
+                    window.request_redraw();
                 }
                 
                 Event::RedrawRequested(_) => {
                     if let Some(renderer) = &mut self.gpu_renderer {
-                        let terminal = self.terminal.clone();
-                        tokio::spawn(async move {
-                            let term = terminal.lock().await;
-                            // renderer.render(&term);
-                        });
+                        let mut terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+                        if terminal.should_render(Instant::now()) {
+                            self.redraw_at = None;
+                            if let Err(e) = renderer.render(&terminal) {
+                                log::warn!("Failed to render: {:#}", e);
+                            }
+                        } else if let Some(deadline) = terminal.synchronized_update_deadline() {
+                            // Draw anyway once the update times out
+                            self.redraw_at = Some(deadline);
+                            *control_flow = ControlFlow::WaitUntil(deadline);
+                        }
                     }
                 }
                 
@@ -80,10 +220,242 @@ impl App {
         });
     }
     
-    fn handle_keyboard_input(&mut self, _input: winit::event::KeyboardInput) {
-        // Handle keyboard shortcuts like Ctrl+Shift+P for command palette
-        if self.config.features.command_palette {
-            // self.command_palette.toggle();
+    /// Sends keys to the focused pane, except for key bindings, which run
+    /// their action, and keys typed while the command palette is open.
+    fn handle_keyboard_input(&mut self, event: winit::event::KeyEvent) {
+        let Some(key) = keyboard::from_winit(&event, self.modifiers) else {
+            return;
+        };
+        if self.clipboard_prompt.is_some() {
+            self.answer_clipboard_prompt(&key);
+            return;
+        }
+        if self.command_palette.is_visible() {
+            self.handle_palette_key(&key);
+            return;
+        }
+        
+        // Releases of bound keys are swallowed along with the presses
+        let bound = self.config.keybindings.iter().find(|binding| binding.matches(&key));
+        if let Some(name) = bound.map(|binding| binding.action.clone()) {
+            if key.kind == KeyEventKind::Release {
+                return;
+            }
+            if name == "command_palette" {
+                if self.config.features.command_palette {
+                    self.command_palette.toggle();
+                }
+            } else {
+                match CommandAction::from_binding(&name) {
+                    Some(action) => self.run_action(action),
+                    None => log::warn!("Unknown action {:?} in key bindings", name),
+                }
+            }
+            return;
+        }
+        
+        let terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+        if let Err(e) = terminal.send_key(&key) {
+            log::warn!("Failed to send key: {:#}", e);
+        }
+    }
+    
+    /// Asks about the oldest clipboard request waiting under the `ask`
+    /// policy in the window title, or puts the title back once none are.
+    fn update_clipboard_prompt(&mut self, window: &Window) {
+        let prompt = tokio::task::block_in_place(|| self.terminal.blocking_lock())
+            .pending_clipboard_request()
+            .map(|(pane_id, request)| match request {
+                ClipboardRequest::Store(_, text) => {
+                    format!("Allow pane {} to copy {} bytes to the clipboard? (y/n)", pane_id, text.len())
+                }
+                ClipboardRequest::Load(_) => format!("Allow pane {} to read the clipboard? (y/n)", pane_id),
+            });
+        if prompt != self.clipboard_prompt {
+            window.set_title(prompt.as_deref().unwrap_or(WINDOW_TITLE));
+            self.clipboard_prompt = prompt;
+        }
+    }
+    
+    /// While a clipboard request waits, y allows it, n or escape denies it
+    /// and other keys are ignored.
+    fn answer_clipboard_prompt(&mut self, key: &KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let allow = match key.key {
+            Key::Char('y') => true,
+            Key::Char('n') | Key::Named(NamedKey::Escape) => false,
+            _ => return,
+        };
+        tokio::task::block_in_place(|| self.terminal.blocking_lock()).answer_clipboard_request(allow);
+    }
+    
+    /// Arrows move the palette's selection, enter runs the selected command,
+    /// escape closes the palette and anything else edits the query.
+    fn handle_palette_key(&mut self, key: &KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        match key.key {
+            Key::Named(NamedKey::Escape) => self.command_palette.toggle(),
+            Key::Named(NamedKey::Up) => self.command_palette.select_previous(),
+            Key::Named(NamedKey::Down) => self.command_palette.select_next(),
+            Key::Named(NamedKey::Enter) => {
+                if let Some(action) = self.command_palette.execute_selected() {
+                    self.run_action(action);
+                }
+            }
+            Key::Named(NamedKey::Backspace) => {
+                let mut query = self.command_palette.query().to_string();
+                query.pop();
+                self.command_palette.update_query(query);
+            }
+            _ => {
+                if let Some(text) = &key.text {
+                    let query = format!("{}{}", self.command_palette.query(), text);
+                    self.command_palette.update_query(query);
+                }
+            }
+        }
+    }
+    
+    /// Runs a command from a key binding or the command palette.
+    fn run_action(&mut self, action: CommandAction) {
+        // Starting the new pane's shell takes the lock itself
+        if let CommandAction::SplitPane(direction) = action {
+            let terminal = self.terminal.clone();
+            let result = tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(Terminal::open_split(terminal, direction))
+            });
+            if let Err(e) = result {
+                log::warn!("Failed to split pane: {:#}", e);
+            }
+            return;
+        }
+        if let CommandAction::NewTab = action {
+            let terminal = self.terminal.clone();
+            let result = tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(Terminal::open_tab(terminal, "Terminal".to_string()))
+            });
+            if let Err(e) = result {
+                log::warn!("Failed to open tab: {:#}", e);
+            }
+            return;
+        }
+        
+        let mut terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+        let result = match action {
+            CommandAction::CloseTab => {
+                let index = terminal.active_tab();
+                terminal.close_tab(index);
+                Ok(())
+            }
+            CommandAction::SwitchTab(index) => {
+                terminal.switch_tab(index);
+                Ok(())
+            }
+            CommandAction::ClosePane => {
+                terminal.close_pane();
+                Ok(())
+            }
+            CommandAction::FocusPane(direction) => {
+                terminal.focus_pane(direction);
+                Ok(())
+            }
+            CommandAction::ResizePane(direction, delta) => {
+                terminal.resize_pane(direction, delta);
+                Ok(())
+            }
+            CommandAction::TogglePaneZoom => {
+                terminal.toggle_zoom();
+                Ok(())
+            }
+            CommandAction::NextLayout => {
+                terminal.next_layout();
+                Ok(())
+            }
+            CommandAction::SetLayout(kind) => {
+                terminal.set_layout(kind);
+                Ok(())
+            }
+            CommandAction::PreviousPrompt | CommandAction::NextPrompt => {
+                terminal.scroll_to_prompt(matches!(action, CommandAction::NextPrompt));
+                Ok(())
+            }
+            CommandAction::SelectLastCommandOutput => {
+                terminal.select_last_command_output();
+                Ok(())
+            }
+            CommandAction::CopyLastCommandOutput => terminal.copy_last_command_output(),
+            CommandAction::OpenLink => terminal.open_hovered_link(),
+            CommandAction::Paste => terminal.paste_from_clipboard(),
+            action => {
+                log::debug!("{:?} is not available", action);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to run command: {:#}", e);
+        }
+    }
+    
+    /// Updates the link under the pointer. Returns whether it changed.
+    fn hover_link(&mut self) -> bool {
+        let mut terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+        let cell_size = self.cell_size(&terminal);
+        terminal.hover((self.cursor_position.x, self.cursor_position.y), cell_size)
+    }
+    
+    /// Sizes the terminal grid to the window. Without a renderer there is no
+    /// font to measure, so the cell size follows from the current grid.
+    fn fit_terminal(&mut self) {
+        let size = self.window_size;
+        let mut terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+        match &self.gpu_renderer {
+            Some(renderer) => {
+                let (cell_width, cell_height) = renderer.cell_size();
+                let rows = (size.height / cell_height).max(1) as usize;
+                let cols = (size.width / cell_width).max(1) as usize;
+                terminal.resize(rows, cols);
+                terminal.set_cell_size(cell_width, cell_height);
+            }
+            None => {
+                let (rows, cols) = terminal.size();
+                terminal.set_cell_size(size.width / cols.max(1) as u32, size.height / rows.max(1) as u32);
+            }
+        }
+    }
+    
+    /// Size of a cell in pixels: the font's when there is a renderer, or the
+    /// grid's share of the window otherwise.
+    fn cell_size(&self, terminal: &Terminal) -> (f64, f64) {
+        match &self.gpu_renderer {
+            Some(renderer) => {
+                let (width, height) = renderer.cell_size();
+                (width as f64, height as f64)
+            }
+            None => {
+                let (rows, cols) = terminal.size();
+                (
+                    self.window_size.width as f64 / cols.max(1) as f64,
+                    self.window_size.height as f64 / rows.max(1) as f64,
+                )
+            }
+        }
+    }
+    
+    /// Reports a mouse event to the pane under the pointer, or the nearest
+    /// one when the pointer is in the padding past the grid. Events the pane
+    /// doesn't take, including everything with shift held, stay with the
+    /// terminal for local selection.
+    fn handle_mouse(&mut self, kind: MouseEventKind) {
+        let mut terminal = tokio::task::block_in_place(|| self.terminal.blocking_lock());
+        let cell_size = self.cell_size(&terminal);
+        let position = (self.cursor_position.x, self.cursor_position.y);
+        let modifiers = keyboard::modifiers_from_winit(self.modifiers);
+        if let Err(e) = terminal.send_mouse(kind, modifiers, position, cell_size) {
+            log::warn!("Failed to send mouse event: {:#}", e);
         }
     }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use crate::config::Config;
+use crate::layout::{Direction, LayoutKind, SplitDirection};
 
 pub struct CommandPalette {
     visible: bool,
@@ -20,6 +21,20 @@ pub enum CommandAction {
     NewTab,
     CloseTab,
     SwitchTab(usize),
+    SplitPane(SplitDirection),
+    ClosePane,
+    FocusPane(Direction),
+    /// Grow the focused pane along a split direction; negative shrinks it.
+    ResizePane(SplitDirection, f32),
+    TogglePaneZoom,
+    NextLayout,
+    OpenLink,
+    PreviousPrompt,
+    NextPrompt,
+    SelectLastCommandOutput,
+    CopyLastCommandOutput,
+    Paste,
+    SetLayout(LayoutKind),
     ToggleFullscreen,
     IncreaseFont,
     DecreaseFont,
@@ -32,6 +47,40 @@ pub enum CommandAction {
     Custom(String),
 }
 
+impl CommandAction {
+    /// The action a key binding names in the config, such as `split_right`.
+    pub fn from_binding(name: &str) -> Option<Self> {
+        Some(match name {
+            "new_tab" => Self::NewTab,
+            "close_tab" => Self::CloseTab,
+            "split_right" => Self::SplitPane(SplitDirection::Horizontal),
+            "split_down" => Self::SplitPane(SplitDirection::Vertical),
+            "close_pane" => Self::ClosePane,
+            "focus_pane_left" => Self::FocusPane(Direction::Left),
+            "focus_pane_right" => Self::FocusPane(Direction::Right),
+            "focus_pane_up" => Self::FocusPane(Direction::Up),
+            "focus_pane_down" => Self::FocusPane(Direction::Down),
+            "pane_wider" => Self::ResizePane(SplitDirection::Horizontal, 0.05),
+            "pane_narrower" => Self::ResizePane(SplitDirection::Horizontal, -0.05),
+            "pane_taller" => Self::ResizePane(SplitDirection::Vertical, 0.05),
+            "pane_shorter" => Self::ResizePane(SplitDirection::Vertical, -0.05),
+            "toggle_pane_zoom" => Self::TogglePaneZoom,
+            "next_layout" => Self::NextLayout,
+            "open_link" => Self::OpenLink,
+            "previous_prompt" => Self::PreviousPrompt,
+            "next_prompt" => Self::NextPrompt,
+            "select_last_command_output" => Self::SelectLastCommandOutput,
+            "copy_last_command_output" => Self::CopyLastCommandOutput,
+            "paste" => Self::Paste,
+            "toggle_fullscreen" => Self::ToggleFullscreen,
+            "increase_font" => Self::IncreaseFont,
+            "decrease_font" => Self::DecreaseFont,
+            "reset_font" => Self::ResetFont,
+            _ => return None,
+        })
+    }
+}
+
 impl CommandPalette {
     pub fn new(config: Config) -> Self {
         let mut commands = vec![
@@ -47,6 +96,78 @@ impl CommandPalette {
                 keybinding: Some("Ctrl+Shift+W".to_string()),
                 action: CommandAction::CloseTab,
             },
+            Command {
+                name: "Split Right".to_string(),
+                description: "Split the current pane side by side".to_string(),
+                keybinding: Some("Ctrl+Shift+D".to_string()),
+                action: CommandAction::SplitPane(SplitDirection::Horizontal),
+            },
+            Command {
+                name: "Split Down".to_string(),
+                description: "Split the current pane top and bottom".to_string(),
+                keybinding: Some("Ctrl+Shift+E".to_string()),
+                action: CommandAction::SplitPane(SplitDirection::Vertical),
+            },
+            Command {
+                name: "Close Pane".to_string(),
+                description: "Close the current pane".to_string(),
+                keybinding: Some("Ctrl+Shift+X".to_string()),
+                action: CommandAction::ClosePane,
+            },
+            Command {
+                name: "Focus Pane Left".to_string(),
+                description: "Move focus to the pane on the left".to_string(),
+                keybinding: Some("Ctrl+Shift+Left".to_string()),
+                action: CommandAction::FocusPane(Direction::Left),
+            },
+            Command {
+                name: "Focus Pane Right".to_string(),
+                description: "Move focus to the pane on the right".to_string(),
+                keybinding: Some("Ctrl+Shift+Right".to_string()),
+                action: CommandAction::FocusPane(Direction::Right),
+            },
+            Command {
+                name: "Focus Pane Up".to_string(),
+                description: "Move focus to the pane above".to_string(),
+                keybinding: Some("Ctrl+Shift+Up".to_string()),
+                action: CommandAction::FocusPane(Direction::Up),
+            },
+            Command {
+                name: "Focus Pane Down".to_string(),
+                description: "Move focus to the pane below".to_string(),
+                keybinding: Some("Ctrl+Shift+Down".to_string()),
+                action: CommandAction::FocusPane(Direction::Down),
+            },
+            Command {
+                name: "Make Pane Wider".to_string(),
+                description: "Grow the current pane horizontally".to_string(),
+                keybinding: Some("Ctrl+Alt+Right".to_string()),
+                action: CommandAction::ResizePane(SplitDirection::Horizontal, 0.05),
+            },
+            Command {
+                name: "Make Pane Narrower".to_string(),
+                description: "Shrink the current pane horizontally".to_string(),
+                keybinding: Some("Ctrl+Alt+Left".to_string()),
+                action: CommandAction::ResizePane(SplitDirection::Horizontal, -0.05),
+            },
+            Command {
+                name: "Make Pane Taller".to_string(),
+                description: "Grow the current pane vertically".to_string(),
+                keybinding: Some("Ctrl+Alt+Down".to_string()),
+                action: CommandAction::ResizePane(SplitDirection::Vertical, 0.05),
+            },
+            Command {
+                name: "Make Pane Shorter".to_string(),
+                description: "Shrink the current pane vertically".to_string(),
+                keybinding: Some("Ctrl+Alt+Up".to_string()),
+                action: CommandAction::ResizePane(SplitDirection::Vertical, -0.05),
+            },
+            Command {
+                name: "Toggle Pane Zoom".to_string(),
+                description: "Show only the current pane, or restore the split".to_string(),
+                keybinding: Some("Ctrl+Shift+Z".to_string()),
+                action: CommandAction::TogglePaneZoom,
+            },
             Command {
                 name: "Toggle Fullscreen".to_string(),
                 description: "Toggle fullscreen mode".to_string(),
@@ -83,8 +204,57 @@ impl CommandPalette {
                 keybinding: Some("F1".to_string()),
                 action: CommandAction::ShowHelp,
             },
+            Command {
+                name: "Previous Prompt".to_string(),
+                description: "Scroll to the previous shell prompt".to_string(),
+                keybinding: Some("Ctrl+Shift+K".to_string()),
+                action: CommandAction::PreviousPrompt,
+            },
+            Command {
+                name: "Next Prompt".to_string(),
+                description: "Scroll to the next shell prompt".to_string(),
+                keybinding: Some("Ctrl+Shift+J".to_string()),
+                action: CommandAction::NextPrompt,
+            },
+            Command {
+                name: "Select Last Command Output".to_string(),
+                description: "Select the output of the last command".to_string(),
+                keybinding: None,
+                action: CommandAction::SelectLastCommandOutput,
+            },
+            Command {
+                name: "Copy Last Command Output".to_string(),
+                description: "Copy the output of the last command to the clipboard".to_string(),
+                keybinding: Some("Ctrl+Shift+G".to_string()),
+                action: CommandAction::CopyLastCommandOutput,
+            },
+            Command {
+                name: "Paste".to_string(),
+                description: "Paste text from the clipboard".to_string(),
+                keybinding: Some("Ctrl+Shift+V".to_string()),
+                action: CommandAction::Paste,
+            },
+            Command {
+                name: "Open Link".to_string(),
+                description: "Open the hyperlink under the mouse pointer".to_string(),
+                keybinding: None,
+                action: CommandAction::OpenLink,
+            },
+            Command {
+                name: "Next Layout".to_string(),
+                description: "Switch to the next enabled layout".to_string(),
+                keybinding: Some("Ctrl+Shift+L".to_string()),
+                action: CommandAction::NextLayout,
+            },
         ];
         
+        commands.extend(config.layouts.enabled.iter().map(|&kind| Command {
+            name: format!("Switch Layout: {}", kind.name()),
+            description: format!("Arrange the panes of this tab in the {} layout", kind.name().to_lowercase()),
+            keybinding: None,
+            action: CommandAction::SetLayout(kind),
+        }));
+        
         // Add AI features if enabled
         if config.features.ai_suggestions {
             commands.push(Command {
@@ -133,6 +303,10 @@ impl CommandPalette {
         self.visible
     }
     
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+    
     pub fn update_query(&mut self, query: String) {
         self.query = query;
         self.selected_index = 0;
@@ -176,11 +350,8 @@ impl CommandPalette {
     }
     
     pub fn execute_selected(&mut self) -> Option<CommandAction> {
-        if let Some(command) = self.get_selected_command() {
-            self.visible = false;
-            Some(command.action.clone())
-        } else {
-            None
-        }
+        let action = self.get_selected_command()?.action.clone();
+        self.visible = false;
+        Some(action)
     }
 }
\ No newline at end of file